@@ -0,0 +1,144 @@
+use crate::manifest::DatasetPart;
+use crate::{Image, Info, License, TestMetadata, TrainMetadata};
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::io;
+use std::path;
+use strum_macros::{Display, EnumString};
+
+// worked out from the license name & url, anything that isn't recognized is treated as Unknown and flagged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum LicenseTerms {
+    PublicDomain,
+    Attribution,
+    AttributionShareAlike,
+    NonCommercial,
+    NoDerivatives,
+    AllRightsReserved,
+    Unknown,
+}
+
+impl LicenseTerms {
+    pub fn classify(license: &License) -> LicenseTerms {
+        let text = format!("{} {}", license.name, license.url).to_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|e| text.contains(e));
+
+        if has(&["publicdomain", "public domain", "cc0", "/zero/", "no known copyright"]) {
+            LicenseTerms::PublicDomain
+        } else if has(&["all rights reserved", "©"]) {
+            LicenseTerms::AllRightsReserved
+        } else if has(&["noderiv", "no deriv", "-nd", "_nd", "/by-nc-nd", "/by-nd"]) {
+            LicenseTerms::NoDerivatives
+        } else if has(&["noncommercial", "non-commercial", "non commercial", "/by-nc"]) {
+            LicenseTerms::NonCommercial
+        } else if has(&["sharealike", "share alike", "/by-sa"]) {
+            LicenseTerms::AttributionShareAlike
+        } else if has(&["attribution", "/by/"]) {
+            LicenseTerms::Attribution
+        } else {
+            LicenseTerms::Unknown
+        }
+    }
+
+    // published figures crop & resize the sheets, which makes them derivatives
+    pub fn allows_redistribution(&self) -> bool {
+        match self {
+            LicenseTerms::PublicDomain | LicenseTerms::Attribution | LicenseTerms::AttributionShareAlike | LicenseTerms::NonCommercial => true,
+            LicenseTerms::NoDerivatives | LicenseTerms::AllRightsReserved | LicenseTerms::Unknown => false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attribution {
+    pub image_id: i32,
+    pub split: DatasetPart,
+    pub file_name: String,
+    pub license_id: i32,
+    pub license_name: String,
+    pub license_url: String,
+    pub terms: LicenseTerms,
+    pub contributor: String,
+    pub redistributable: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AttributionReport {
+    pub attributions: Vec<Attribution>,
+    pub missing_image_ids: Vec<(DatasetPart, i32)>,
+}
+
+struct Source<'a> {
+    images: collections::HashMap<i32, &'a Image>,
+    licenses: collections::HashMap<i32, &'a License>,
+    info: &'a Info,
+}
+
+impl<'a> Source<'a> {
+    fn new(images: &'a [Image], licenses: &'a [License], info: &'a Info) -> Source<'a> {
+        Source {
+            images: images.iter().map(|e| (e.id, e)).collect(),
+            licenses: licenses.iter().map(|e| (e.id, e)).collect(),
+            info,
+        }
+    }
+
+    fn attribution(&self, image_id: i32, split: DatasetPart) -> Option<Attribution> {
+        let image = self.images.get(&image_id)?;
+        let (license_name, license_url, terms) = match self.licenses.get(&image.license) {
+            Some(license) => (license.name.clone(), license.url.clone(), LicenseTerms::classify(license)),
+            None => (String::new(), String::new(), LicenseTerms::Unknown),
+        };
+        Some(Attribution {
+            image_id,
+            split,
+            file_name: image.file_name.clone(),
+            license_id: image.license,
+            license_name,
+            license_url,
+            terms,
+            contributor: self.info.contributor.clone(),
+            redistributable: terms.allows_redistribution(),
+        })
+    }
+}
+
+impl AttributionReport {
+    // the test images are looked up in the test metadata, every other part in the train metadata
+    pub fn new(image_ids: &[(DatasetPart, i32)], train_metadata: &TrainMetadata, test_metadata: Option<&TestMetadata>) -> AttributionReport {
+        let train_source = Source::new(&train_metadata.images, &train_metadata.licenses, &train_metadata.info);
+        let test_source = test_metadata.map(|e| Source::new(&e.images, &e.licenses, &e.info));
+
+        let mut report = AttributionReport::default();
+        let mut seen = collections::HashSet::new();
+        for (part, image_id) in image_ids.iter() {
+            if !seen.insert((*part, *image_id)) {
+                continue;
+            }
+            let attribution = match part {
+                DatasetPart::Testing => test_source.as_ref().and_then(|e| e.attribution(*image_id, *part)),
+                _ => train_source.attribution(*image_id, *part),
+            };
+            match attribution {
+                Some(attribution) => report.attributions.push(attribution),
+                None => report.missing_image_ids.push((*part, *image_id)),
+            }
+        }
+        report
+    }
+
+    pub fn flagged(&self) -> Vec<&Attribution> {
+        self.attributions.iter().filter(|e| !e.redistributable).collect()
+    }
+
+    pub fn write(&self, report_path: &path::Path) -> io::Result<()> {
+        let mut writer = csv::Writer::from_path(report_path)?;
+        for attribution in self.attributions.iter() {
+            writer.serialize(attribution)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::attribution;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "attribution_report", about = "license & attribution table for a set of images")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train & test", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "o", long = "output", long_help = "attribution csv output", required = true, parse(from_os_str))]
+    output: path::PathBuf,
+
+    #[structopt(short = "i", long = "image_ids", long_help = "comma separated image ids", use_delimiter = true, required_unless = "manifest")]
+    image_ids: Vec<i32>,
+
+    #[structopt(short = "t", long = "test", long_help = "the image ids are test image ids")]
+    test: bool,
+
+    #[structopt(
+        short = "f",
+        long = "manifest",
+        long_help = "take the images from a csv manifest",
+        conflicts_with = "image-ids",
+        parse(from_os_str)
+    )]
+    manifest: Option<path::PathBuf>,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let image_ids: Vec<(DatasetPart, i32)> = match options.manifest {
+        Some(ref manifest_path) => {
+            info!("reading: {}", manifest_path.to_string_lossy());
+            manifest::Manifest::read(manifest_path.as_path())?.entries.iter().map(|e| (e.split, e.image_id)).collect()
+        }
+        None => {
+            let part = if options.test { DatasetPart::Testing } else { DatasetPart::Training };
+            options.image_ids.iter().map(|e| (part, *e)).collect()
+        }
+    };
+
+    let mut train_metadata_path = options.base_dir.clone();
+    train_metadata_path.push("train");
+    train_metadata_path.push("metadata.json");
+
+    info!("reading: {}", train_metadata_path.to_string_lossy());
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+
+    let test_metadata = if image_ids.iter().any(|e| e.0 == DatasetPart::Testing) {
+        let mut test_metadata_path = options.base_dir.clone();
+        test_metadata_path.push("test");
+        test_metadata_path.push("metadata.json");
+
+        info!("reading: {}", test_metadata_path.to_string_lossy());
+        Some(rusty_herbarium::metadata::read_test_metadata_cached(
+            test_metadata_path.as_path(),
+            options.metadata_version,
+        )?)
+    } else {
+        None
+    };
+
+    let report = attribution::AttributionReport::new(&image_ids, &train_metadata, test_metadata.as_ref());
+    for (part, image_id) in report.missing_image_ids.iter() {
+        warn!("{} image_id: {} is not in the metadata", part, image_id);
+    }
+    for attribution in report.flagged().iter() {
+        warn!(
+            "image_id: {} ({}) may not be redistributed, license: {} ({})",
+            attribution.image_id, attribution.file_name, attribution.license_name, attribution.terms
+        );
+    }
+    info!(
+        "images: {}, flagged: {}, missing: {}",
+        report.attributions.len(),
+        report.flagged().len(),
+        report.missing_image_ids.len()
+    );
+
+    info!("writing: {}", options.output.to_string_lossy());
+    report.write(options.output.as_path())?;
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
@@ -0,0 +1,155 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate image;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::pipeline::RgbaImage;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "benchmark_preprocessing",
+    about = "times preprocessing_step_3 & preprocessing_step_4 against the original implementations & checks the output is identical"
+)]
+struct Options {
+    #[structopt(short = "i", long = "input", long_help = "input images", required = true, parse(from_os_str))]
+    inputs: Vec<path::PathBuf>,
+
+    #[structopt(short = "r", long = "repeat", long_help = "how many times each step is run on each image", default_value = "1")]
+    repeat: u32,
+
+    #[structopt(short = "s", long = "skip_original", long_help = "only time the current implementations")]
+    skip_original: bool,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+// preprocessing_step_4 before border::blank_gray_borders, one border at a time with a Vec per pixel for the min & max
+fn preprocessing_step_4_orig(mut img: RgbaImage) -> RgbaImage {
+    let replacement_pixel = *img.get_pixel_mut(4, 40);
+    let (width, height) = img.dimensions();
+    let borders = [(0, 55, 0, height), (width - 55, width, 0, height), (0, width, height - 40, height), (0, width, 0, 40)];
+
+    for (x_start, x_end, y_start, y_end) in borders.iter() {
+        for y in *y_start..*y_end {
+            for x in *x_start..*x_end {
+                let pixel = img.get_pixel_mut(x, y);
+                let tmp = vec![pixel[0], pixel[1], pixel[2]];
+                let min = itertools::min(tmp.clone()).unwrap();
+                let max = itertools::max(tmp.clone()).unwrap();
+
+                if (max - min) <= 10 {
+                    pixel[0] = replacement_pixel[0];
+                    pixel[1] = replacement_pixel[1];
+                    pixel[2] = replacement_pixel[2];
+                }
+            }
+        }
+    }
+    img
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    original: Duration,
+    current: Duration,
+    mismatches: usize,
+}
+
+impl Timing {
+    fn report(&self, step: &str, skip_original: bool) {
+        if skip_original {
+            info!("{}: {}", step, format_duration(self.current));
+            return;
+        }
+        let speedup = self.original.as_secs_f64() / self.current.as_secs_f64().max(1e-9);
+        info!(
+            "{}: original: {}, current: {}, speedup: {:.1}x, images with different output: {}",
+            step,
+            format_duration(self.original),
+            format_duration(self.current),
+            speedup,
+            self.mismatches
+        );
+    }
+}
+
+fn time<F: Fn() -> RgbaImage>(repeat: u32, f: F) -> (Duration, RgbaImage) {
+    let start = Instant::now();
+    let mut img = f();
+    for _ in 1..repeat {
+        img = f();
+    }
+    (start.elapsed(), img)
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let repeat = options.repeat.max(1);
+    let (mut step_3, mut step_4) = (Timing::default(), Timing::default());
+    let mut count = 0;
+    for input in options.inputs.iter() {
+        // the steps run on the 600x800 preprocessing_step_1 output, the size step_4's margins are relative to
+        let img = match image::open(input.as_path()) {
+            Ok(img) => img,
+            Err(e) => {
+                warn!("skipping: {}, {}", input.to_string_lossy(), e);
+                continue;
+            }
+        };
+        let img = match rusty_herbarium::preprocessing_step_1(img) {
+            Ok(img) => img,
+            Err(e) => {
+                warn!("skipping: {}, {}", input.to_string_lossy(), e);
+                continue;
+            }
+        };
+        count += 1;
+
+        let (elapsed, current) = time(repeat, || rusty_herbarium::preprocessing_step_3(img.clone()).unwrap());
+        step_3.current += elapsed;
+        if !options.skip_original {
+            let (elapsed, original) = time(repeat, || rusty_herbarium::preprocessing_step_3_reference(img.clone()));
+            step_3.original += elapsed;
+            if original != current {
+                warn!("preprocessing_step_3 output differs: {}", input.to_string_lossy());
+                step_3.mismatches += 1;
+            }
+        }
+
+        let (elapsed, current) = time(repeat, || rusty_herbarium::preprocessing_step_4(img.clone()).unwrap());
+        step_4.current += elapsed;
+        if !options.skip_original {
+            let (elapsed, original) = time(repeat, || preprocessing_step_4_orig(img.clone()));
+            step_4.original += elapsed;
+            if original != current {
+                warn!("preprocessing_step_4 output differs: {}", input.to_string_lossy());
+                step_4.mismatches += 1;
+            }
+        }
+        debug!("done: {}", input.to_string_lossy());
+    }
+
+    info!("images: {}, runs per image: {}", count, repeat);
+    step_3.report("preprocessing_step_3", options.skip_original);
+    step_4.report("preprocessing_step_4", options.skip_original);
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    if step_3.mismatches + step_4.mismatches > 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the current implementations don't match the originals"));
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::cross_validation;
+use rusty_herbarium::split;
+use std::env;
+use std::fs;
+use std::io;
+use std::path;
+use std::process;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "cross_validate", about = "stratified k-fold cross validation of one of the model binaries")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(
+        short = "o",
+        long = "output_dir",
+        long_help = "output directory, every fold gets its own sub directory",
+        required = true,
+        parse(from_os_str)
+    )]
+    output_dir: path::PathBuf,
+
+    #[structopt(
+        short = "n",
+        long = "model",
+        long_help = "model binary that accepts --metrics_output",
+        default_value = "rustlearn_random_forest"
+    )]
+    model: String,
+
+    #[structopt(short = "k", long = "folds", long_help = "number of folds", default_value = "5")]
+    folds: usize,
+
+    #[structopt(short = "e", long = "seed", long_help = "seed", default_value = "0")]
+    seed: u64,
+
+    #[structopt(short = "c", long = "category_limit", long_help = "category limit", default_value = "0")]
+    category_limit: usize,
+
+    #[structopt(short = "w", long = "width", long_help = "width", default_value = "315")]
+    width: u32,
+
+    #[structopt(short = "h", long = "height", long_help = "height", default_value = "390")]
+    height: u32,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+
+    #[structopt(last = true, long_help = "extra arguments passed through to the model binary")]
+    model_args: Vec<String>,
+}
+
+// the serializer and the models are expected to sit next to this binary, as they do in target/{debug,release}
+fn sibling_command(name: &str) -> io::Result<process::Command> {
+    let binary = env::current_exe()?.with_file_name(name);
+    if !binary.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("binary not found: {}", binary.to_string_lossy())));
+    }
+    Ok(process::Command::new(binary))
+}
+
+fn run(mut command: process::Command) -> io::Result<()> {
+    debug!("{:?}", command);
+    let status = command.status()?;
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{:?} failed: {}", command, status)));
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    if options.folds < 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "need at least 2 folds"));
+    }
+
+    let mut train_metadata_path = options.base_dir.clone();
+    train_metadata_path.push("train");
+    train_metadata_path.push("metadata.json");
+
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+    let catalog = rusty_herbarium::HerbariumCatalog::new(train_metadata);
+
+    let folds = split::stratified_k_fold(&catalog, options.folds, options.seed, options.category_limit);
+
+    let mut fold_metrics = Vec::new();
+    for fold in folds.iter() {
+        let fold_index = fold.fold.map(|e| e.index).unwrap_or(0);
+        info!(
+            "fold: {}/{}, train: {}, validation: {}",
+            fold_index + 1,
+            options.folds,
+            fold.train.len(),
+            fold.validation.len()
+        );
+
+        let mut fold_dir = options.output_dir.clone();
+        fold_dir.push(format!("fold-{}", fold_index));
+        fs::create_dir_all(&fold_dir)?;
+
+        let mut fold_split_path = fold_dir.clone();
+        fold_split_path.push(format!("herbarium-fold-{}-of-{}.json", fold_index, options.folds));
+        fold.write(fold_split_path.as_path())?;
+
+        let mut serialize = sibling_command("serialize_train_and_label_data")?;
+        serialize
+            .arg("--base_dir")
+            .arg(&options.base_dir)
+            .arg("--output_dir")
+            .arg(&fold_dir)
+            .arg("--split")
+            .arg(&fold_split_path)
+            .arg("--width")
+            .arg(options.width.to_string())
+            .arg("--height")
+            .arg(options.height.to_string())
+            .arg("--log_level")
+            .arg(&options.log_level);
+        if let Some(metadata_version) = options.metadata_version {
+            serialize.arg("--metadata_version").arg(metadata_version.to_string());
+        }
+        run(serialize)?;
+
+        let mut metrics_path = fold_dir.clone();
+        metrics_path.push(format!("{}-metrics.json", options.model));
+
+        let mut model = sibling_command(options.model.as_str())?;
+        model
+            .arg("--serialization_dir")
+            .arg(&fold_dir)
+            .arg("--width")
+            .arg(options.width.to_string())
+            .arg("--height")
+            .arg(options.height.to_string())
+            .arg("--metrics_output")
+            .arg(&metrics_path)
+            .arg("--log_level")
+            .arg(&options.log_level)
+            .args(&options.model_args);
+        run(model)?;
+
+        let metrics = cross_validation::ModelMetrics::read(metrics_path.as_path())?;
+        info!("fold: {}/{}, metrics: {:?}", fold_index + 1, options.folds, metrics.metrics);
+        fold_metrics.push(metrics);
+    }
+
+    let report = cross_validation::CrossValidationReport::new(options.model.as_str(), fold_metrics);
+    for (name, summary) in report.summary.iter() {
+        info!("{}: mean: {:.4}, standard deviation: {:.4}", name, summary.mean, summary.standard_deviation);
+    }
+
+    let mut report_path = options.output_dir.clone();
+    report_path.push(format!("herbarium-cross-validation-{}-{}x{}.json", options.model, options.width, options.height));
+    info!("writing: {}", report_path.to_string_lossy());
+    report.write(report_path.as_path())?;
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
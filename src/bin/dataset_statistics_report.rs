@@ -0,0 +1,93 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "dataset_statistics_report", about = "write dataset statistics as json, markdown & html")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train & test", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "o", long = "output_dir", long_help = "output directory", required = true, parse(from_os_str))]
+    output_dir: path::PathBuf,
+
+    #[structopt(short = "s", long = "skip_test", long_help = "only report on the train metadata")]
+    skip_test: bool,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let mut train_metadata_path = options.base_dir.clone();
+    train_metadata_path.push("train");
+    train_metadata_path.push("metadata.json");
+
+    info!("reading: {}", train_metadata_path.to_string_lossy());
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+    let mut catalog = rusty_herbarium::HerbariumCatalog::new(train_metadata);
+
+    if !options.skip_test {
+        let mut test_metadata_path = options.base_dir.clone();
+        test_metadata_path.push("test");
+        test_metadata_path.push("metadata.json");
+
+        info!("reading: {}", test_metadata_path.to_string_lossy());
+        catalog = catalog.with_test_metadata(rusty_herbarium::metadata::read_test_metadata_cached(
+            test_metadata_path.as_path(),
+            options.metadata_version,
+        )?);
+    }
+
+    let statistics = rusty_herbarium::statistics::DatasetStatistics::new(&catalog);
+    info!(
+        "classes: {}, images per class: {} - {}, gini: {:.4}",
+        statistics.class_distribution.class_count,
+        statistics.class_distribution.images_per_class.min,
+        statistics.class_distribution.images_per_class.max,
+        statistics.imbalance.gini
+    );
+
+    fs::create_dir_all(options.output_dir.as_path())?;
+
+    let mut json_path = options.output_dir.clone();
+    json_path.push("herbarium-statistics.json");
+    info!("writing: {}", json_path.to_string_lossy());
+    serde_json::to_writer_pretty(io::BufWriter::new(fs::File::create(json_path.as_path())?), &statistics)?;
+
+    let mut markdown_path = options.output_dir.clone();
+    markdown_path.push("herbarium-statistics.md");
+    info!("writing: {}", markdown_path.to_string_lossy());
+    let mut markdown_file = io::BufWriter::new(fs::File::create(markdown_path.as_path())?);
+    markdown_file.write_all(statistics.to_markdown().as_bytes())?;
+
+    let mut html_path = options.output_dir.clone();
+    html_path.push("herbarium-statistics.html");
+    info!("writing: {}", html_path.to_string_lossy());
+    let mut html_file = io::BufWriter::new(fs::File::create(html_path.as_path())?);
+    html_file.write_all(statistics.to_html().as_bytes())?;
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
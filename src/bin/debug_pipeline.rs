@@ -0,0 +1,134 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate image;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::pipeline::{Pipeline, PipelineSpec, RgbaImage};
+use rusty_herbarium::visualize;
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "debug_pipeline",
+    about = "writes every intermediate image of the preprocessing pipeline with overlays of what each step detected"
+)]
+struct Options {
+    #[structopt(short = "i", long = "input", long_help = "input images", required = true, parse(from_os_str))]
+    inputs: Vec<path::PathBuf>,
+
+    #[structopt(
+        short = "o",
+        long = "output_dir",
+        long_help = "output directory, one directory per image plus contact-sheet.png",
+        required = true,
+        parse(from_os_str)
+    )]
+    output_dir: path::PathBuf,
+
+    #[structopt(short = "p", long = "pipeline", long_help = "preprocessing pipeline spec (toml or json)", parse(from_os_str))]
+    pipeline: Option<path::PathBuf>,
+
+    #[structopt(short = "w", long = "width", long_help = "output width", default_value = "315")]
+    width: u32,
+
+    #[structopt(short = "h", long = "height", long_help = "output height", default_value = "390")]
+    height: u32,
+
+    #[structopt(
+        short = "n",
+        long = "contact_sheet_images",
+        long_help = "how many images go on the contact sheet, 0 for no contact sheet",
+        default_value = "16"
+    )]
+    contact_sheet_images: usize,
+
+    #[structopt(short = "s", long = "cell_size", long_help = "contact sheet cell width & height", default_value = "200")]
+    cell_size: u32,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let pipeline_spec = pipeline::load_spec(options.pipeline.as_ref().map(|e| e.as_path()), PipelineSpec::default())?;
+    let pipeline = Pipeline::new(pipeline_spec, options.width, options.height);
+    fs::create_dir_all(options.output_dir.as_path())?;
+
+    let mut contact_sheet_rows: Vec<Vec<RgbaImage>> = Vec::new();
+    let mut names: collections::HashSet<String> = collections::HashSet::new();
+    for input in options.inputs.iter() {
+        let img = match image::open(input.as_path()) {
+            Ok(img) => img,
+            Err(e) => {
+                warn!("skipping: {}, {}", input.to_string_lossy(), e);
+                continue;
+            }
+        };
+
+        let stem = input.file_stem().unwrap().to_string_lossy().to_string();
+        let mut name = match input.parent().and_then(|e| e.file_name()) {
+            Some(parent) => format!("{}-{}", parent.to_string_lossy(), stem),
+            None => stem,
+        };
+        if !names.insert(name.clone()) {
+            name = format!("{}-{}", name, names.len());
+            names.insert(name.clone());
+        }
+        let mut image_dir = options.output_dir.clone();
+        image_dir.push(name);
+
+        let (stages, result) = pipeline.apply_debug(img);
+        pipeline::write_debug_stages(image_dir.as_path(), &stages)?;
+        let mut row: Vec<RgbaImage> = stages.iter().map(|e| e.overlay.clone().unwrap_or_else(|| e.image.clone())).collect();
+        match result {
+            Ok(img) => {
+                let mut output_path = image_dir.clone();
+                output_path.push("output.png");
+                pipeline
+                    .render(&pipeline.resize(&img))
+                    .save(output_path.as_path())
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+                info!("{}: {} stages, written to {}", input.to_string_lossy(), stages.len(), image_dir.to_string_lossy());
+            }
+            Err(e) => {
+                warn!("{}: {}", input.to_string_lossy(), e);
+                let mut error_path = image_dir.clone();
+                error_path.push("error.txt");
+                fs::write(error_path.as_path(), format!("{}\n", e))?;
+                // a red cell marks where the pipeline stopped
+                row.push(RgbaImage::from_pixel(options.cell_size, options.cell_size, visualize::BORDER_COLOR));
+            }
+        }
+        if contact_sheet_rows.len() < options.contact_sheet_images {
+            contact_sheet_rows.push(row);
+        }
+    }
+
+    if !contact_sheet_rows.is_empty() {
+        let mut contact_sheet_path = options.output_dir.clone();
+        contact_sheet_path.push("contact-sheet.png");
+        info!("writing: {}", contact_sheet_path.to_string_lossy());
+        visualize::contact_sheet(&contact_sheet_rows, options.cell_size, options.cell_size)
+            .save(contact_sheet_path.as_path())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    }
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
@@ -0,0 +1,86 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate image;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::calibration::CalibrationParams;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::specimen_label;
+use rusty_herbarium::specimen_label::{LabelParams, SheetRegions};
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "detect_sheet_regions",
+    about = "bounding boxes of the color bars, rulers, labels, stamps & barcodes on herbarium sheets"
+)]
+struct Options {
+    #[structopt(short = "i", long = "input", long_help = "input images", required = true, parse(from_os_str))]
+    inputs: Vec<path::PathBuf>,
+
+    #[structopt(short = "o", long = "output", long_help = "json output, keyed by input path", parse(from_os_str))]
+    output: Option<path::PathBuf>,
+
+    #[structopt(short = "c", long = "calibration_params", long_help = "color bar & ruler detection params (toml or json)", parse(from_os_str))]
+    calibration_params: Option<path::PathBuf>,
+
+    #[structopt(short = "a", long = "label_params", long_help = "label, stamp & barcode detection params (toml or json)", parse(from_os_str))]
+    label_params: Option<path::PathBuf>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let calibration_params: CalibrationParams = match options.calibration_params {
+        Some(ref params_path) => pipeline::read_config(params_path.as_path())?,
+        None => CalibrationParams::default(),
+    };
+    let label_params: LabelParams = match options.label_params {
+        Some(ref params_path) => pipeline::read_config(params_path.as_path())?,
+        None => LabelParams::default(),
+    };
+
+    let mut regions: collections::BTreeMap<String, SheetRegions> = collections::BTreeMap::new();
+    for input in options.inputs.iter() {
+        let img = match image::open(input.as_path()) {
+            Ok(img) => img.to_rgba8(),
+            Err(e) => {
+                warn!("skipping: {}, {}", input.to_string_lossy(), e);
+                continue;
+            }
+        };
+        let sheet_regions = specimen_label::detect_sheet_regions(&img, &calibration_params, &label_params);
+        info!(
+            "{}: color bars: {:?}, rulers: {:?}, labels: {:?}",
+            input.to_string_lossy(),
+            sheet_regions.color_bars,
+            sheet_regions.rulers,
+            sheet_regions.labels
+        );
+        regions.insert(input.to_string_lossy().to_string(), sheet_regions);
+    }
+
+    if let Some(ref output) = options.output {
+        info!("writing: {}", output.to_string_lossy());
+        serde_json::to_writer_pretty(io::BufWriter::new(fs::File::create(output.as_path())?), &regions)?;
+    }
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
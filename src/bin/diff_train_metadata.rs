@@ -0,0 +1,60 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "diff_train_metadata", about = "compare two train metadata files")]
+struct Options {
+    #[structopt(short = "p", long = "previous", long_help = "previous train metadata.json", required = true, parse(from_os_str))]
+    previous: path::PathBuf,
+
+    #[structopt(short = "c", long = "current", long_help = "current train metadata.json", required = true, parse(from_os_str))]
+    current: path::PathBuf,
+
+    #[structopt(short = "o", long = "output", long_help = "json diff output", parse(from_os_str))]
+    output: Option<path::PathBuf>,
+
+    #[structopt(short = "d", long = "max_details", long_help = "ids & changes listed per section", default_value = "20")]
+    max_details: usize,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    // the releases may use different metadata layouts, so each one is detected separately
+    info!("reading: {}", options.previous.to_string_lossy());
+    let previous = rusty_herbarium::metadata::read_train_metadata_cached(options.previous.as_path(), None)?;
+
+    info!("reading: {}", options.current.to_string_lossy());
+    let current = rusty_herbarium::metadata::read_train_metadata_cached(options.current.as_path(), None)?;
+
+    let diff = rusty_herbarium::metadata_diff::MetadataDiff::new(&previous, &current);
+    for line in diff.to_text(options.max_details).lines() {
+        info!("{}", line);
+    }
+
+    if let Some(output) = options.output.as_ref() {
+        info!("writing: {}", output.to_string_lossy());
+        diff.write(output.as_path())?;
+    }
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "export_metadata_sqlite", about = "export the train & test metadata into a normalized sqlite database")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train & test", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "o", long = "output", long_help = "sqlite database file, replaced if it exists", required = true, parse(from_os_str))]
+    output: path::PathBuf,
+
+    #[structopt(short = "s", long = "skip_test", long_help = "only export the train metadata")]
+    skip_test: bool,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let mut train_metadata_path = options.base_dir.clone();
+    train_metadata_path.push("train");
+    train_metadata_path.push("metadata.json");
+
+    info!("reading: {}", train_metadata_path.to_string_lossy());
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+
+    let test_metadata = if options.skip_test {
+        None
+    } else {
+        let mut test_metadata_path = options.base_dir.clone();
+        test_metadata_path.push("test");
+        test_metadata_path.push("metadata.json");
+
+        info!("reading: {}", test_metadata_path.to_string_lossy());
+        Some(rusty_herbarium::metadata::read_test_metadata_cached(
+            test_metadata_path.as_path(),
+            options.metadata_version,
+        )?)
+    };
+
+    info!("writing: {}", options.output.to_string_lossy());
+    let counts = rusty_herbarium::sqlite::export_metadata(options.output.as_path(), &train_metadata, test_metadata.as_ref())?;
+    info!("{:?}", counts);
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
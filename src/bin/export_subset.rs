@@ -0,0 +1,132 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::subset;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "export_subset", about = "carve a smaller dataset with the same train/test layout out of the full one")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train & test", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "o", long = "output_dir", long_help = "base directory of the subset", required = true, parse(from_os_str))]
+    output_dir: path::PathBuf,
+
+    #[structopt(short = "e", long = "seed", long_help = "seed", default_value = "0")]
+    seed: u64,
+
+    #[structopt(short = "r", long = "region_id", long_help = "only take categories & images from this region")]
+    region_id: Option<i32>,
+
+    #[structopt(short = "c", long = "category_count", long_help = "categories to take, 0 takes all", default_value = "10")]
+    category_count: usize,
+
+    #[structopt(short = "n", long = "images_per_category", long_help = "images to take per category, 0 takes all", default_value = "20")]
+    images_per_category: usize,
+
+    #[structopt(short = "m", long = "min_images_per_category", long_help = "skip categories with fewer images", default_value = "1")]
+    min_images_per_category: usize,
+
+    #[structopt(short = "t", long = "test_image_count", long_help = "test images to take, 0 skips test", default_value = "100")]
+    test_image_count: usize,
+
+    #[structopt(short = "k", long = "link_mode", long_help = "copy, hard_link or symlink", default_value = "hard_link")]
+    link_mode: subset::LinkMode,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let mut train_dir = options.base_dir.clone();
+    train_dir.push("train");
+
+    let mut train_metadata_path = train_dir.clone();
+    train_metadata_path.push("metadata.json");
+
+    info!("reading: {}", train_metadata_path.to_string_lossy());
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+    let mut catalog = rusty_herbarium::HerbariumCatalog::new(train_metadata);
+
+    let mut test_dir = options.base_dir.clone();
+    test_dir.push("test");
+
+    if options.test_image_count > 0 {
+        let mut test_metadata_path = test_dir.clone();
+        test_metadata_path.push("metadata.json");
+
+        info!("reading: {}", test_metadata_path.to_string_lossy());
+        catalog = catalog.with_test_metadata(rusty_herbarium::metadata::read_test_metadata_cached(
+            test_metadata_path.as_path(),
+            options.metadata_version,
+        )?);
+    }
+
+    let config = subset::SubsetConfig {
+        seed: options.seed,
+        region_id: options.region_id,
+        category_count: options.category_count,
+        images_per_category: options.images_per_category,
+        min_images_per_category: options.min_images_per_category,
+        test_image_count: options.test_image_count,
+    };
+    let subset = subset::select_subset(&catalog, &config);
+    info!(
+        "categories: {}, images: {}, annotations: {}, test images: {}",
+        subset.train_metadata.categories.len(),
+        subset.train_metadata.images.len(),
+        subset.train_metadata.annotations.len(),
+        subset.test_metadata.as_ref().map(|e| e.images.len()).unwrap_or(0)
+    );
+
+    let mut output_train_dir = options.output_dir.clone();
+    output_train_dir.push("train");
+
+    let mut output_train_metadata_path = output_train_dir.clone();
+    output_train_metadata_path.push("metadata.json");
+    info!("writing: {}", output_train_metadata_path.to_string_lossy());
+    subset::write_metadata(output_train_metadata_path.as_path(), &subset.train_metadata)?;
+
+    let linked = subset::link_images(train_dir.as_path(), output_train_dir.as_path(), &subset.train_metadata.images, options.link_mode)?;
+    info!("train images ({}): {}", options.link_mode, linked);
+
+    if let Some(test_metadata) = subset.test_metadata.as_ref() {
+        let mut output_test_dir = options.output_dir.clone();
+        output_test_dir.push("test");
+
+        let mut output_test_metadata_path = output_test_dir.clone();
+        output_test_metadata_path.push("metadata.json");
+        info!("writing: {}", output_test_metadata_path.to_string_lossy());
+        subset::write_metadata(output_test_metadata_path.as_path(), test_metadata)?;
+
+        let linked = subset::link_images(test_dir.as_path(), output_test_dir.as_path(), &test_metadata.images, options.link_mode)?;
+        info!("test images ({}): {}", options.link_mode, linked);
+    }
+
+    let mut report_path = options.output_dir.clone();
+    report_path.push("herbarium-subset.json");
+    info!("writing: {}", report_path.to_string_lossy());
+    subset.report.write(report_path.as_path())?;
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
@@ -0,0 +1,83 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::synthetic;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "generate_synthetic_dataset", about = "generate a small fake dataset with drawn herbarium sheets for offline runs")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory to write train & test into", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "e", long = "seed", long_help = "seed", default_value = "0")]
+    seed: u64,
+
+    #[structopt(short = "f", long = "family_count", long_help = "families", default_value = "2")]
+    family_count: usize,
+
+    #[structopt(short = "g", long = "genera_per_family", long_help = "genera per family", default_value = "2")]
+    genera_per_family: usize,
+
+    #[structopt(short = "s", long = "species_per_genus", long_help = "species (categories) per genus", default_value = "2")]
+    species_per_genus: usize,
+
+    #[structopt(short = "r", long = "region_count", long_help = "regions", default_value = "2")]
+    region_count: usize,
+
+    #[structopt(short = "n", long = "images_per_category", long_help = "train images per category", default_value = "5")]
+    images_per_category: usize,
+
+    #[structopt(short = "t", long = "test_images_per_category", long_help = "test images per category", default_value = "2")]
+    test_images_per_category: usize,
+
+    #[structopt(short = "w", long = "width", long_help = "sheet width", default_value = "680")]
+    width: u32,
+
+    #[structopt(short = "h", long = "height", long_help = "sheet height", default_value = "1000")]
+    height: u32,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let config = synthetic::SyntheticConfig {
+        seed: options.seed,
+        family_count: options.family_count,
+        genera_per_family: options.genera_per_family,
+        species_per_genus: options.species_per_genus,
+        region_count: options.region_count,
+        images_per_category: options.images_per_category,
+        test_images_per_category: options.test_images_per_category,
+        width: options.width,
+        height: options.height,
+    };
+
+    info!("writing: {}", options.base_dir.to_string_lossy());
+    let dataset = synthetic::generate(options.base_dir.as_path(), &config)?;
+    info!(
+        "categories: {}, train images: {}, test images: {}",
+        dataset.train_metadata.categories.len(),
+        dataset.train_metadata.images.len(),
+        dataset.test_metadata.images.len()
+    );
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
@@ -18,6 +18,14 @@ use juice::layers;
 use juice::solver;
 use juice::util;
 use log::Level;
+use rand::distributions;
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rusty_herbarium::label_encoder;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
+use rusty_herbarium::pipeline;
 use std::fs;
 use std::io;
 use std::path;
@@ -48,6 +56,16 @@ struct Options {
     #[structopt(short = "o", long = "momentum", long_help = "momentum", default_value = "0")]
     momentum: f32,
 
+    #[structopt(
+        short = "g",
+        long = "weighted_sampling",
+        long_help = "draw the training rows according to the sample weights written by the serializer"
+    )]
+    weighted_sampling: bool,
+
+    #[structopt(short = "e", long = "seed", long_help = "weighted sampling seed", default_value = "0")]
+    seed: u64,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "debug")]
     log_level: String,
 }
@@ -77,14 +95,47 @@ fn main() -> io::Result<()> {
     let training_labels: Vec<f32> = bincode::deserialize_from(&mut training_labels_decoder).unwrap();
     debug!("training_labels.len(): {}", training_labels.len());
 
+    let training_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Training, options.width as u32, options.height as u32)?;
+    training_manifest.check_rows(DatasetPart::Training, training_labels.len())?;
+    info!(
+        "training rows: {}, images: {}, categories: {}",
+        training_manifest.entries.len(),
+        training_manifest.image_count(),
+        training_manifest.category_count()
+    );
+
+    let label_encoder = label_encoder::read_label_encoder(options.serialization_dir.as_path(), options.width as u32, options.height as u32)?;
+    info!("classes: {}", label_encoder.class_count());
+
     let mut associated_data = Vec::new();
     for (i, row_data) in training_data.iter().enumerate() {
         associated_data.push((*training_labels.get(i).unwrap(), row_data));
     }
-    let mut tmp_labels = training_labels.clone();
-    tmp_labels.sort_by(|a, b| a.partial_cmp(&b).unwrap());
-    tmp_labels.dedup();
-    let unique_labels_count = tmp_labels.len();
+
+    if options.weighted_sampling {
+        let mut training_weights_path = options.serialization_dir.clone();
+        training_weights_path.push(DatasetPart::Training.weights_file_name(options.width as u32, options.height as u32));
+        debug!("reading: {}", training_weights_path.to_string_lossy());
+
+        let training_weights_reader = io::BufReader::new(fs::File::open(training_weights_path).unwrap());
+        let mut training_weights_decoder = GzDecoder::new(training_weights_reader);
+        let training_weights: Vec<f32> = bincode::deserialize_from(&mut training_weights_decoder).unwrap();
+        training_manifest.check_rows(DatasetPart::Training, training_weights.len())?;
+
+        // same number of rows per epoch, but drawn with replacement so the rare classes show up as often as their weight says
+        let weighted_index = distributions::WeightedIndex::new(&training_weights).unwrap();
+        let mut rng = StdRng::seed_from_u64(options.seed);
+        associated_data = (0..associated_data.len()).map(|_| associated_data[weighted_index.sample(&mut rng)]).collect();
+    }
+
+    // the labels are encoded class indices, so the encoder (not the labels that happen to be in this split) decides the class count
+    let unique_labels_count = label_encoder.class_count();
+    if let Some(label) = training_labels.iter().find(|e| **e < 0.0 || **e >= unique_labels_count as f32) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("label {} is outside of 0..{}", label, unique_labels_count),
+        ));
+    }
 
     let features_count = training_data.first().unwrap().len();
     debug!("features_count: {}, unique_label_count: {}", features_count, unique_labels_count);
@@ -92,8 +143,23 @@ fn main() -> io::Result<()> {
     let all_same_size = itertools::all(&training_data, |e| e.len() == features_count);
     debug!("all_same_size: {}", all_same_size);
 
+    // the input is sized from the rows, an rgb pipeline writes 3 features per pixel
+    if let Some(record) = pipeline::read_pipeline_record(options.serialization_dir.as_path(), options.width as u32, options.height as u32)? {
+        if record.feature_count() != features_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "pipeline {} writes {} features per row, the training data has {}",
+                    record.hash,
+                    record.feature_count(),
+                    features_count
+                ),
+            ));
+        }
+    }
+
     let mut net_cfg = layers::SequentialConfig::default();
-    net_cfg.add_input("data", &[options.batch_size, options.width, options.height]);
+    net_cfg.add_input("data", &[options.batch_size, features_count]);
     net_cfg.force_backward = true;
 
     let reshape_layer_type = layer::LayerType::Reshape(layers::ReshapeConfig::of_shape(&[options.batch_size, features_count]));
@@ -140,7 +206,7 @@ fn main() -> io::Result<()> {
 
     let mut solver = solver::Solver::from_config(backend.clone(), backend.clone(), &solver_cfg);
 
-    let inp = SharedTensor::<f32>::new(&[options.batch_size, options.width, options.height]);
+    let inp = SharedTensor::<f32>::new(&[options.batch_size, features_count]);
     let label = SharedTensor::<f32>::new(&[options.batch_size, 1]);
 
     let inp_lock = sync::Arc::new(sync::RwLock::new(inp));
@@ -8,9 +8,12 @@ extern crate walkdir;
 
 use humantime::format_duration;
 use log::Level;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::pipeline::{ColorSpace, Pipeline, PipelineSpec, ResizeFilter, TransformSpec};
 use std::io;
 use std::path;
 use std::str::FromStr;
+use std::sync;
 use std::time::Instant;
 use structopt::StructOpt;
 use walkdir::WalkDir;
@@ -21,6 +24,15 @@ struct Options {
     #[structopt(short = "i", long = "input", long_help = "input dir", required = true, parse(from_os_str))]
     base_dir: path::PathBuf,
 
+    #[structopt(short = "w", long = "width", long_help = "width", default_value = "85")]
+    width: u32,
+
+    #[structopt(short = "h", long = "height", long_help = "height", default_value = "112")]
+    height: u32,
+
+    #[structopt(short = "p", long = "pipeline", long_help = "preprocessing pipeline spec (toml or json)", parse(from_os_str))]
+    pipeline: Option<path::PathBuf>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "Info")]
     log_level: String,
 }
@@ -42,15 +54,43 @@ fn main() -> io::Result<()> {
     }
     info!("filtered_files.len(): {}", filtered_files.len());
 
+    let default_spec = PipelineSpec {
+        steps: vec![
+            TransformSpec::Crop {
+                left: 25,
+                right: 25,
+                top: 75,
+                bottom: 100,
+            },
+            TransformSpec::Invert,
+        ],
+        filter: ResizeFilter::Gaussian,
+        color_space: ColorSpace::Rgb,
+    };
+    let pipeline_spec = pipeline::load_spec(options.pipeline.as_ref().map(|e| e.as_path()), default_spec)?;
+    let pipeline = sync::Arc::new(Pipeline::new(pipeline_spec, options.width, options.height));
+    info!("pipeline: {}", pipeline.hash());
+
     let pool = threadpool::ThreadPool::new(num_cpus::get() / 2);
+    let failures = sync::Arc::new(sync::Mutex::new(Vec::new()));
 
     for entry in filtered_files.into_iter() {
         // info!("parent_dir: {:?}", parent_entry_path);
         if !entry.is_dir() && entry.to_string_lossy().ends_with("jpg") && !entry.to_string_lossy().contains("normalized") {
+            let pipeline = pipeline.clone();
+            let failures = failures.clone();
             pool.execute(move || {
-                let img = image::open(entry.clone()).unwrap();
-                let mut cropped_image = rusty_herbarium::crop_image(img, 25, 25, 75, 100);
-                image::imageops::invert(&mut cropped_image);
+                let img = match image::open(entry.clone())
+                    .map_err(|e| e.to_string())
+                    .and_then(|img| pipeline.apply(img).map_err(|e| e.to_string()))
+                {
+                    Ok(img) => img,
+                    Err(reason) => {
+                        warn!("skipping: {}, {}", entry.to_string_lossy(), reason);
+                        failures.lock().unwrap().push(entry);
+                        return;
+                    }
+                };
 
                 //let resized_image = image::imageops::resize(&cropped_image, 440, 660, image::imageops::FilterType::Gaussian);
                 // roughly 7k images loaded
@@ -58,7 +98,7 @@ fn main() -> io::Result<()> {
                 //let resized_image = image::imageops::resize(&cropped_image, 350, 450, image::imageops::FilterType::Gaussian);
                 // roughly 14k images loaded
 
-                let resized_image = image::imageops::resize(&cropped_image, 85, 112, image::imageops::FilterType::Gaussian);
+                let resized_image = pipeline.render(&pipeline.resize(&img));
                 let parent_entry_path = entry.parent().unwrap();
                 let mut output = parent_entry_path.to_path_buf();
                 output.push(format!("normalized-{}", entry.file_name().unwrap().to_string_lossy()));
@@ -70,6 +110,11 @@ fn main() -> io::Result<()> {
 
     pool.join();
 
+    let failures = failures.lock().unwrap();
+    if !failures.is_empty() {
+        warn!("skipped {} images that failed to open or preprocess", failures.len());
+    }
+
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
     Ok(())
 }
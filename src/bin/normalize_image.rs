@@ -6,6 +6,8 @@ extern crate structopt;
 
 use humantime::format_duration;
 use log::Level;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::pipeline::{Pipeline, PipelineSpec};
 use std::io;
 use std::path;
 use std::str::FromStr;
@@ -27,6 +29,17 @@ struct Options {
     #[structopt(short = "o", long = "output", long_help = "output file", required = true, parse(from_os_str))]
     output: path::PathBuf,
 
+    #[structopt(short = "p", long = "pipeline", long_help = "preprocessing pipeline spec (toml or json)", parse(from_os_str))]
+    pipeline: Option<path::PathBuf>,
+
+    #[structopt(
+        short = "d",
+        long = "debug_dir",
+        long_help = "also write every intermediate image & overlay into this directory",
+        parse(from_os_str)
+    )]
+    debug_dir: Option<path::PathBuf>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "Info")]
     log_level: String,
 }
@@ -39,15 +52,19 @@ fn main() -> io::Result<()> {
     //680x1000
     //400x600
 
-    let img = image::open(options.input).unwrap();
-    let img = rusty_herbarium::preprocessing_step_1(img);
-    let img = rusty_herbarium::preprocessing_step_2(img);
-    // let img = rusty_herbarium::preprocessing_step_3(img);
-    // let img = rusty_herbarium::preprocessing_step_4(img);
+    let pipeline_spec = pipeline::load_spec(options.pipeline.as_ref().map(|e| e.as_path()), PipelineSpec::default())?;
+    let pipeline = Pipeline::new(pipeline_spec, options.width, options.height);
 
-    let mut img = image::imageops::resize(&img, options.width, options.height, image::imageops::FilterType::CatmullRom);
-
-    let img = image::imageops::grayscale(&mut img);
+    let img = image::open(options.input).unwrap();
+    let img = match options.debug_dir {
+        Some(ref debug_dir) => {
+            let (stages, result) = pipeline.apply_debug(img);
+            pipeline::write_debug_stages(debug_dir.as_path(), &stages)?;
+            result?
+        }
+        None => pipeline.apply(img)?,
+    };
+    let img = pipeline.render(&pipeline.resize(&img));
 
     img.save(options.output).ok();
 
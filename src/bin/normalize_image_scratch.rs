@@ -7,6 +7,8 @@ extern crate structopt;
 
 use humantime::format_duration;
 use log::Level;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::pipeline::{ColorSpace, Pipeline, PipelineSpec};
 use std::io;
 use std::path;
 use std::str::FromStr;
@@ -28,6 +30,9 @@ struct Options {
     #[structopt(short = "o", long = "output", long_help = "output file", required = true, parse(from_os_str))]
     output: path::PathBuf,
 
+    #[structopt(short = "p", long = "pipeline", long_help = "preprocessing pipeline spec (toml or json)", parse(from_os_str))]
+    pipeline: Option<path::PathBuf>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
     log_level: String,
 }
@@ -38,14 +43,16 @@ fn main() -> io::Result<()> {
     simple_logger::init_with_level(log_level).unwrap();
     debug!("{:?}", options);
 
-    let img = image::open(options.input).unwrap();
-
-    let img = rusty_herbarium::preprocessing_step_1(img);
-    let img = rusty_herbarium::preprocessing_step_2(img);
-    // let img = preprocessing_step_3(img);
-    // let img = preprocessing_step_4(img);
+    // the training steps, but kept in color
+    let default_spec = PipelineSpec {
+        color_space: ColorSpace::Rgb,
+        ..PipelineSpec::default()
+    };
+    let pipeline_spec = pipeline::load_spec(options.pipeline.as_ref().map(|e| e.as_path()), default_spec)?;
+    let pipeline = Pipeline::new(pipeline_spec, options.width, options.height);
 
-    let img = image::imageops::resize(&img, options.width, options.height, image::imageops::FilterType::CatmullRom);
+    let img = image::open(options.input).unwrap();
+    let img = pipeline.render(&pipeline.resize(&pipeline.apply(img)?));
 
     img.save(options.output).ok();
 
@@ -16,6 +16,9 @@ use rustlearn::array;
 use rustlearn::linear_models::sgdclassifier;
 use rustlearn::metrics;
 use rustlearn::prelude::*;
+use rusty_herbarium::label_encoder;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
 use std::fs;
 use std::io;
 use std::path;
@@ -38,6 +41,9 @@ struct Options {
     #[structopt(short = "s", long = "serialization_dir", long_help = "serialization directory", required = true, parse(from_os_str))]
     serialization_dir: path::PathBuf,
 
+    #[structopt(short = "m", long = "metrics_output", long_help = "write the validation metrics as json to this file", parse(from_os_str))]
+    metrics_output: Option<path::PathBuf>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "debug")]
     log_level: String,
 }
@@ -74,6 +80,18 @@ fn main() -> io::Result<()> {
     let training_labels: Vec<f32> = bincode::deserialize_from(&mut training_labels_decoder).unwrap();
     debug!("training_labels.len(): {}", training_labels.len());
 
+    let training_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Training, options.width, options.height)?;
+    training_manifest.check_rows(DatasetPart::Training, training_labels.len())?;
+    info!(
+        "training rows: {}, images: {}, categories: {}",
+        training_manifest.entries.len(),
+        training_manifest.image_count(),
+        training_manifest.category_count()
+    );
+
+    let label_encoder = label_encoder::read_label_encoder(options.serialization_dir.as_path(), options.width, options.height)?;
+    info!("classes: {}", label_encoder.class_count());
+
     let dense_training_labels = array::dense::Array::from(training_labels);
 
     // deserializing the validation data
@@ -99,6 +117,16 @@ fn main() -> io::Result<()> {
     let validation_labels_reader = io::BufReader::new(fs::File::open(validation_labels_path).unwrap());
     let mut validation_labels_decoder = GzDecoder::new(validation_labels_reader);
     let validation_labels: Vec<f32> = bincode::deserialize_from(&mut validation_labels_decoder).unwrap();
+
+    let validation_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Validation, options.width, options.height)?;
+    validation_manifest.check_rows(DatasetPart::Validation, validation_labels.len())?;
+    info!(
+        "validation rows: {}, images: {}, categories: {}",
+        validation_manifest.entries.len(),
+        validation_manifest.image_count(),
+        validation_manifest.category_count()
+    );
+
     debug!("validation_labels.len(): {}", validation_labels.len());
 
     let dense_validation_labels = array::dense::Array::from(validation_labels);
@@ -119,8 +147,21 @@ fn main() -> io::Result<()> {
 
     debug!("validation_labels: {:?}", dense_validation_labels);
     debug!("prediction_output: {:?}", prediction_output);
-
-    info!("accuracy: {}", metrics::accuracy_score(&dense_validation_labels, &prediction_output));
+    debug!(
+        "predicted category_ids: {:?}",
+        prediction_output.data().iter().map(|e| label_encoder.decode_label(*e)).collect::<Vec<_>>()
+    );
+
+    let accuracy = metrics::accuracy_score(&dense_validation_labels, &prediction_output);
+    info!("accuracy: {}", accuracy);
+
+    if let Some(ref metrics_output) = options.metrics_output {
+        let mut model_metrics = rusty_herbarium::cross_validation::ModelMetrics::new("rustlearn_logistic_regression");
+        model_metrics.insert("accuracy", accuracy as f64);
+        model_metrics.insert("validation_rows", dense_validation_labels.rows() as f64);
+        info!("writing: {}", metrics_output.to_string_lossy());
+        model_metrics.write(metrics_output.as_path())?;
+    }
 
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
     Ok(())
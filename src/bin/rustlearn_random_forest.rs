@@ -17,6 +17,11 @@ use rustlearn::ensemble::random_forest;
 use rustlearn::metrics;
 use rustlearn::prelude::*;
 use rustlearn::trees::decision_tree;
+use rusty_herbarium::label_encoder;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::submission;
 use std::fs;
 use std::io;
 use std::path;
@@ -36,6 +41,29 @@ struct Options {
     #[structopt(short = "s", long = "serialization_dir", long_help = "serialization directory", required = true, parse(from_os_str))]
     serialization_dir: path::PathBuf,
 
+    #[structopt(short = "m", long = "metrics_output", long_help = "write the validation metrics as json to this file", parse(from_os_str))]
+    metrics_output: Option<path::PathBuf>,
+
+    #[structopt(
+        short = "t",
+        long = "submission_output",
+        long_help = "predict the testing data and write a kaggle submission csv",
+        requires = "base-dir",
+        parse(from_os_str)
+    )]
+    submission_output: Option<path::PathBuf>,
+
+    #[structopt(
+        short = "b",
+        long = "base_dir",
+        long_help = "base directory containing test, the submission is checked against its metadata",
+        parse(from_os_str)
+    )]
+    base_dir: Option<path::PathBuf>,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "debug")]
     log_level: String,
 }
@@ -61,14 +89,7 @@ fn main() -> io::Result<()> {
     let all_same_size = itertools::all(&training_data, |e| e.len() == features_count);
     debug!("all_same_size: {}", all_same_size);
 
-    let mut sparse_array_training_data = array::sparse::SparseRowArray::zeros(training_data.len(), features_count);
-    for (i, row) in training_data.into_iter().enumerate() {
-        for (j, col) in row.into_iter().enumerate() {
-            if col > 0.04 {
-                sparse_array_training_data.set(i, j, col);
-            }
-        }
-    }
+    let sparse_array_training_data = submission::to_sparse_array(training_data, features_count);
     debug!("sparse_array_training_data.rows(): {}", sparse_array_training_data.rows());
 
     // deserializing the training labels
@@ -78,6 +99,19 @@ fn main() -> io::Result<()> {
     let training_labels_reader = io::BufReader::new(fs::File::open(training_labels_path).unwrap());
     let mut training_labels_decoder = GzDecoder::new(training_labels_reader);
     let training_labels: Vec<f32> = bincode::deserialize_from(&mut training_labels_decoder).unwrap();
+
+    let training_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Training, options.width, options.height)?;
+    training_manifest.check_rows(DatasetPart::Training, training_labels.len())?;
+    info!(
+        "training rows: {}, images: {}, categories: {}",
+        training_manifest.entries.len(),
+        training_manifest.image_count(),
+        training_manifest.category_count()
+    );
+
+    let label_encoder = label_encoder::read_label_encoder(options.serialization_dir.as_path(), options.width, options.height)?;
+    info!("classes: {}", label_encoder.class_count());
+
     let dense_array_training_labels = array::dense::Array::from(training_labels);
     debug!("dense_array_training_labels.rows(): {}", dense_array_training_labels.rows());
 
@@ -89,14 +123,7 @@ fn main() -> io::Result<()> {
     let mut validation_data_decoder = GzDecoder::new(validation_data_reader);
     let validation_data: Vec<Vec<f32>> = bincode::deserialize_from(&mut validation_data_decoder).unwrap();
 
-    let mut sparse_array_validation_data = array::sparse::SparseRowArray::zeros(validation_data.len(), validation_data.first().unwrap().len());
-    for (i, row) in validation_data.into_iter().enumerate() {
-        for (j, col) in row.into_iter().enumerate() {
-            if col > 0.04 {
-                sparse_array_validation_data.set(i, j, col);
-            }
-        }
-    }
+    let sparse_array_validation_data = submission::to_sparse_array(validation_data, features_count);
     debug!("sparse_array_validation_data.rows(): {}", sparse_array_validation_data.rows());
 
     // deserializing the validation labels
@@ -106,6 +133,16 @@ fn main() -> io::Result<()> {
     let validation_labels_reader = io::BufReader::new(fs::File::open(validation_labels_path).unwrap());
     let mut validation_labels_decoder = GzDecoder::new(validation_labels_reader);
     let validation_labels: Vec<f32> = bincode::deserialize_from(&mut validation_labels_decoder).unwrap();
+
+    let validation_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Validation, options.width, options.height)?;
+    validation_manifest.check_rows(DatasetPart::Validation, validation_labels.len())?;
+    info!(
+        "validation rows: {}, images: {}, categories: {}",
+        validation_manifest.entries.len(),
+        validation_manifest.image_count(),
+        validation_manifest.category_count()
+    );
+
     let dense_array_validation_labels = array::dense::Array::from(validation_labels);
     debug!("dense_array_validation_labels.rows(): {}", dense_array_validation_labels.rows());
 
@@ -124,8 +161,57 @@ fn main() -> io::Result<()> {
 
     debug!("validation_labels: {:?}", dense_array_validation_labels);
     debug!("prediction_output: {:?}", prediction_output);
+    debug!(
+        "predicted category_ids: {:?}",
+        prediction_output.data().iter().map(|e| label_encoder.decode_label(*e)).collect::<Vec<_>>()
+    );
+
+    let accuracy = metrics::accuracy_score(&dense_array_validation_labels, &prediction_output);
+    info!("accuracy: {}", accuracy);
+
+    if let Some(ref metrics_output) = options.metrics_output {
+        let mut model_metrics = rusty_herbarium::cross_validation::ModelMetrics::new("rustlearn_random_forest");
+        model_metrics.insert("accuracy", accuracy as f64);
+        model_metrics.insert("validation_rows", dense_array_validation_labels.rows() as f64);
+        info!("writing: {}", metrics_output.to_string_lossy());
+        model_metrics.write(metrics_output.as_path())?;
+    }
+
+    if let (Some(submission_output), Some(base_dir)) = (options.submission_output.as_ref(), options.base_dir.as_ref()) {
+        let testing_data = submission::read_testing_data(options.serialization_dir.as_path(), options.width, options.height)?;
+        if testing_data.cols() != features_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("testing data has {} features, training data {}", testing_data.cols(), features_count),
+            ));
+        }
+        let testing_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Testing, options.width, options.height)?;
+        testing_manifest.check_rows(DatasetPart::Testing, testing_data.rows())?;
+
+        let testing_predictions = model.predict(&testing_data).unwrap();
+        let mut submission = submission::Submission::from_predictions(&testing_manifest, testing_predictions.data(), &label_encoder)?;
+
+        let testing_failures = pipeline::read_part_failures(options.serialization_dir.as_path(), DatasetPart::Testing, options.width, options.height)?;
+        if !testing_failures.is_empty() {
+            let fallback_category_id =
+                submission::most_frequent_category_id(&training_manifest).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no training category to fall back on"))?;
+            warn!(
+                "{} test images failed to preprocess, predicting the most frequent training category_id: {} for them",
+                testing_failures.len(),
+                fallback_category_id
+            );
+            submission.add_fallback(&testing_failures, fallback_category_id)?;
+        }
 
-    info!("accuracy: {}", metrics::accuracy_score(&dense_array_validation_labels, &prediction_output));
+        let mut test_metadata_path = base_dir.to_path_buf();
+        test_metadata_path.push("test");
+        test_metadata_path.push("metadata.json");
+        let test_metadata = rusty_herbarium::metadata::read_test_metadata_cached(test_metadata_path.as_path(), options.metadata_version)?;
+        submission.validate(&test_metadata)?;
+
+        info!("writing: {} ({} test images)", submission_output.to_string_lossy(), submission.len());
+        submission.write(submission_output.as_path())?;
+    }
 
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
     Ok(())
@@ -16,6 +16,10 @@ use rustlearn::array;
 use rustlearn::metrics;
 use rustlearn::prelude::*;
 use rustlearn::svm::libsvm::svc;
+use rusty_herbarium::label_encoder;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
+use rusty_herbarium::submission;
 use std::fs;
 use std::io;
 use std::path;
@@ -35,6 +39,9 @@ struct Options {
     #[structopt(short = "s", long = "serialization_dir", long_help = "serialization directory", required = true, parse(from_os_str))]
     serialization_dir: path::PathBuf,
 
+    #[structopt(short = "m", long = "metrics_output", long_help = "write the validation metrics as json to this file", parse(from_os_str))]
+    metrics_output: Option<path::PathBuf>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "debug")]
     log_level: String,
 }
@@ -60,14 +67,7 @@ fn main() -> io::Result<()> {
     let all_same_size = itertools::all(&training_data, |e| e.len() == features_count);
     debug!("all_same_size: {}", all_same_size);
 
-    let mut sparse_array_training_data = array::sparse::SparseRowArray::zeros(training_data.len(), features_count);
-    for (i, row) in training_data.into_iter().enumerate() {
-        for (j, col) in row.into_iter().enumerate() {
-            if col > 0.04 {
-                sparse_array_training_data.set(i, j, col);
-            }
-        }
-    }
+    let sparse_array_training_data = submission::to_sparse_array(training_data, features_count);
     debug!("sparse_array_training_data.rows(): {}", sparse_array_training_data.rows());
 
     // deserializing the training labels
@@ -77,6 +77,19 @@ fn main() -> io::Result<()> {
     let training_labels_reader = io::BufReader::new(fs::File::open(training_labels_path).unwrap());
     let mut training_labels_decoder = GzDecoder::new(training_labels_reader);
     let training_labels: Vec<f32> = bincode::deserialize_from(&mut training_labels_decoder).unwrap();
+
+    let training_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Training, options.width, options.height)?;
+    training_manifest.check_rows(DatasetPart::Training, training_labels.len())?;
+    info!(
+        "training rows: {}, images: {}, categories: {}",
+        training_manifest.entries.len(),
+        training_manifest.image_count(),
+        training_manifest.category_count()
+    );
+
+    let label_encoder = label_encoder::read_label_encoder(options.serialization_dir.as_path(), options.width, options.height)?;
+    info!("classes: {}", label_encoder.class_count());
+
     let dense_array_training_labels = array::dense::Array::from(training_labels);
     debug!("dense_array_training_labels.rows(): {}", dense_array_training_labels.rows());
 
@@ -88,14 +101,7 @@ fn main() -> io::Result<()> {
     let mut validation_data_decoder = GzDecoder::new(validation_data_reader);
     let validation_data: Vec<Vec<f32>> = bincode::deserialize_from(&mut validation_data_decoder).unwrap();
 
-    let mut sparse_array_validation_data = array::sparse::SparseRowArray::zeros(validation_data.len(), validation_data.first().unwrap().len());
-    for (i, row) in validation_data.into_iter().enumerate() {
-        for (j, col) in row.into_iter().enumerate() {
-            if col > 0.04 {
-                sparse_array_validation_data.set(i, j, col);
-            }
-        }
-    }
+    let sparse_array_validation_data = submission::to_sparse_array(validation_data, features_count);
     debug!("sparse_array_validation_data.rows(): {}", sparse_array_validation_data.rows());
 
     // deserializing the validation labels
@@ -105,6 +111,16 @@ fn main() -> io::Result<()> {
     let validation_labels_reader = io::BufReader::new(fs::File::open(validation_labels_path).unwrap());
     let mut validation_labels_decoder = GzDecoder::new(validation_labels_reader);
     let validation_labels: Vec<f32> = bincode::deserialize_from(&mut validation_labels_decoder).unwrap();
+
+    let validation_manifest = manifest::read_part_manifest(options.serialization_dir.as_path(), DatasetPart::Validation, options.width, options.height)?;
+    validation_manifest.check_rows(DatasetPart::Validation, validation_labels.len())?;
+    info!(
+        "validation rows: {}, images: {}, categories: {}",
+        validation_manifest.entries.len(),
+        validation_manifest.image_count(),
+        validation_manifest.category_count()
+    );
+
     let dense_array_validation_labels = array::dense::Array::from(validation_labels);
     debug!("dense_array_validation_labels.rows(): {}", dense_array_validation_labels.rows());
 
@@ -118,8 +134,21 @@ fn main() -> io::Result<()> {
 
     debug!("validation_labels: {:?}", dense_array_validation_labels);
     debug!("prediction_output: {:?}", prediction_output);
-
-    info!("accuracy: {}", metrics::accuracy_score(&dense_array_validation_labels, &prediction_output));
+    debug!(
+        "predicted category_ids: {:?}",
+        prediction_output.data().iter().map(|e| label_encoder.decode_label(*e)).collect::<Vec<_>>()
+    );
+
+    let accuracy = metrics::accuracy_score(&dense_array_validation_labels, &prediction_output);
+    info!("accuracy: {}", accuracy);
+
+    if let Some(ref metrics_output) = options.metrics_output {
+        let mut model_metrics = rusty_herbarium::cross_validation::ModelMetrics::new("rustlearn_svm");
+        model_metrics.insert("accuracy", accuracy as f64);
+        model_metrics.insert("validation_rows", dense_array_validation_labels.rows() as f64);
+        info!("writing: {}", metrics_output.to_string_lossy());
+        model_metrics.write(metrics_output.as_path())?;
+    }
 
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
     Ok(())
@@ -10,14 +10,12 @@ extern crate structopt;
 
 use humantime::format_duration;
 use image::GenericImageView;
-use itertools::Itertools;
 use log::Level;
+use rusty_herbarium::label_encoder::LabelEncoder;
 use rusty_machine::learning;
 use rusty_machine::learning::optim::grad_desc::GradientDesc;
 use rusty_machine::linalg;
 use rusty_machine::prelude::SupModel;
-use std::collections;
-use std::fs;
 use std::io;
 use std::path;
 use std::str::FromStr;
@@ -30,6 +28,9 @@ struct Options {
     #[structopt(short = "i", long = "input", long_help = "input dir", required = true, parse(from_os_str))]
     base_dir: path::PathBuf,
 
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
     log_level: String,
 }
@@ -47,27 +48,21 @@ fn main() -> io::Result<()> {
     let mut train_metadata_path = train_dir.clone();
     train_metadata_path.push("metadata.json");
 
-    let train_metadata_file = fs::File::open(train_metadata_path.as_path()).expect("missing file");
-    let br = io::BufReader::new(train_metadata_file);
-
-    let train_metadata: rusty_herbarium::TrainMetadata = serde_json::from_reader(br)?;
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
     //info!("metadata.info.year: {}", metadata.info.year);
+    let catalog = rusty_herbarium::HerbariumCatalog::new(train_metadata);
 
-    let images_by_region_and_category_map: collections::HashMap<(i32, i32), Vec<i32>> =
-        train_metadata.annotations.iter().map(|x| ((x.region_id, x.category_id), x.image_id)).into_group_map();
-    let mut region_and_category_ids: Vec<_> = images_by_region_and_category_map.keys().collect();
-    region_and_category_ids.sort();
+    let region_and_category_ids = catalog.region_and_category_ids();
+    let label_encoder: LabelEncoder = region_and_category_ids.iter().map(|e| e.1).collect();
 
     let mut train_feature_size = 0usize;
     let mut train_data_capacity = 0usize;
 
     for k in region_and_category_ids.iter() {
-        let image_ids = images_by_region_and_category_map.get(k).unwrap();
+        let image_ids = catalog.image_ids_for_region_and_category(k.0, k.1);
         let filtered_images_ids: Vec<_> = image_ids.iter().take(2).collect();
         for image_id in filtered_images_ids.into_iter() {
-            let image = train_metadata.images.iter().find(|e| e.id == *image_id).unwrap();
-            let mut image_path = train_dir.clone();
-            image_path.push(image.file_name.clone());
+            let image_path = catalog.image_path(&train_dir, *image_id).unwrap();
 
             let mut normalized_name = String::new();
             normalized_name.push_str("normalized-");
@@ -93,13 +88,11 @@ fn main() -> io::Result<()> {
     let mut targets: Vec<f64> = Vec::new();
 
     for k in region_and_category_ids.iter() {
-        let image_ids = images_by_region_and_category_map.get(k).unwrap();
+        let image_ids = catalog.image_ids_for_region_and_category(k.0, k.1);
         let filtered_images_ids: Vec<_> = image_ids.iter().take(2).collect();
         for image_id in filtered_images_ids.into_iter() {
-            targets.push(k.1.into()); //category_id
-            let image = train_metadata.images.iter().find(|e| e.id == *image_id).unwrap();
-            let mut image_path = train_dir.clone();
-            image_path.push(image.file_name.clone());
+            targets.push(label_encoder.encode(k.1).unwrap() as f64); //category_id class index
+            let image_path = catalog.image_path(&train_dir, *image_id).unwrap();
 
             let mut normalized_name = String::new();
             normalized_name.push_str("normalized-");
@@ -137,10 +130,7 @@ fn main() -> io::Result<()> {
     let mut test_metadata_path = test_dir.clone();
     test_metadata_path.push("metadata.json");
 
-    let test_metadata_file = fs::File::open(test_metadata_path.as_path()).expect("missing file");
-    let br = io::BufReader::new(test_metadata_file);
-
-    let test_metadata: rusty_herbarium::TestMetadata = serde_json::from_reader(br)?;
+    let test_metadata = rusty_herbarium::metadata::read_test_metadata_cached(test_metadata_path.as_path(), options.metadata_version)?;
 
     let mut test_feature_size = 0usize;
     let mut test_data: Vec<f64> = Vec::new();
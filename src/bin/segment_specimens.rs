@@ -0,0 +1,152 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate image;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::calibration::CalibrationParams;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::region::BoundingBox;
+use rusty_herbarium::segmentation;
+use rusty_herbarium::segmentation::SegmentationParams;
+use rusty_herbarium::specimen_label::LabelParams;
+use serde::Serialize;
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "segment_specimens", about = "plant masks & specimen crops of herbarium sheets")]
+struct Options {
+    #[structopt(short = "i", long = "input", long_help = "input images", required = true, parse(from_os_str))]
+    inputs: Vec<path::PathBuf>,
+
+    #[structopt(
+        short = "o",
+        long = "output_dir",
+        long_help = "output directory, <name>-mask.png, <name>-specimen.png & segmentation.json",
+        required = true,
+        parse(from_os_str)
+    )]
+    output_dir: path::PathBuf,
+
+    #[structopt(short = "s", long = "segmentation_params", long_help = "segmentation params (toml or json)", parse(from_os_str))]
+    segmentation_params: Option<path::PathBuf>,
+
+    #[structopt(short = "c", long = "calibration_params", long_help = "color bar & ruler detection params (toml or json)", parse(from_os_str))]
+    calibration_params: Option<path::PathBuf>,
+
+    #[structopt(short = "a", long = "label_params", long_help = "label, stamp & barcode detection params (toml or json)", parse(from_os_str))]
+    label_params: Option<path::PathBuf>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SegmentationSummary {
+    width: u32,
+    height: u32,
+    threshold: u8,
+    components: usize,
+    foreground_share: f32,
+    bbox: Option<BoundingBox>,
+    excluded: Vec<BoundingBox>,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let segmentation_params: SegmentationParams = match options.segmentation_params {
+        Some(ref params_path) => pipeline::read_config(params_path.as_path())?,
+        None => SegmentationParams::default(),
+    };
+    let calibration_params: CalibrationParams = match options.calibration_params {
+        Some(ref params_path) => pipeline::read_config(params_path.as_path())?,
+        None => CalibrationParams::default(),
+    };
+    let label_params: LabelParams = match options.label_params {
+        Some(ref params_path) => pipeline::read_config(params_path.as_path())?,
+        None => LabelParams::default(),
+    };
+    fs::create_dir_all(options.output_dir.as_path())?;
+
+    let mut summaries: collections::BTreeMap<String, SegmentationSummary> = collections::BTreeMap::new();
+    let mut names: collections::HashSet<String> = collections::HashSet::new();
+    for input in options.inputs.iter() {
+        let img = match image::open(input.as_path()) {
+            Ok(img) => img.to_rgba8(),
+            Err(e) => {
+                warn!("skipping: {}, {}", input.to_string_lossy(), e);
+                continue;
+            }
+        };
+        let excluded = segmentation::excluded_regions(&img, &segmentation_params, &calibration_params, &label_params);
+        let result = segmentation::segment_specimen(&img, &segmentation_params, &excluded);
+        info!(
+            "{}: threshold: {}, components: {}, foreground: {:.3}, specimen: {:?}",
+            input.to_string_lossy(),
+            result.threshold,
+            result.components.len(),
+            result.foreground_share(),
+            result.bbox
+        );
+
+        // inputs from different directories can share a file name, so the parent directory goes into the output name as well
+        let stem = input.file_stem().unwrap().to_string_lossy().to_string();
+        let mut name = match input.parent().and_then(|e| e.file_name()) {
+            Some(parent) => format!("{}-{}", parent.to_string_lossy(), stem),
+            None => stem,
+        };
+        if !names.insert(name.clone()) {
+            name = format!("{}-{}", name, names.len());
+            names.insert(name.clone());
+        }
+        let mut mask_path = options.output_dir.clone();
+        mask_path.push(format!("{}-mask.png", name));
+        segmentation::mask_image(&result.mask)
+            .save(mask_path.as_path())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        match result.bbox {
+            Some(ref bbox) => {
+                let mut specimen_path = options.output_dir.clone();
+                specimen_path.push(format!("{}-specimen.png", name));
+                segmentation::crop_to(&img, bbox)
+                    .save(specimen_path.as_path())
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            }
+            None => warn!("no specimen found: {}", input.to_string_lossy()),
+        }
+
+        summaries.insert(
+            input.to_string_lossy().to_string(),
+            SegmentationSummary {
+                width: img.width(),
+                height: img.height(),
+                threshold: result.threshold,
+                components: result.components.len(),
+                foreground_share: result.foreground_share(),
+                bbox: result.bbox,
+                excluded,
+            },
+        );
+    }
+
+    let mut summary_path = options.output_dir.clone();
+    summary_path.push("segmentation.json");
+    info!("writing: {}", summary_path.to_string_lossy());
+    serde_json::to_writer_pretty(io::BufWriter::new(fs::File::create(summary_path.as_path())?), &summaries)?;
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
@@ -15,6 +15,11 @@ use humantime::format_duration;
 use log::Level;
 use rustlearn::array;
 use rustlearn::prelude::*;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::pipeline::{Pipeline, PipelineSpec};
+use rusty_herbarium::submission;
 use std::fs;
 use std::io;
 use std::path;
@@ -37,6 +42,25 @@ struct Options {
     #[structopt(short = "o", long = "output_dir", long_help = "output directory", required = true, parse(from_os_str))]
     output_dir: path::PathBuf,
 
+    #[structopt(
+        short = "f",
+        long = "manifest",
+        long_help = "serialize the testing images listed in a manifest instead of every test image",
+        parse(from_os_str)
+    )]
+    manifest: Option<path::PathBuf>,
+
+    #[structopt(
+        short = "p",
+        long = "pipeline",
+        long_help = "preprocessing pipeline spec (toml or json), defaults to the one the training data in output_dir was serialized with",
+        parse(from_os_str)
+    )]
+    pipeline: Option<path::PathBuf>,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "debug")]
     log_level: String,
 }
@@ -48,66 +72,103 @@ fn main() -> io::Result<()> {
     simple_logger::init_with_level(log_level).unwrap();
     debug!("{:?}", options);
 
-    let col_size = ((options.width * options.height) * 3) as usize;
-
-    let mut test_dir = options.base_dir.clone();
-    test_dir.push("test");
-
-    let mut test_metadata_path = test_dir.clone();
-    test_metadata_path.push("metadata.json");
-
-    debug!("reading: {}", test_metadata_path.to_string_lossy());
-    let test_metadata_file = fs::File::open(test_metadata_path.as_path()).expect("missing file");
-    let br = io::BufReader::new(test_metadata_file);
-
-    let testing_metadata: rusty_herbarium::TestMetadata = serde_json::from_reader(br)?;
-
-    let mut testing_data = array::sparse::SparseRowArray::zeros(testing_metadata.images.len(), col_size);
-
-    for (i, image) in testing_metadata.images.iter().enumerate() {
-        let mut image_path = test_dir.clone();
-        image_path.push(image.file_name.clone());
-        let img = image::open(image_path.as_path()).unwrap();
-        // risk cropping more from the bottom as roots don't offer identifying species features
-        // stems, leafs, and flowers are where it's at
-        let mut img = rusty_herbarium::crop_image(img, 30, 30, 80, 140);
-        // original images are roughly 680x1000
-        // resulting cropping will return roughly 620x780
-
-        image::imageops::invert(&mut img);
-
-        let mut img = image::imageops::brighten(&mut img, 10);
-        let img = image::imageops::contrast(&mut img, 20.0);
-
-        let img = image::imageops::resize(&img, options.width, options.height, image::imageops::FilterType::Gaussian);
+    // the training data's record decides, so train & test can't end up preprocessed differently
+    let training_record = pipeline::read_pipeline_record(options.output_dir.as_path(), options.width, options.height)?;
+    let pipeline_spec = match (options.pipeline.as_ref(), training_record.as_ref()) {
+        (None, Some(training_record)) => training_record.spec.clone(),
+        (spec_path, _) => pipeline::load_spec(spec_path.map(|e| e.as_path()), PipelineSpec::default())?,
+    };
+    let pipeline = Pipeline::new(pipeline_spec, options.width, options.height);
+    match training_record {
+        Some(ref training_record) if training_record.hash != pipeline.hash() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "pipeline {} differs from the {} the data in the output directory was serialized with",
+                    pipeline.hash(),
+                    training_record.hash
+                ),
+            ));
+        }
+        Some(_) => info!("pipeline: {}", pipeline.hash()),
+        None => {
+            let mut pipeline_output = options.output_dir.clone();
+            pipeline_output.push(pipeline::PipelineRecord::file_name(options.width, options.height));
+            info!("writing: {}, pipeline: {}", pipeline_output.to_string_lossy(), pipeline.hash());
+            pipeline.record().write(pipeline_output.as_path())?;
+        }
+    }
 
-        let mut idx = 0;
-        for x in 0..img.width() {
-            for y in 0..img.height() {
-                let pixel = img.get_pixel(x, y);
-                let red = pixel[0];
-                let green = pixel[1];
-                let blue = pixel[2];
+    let col_size = pipeline.feature_count();
 
-                if red > 20 && green > 20 && blue > 20 {
-                    testing_data.set(i, idx, red as f32 / 255.0);
-                    testing_data.set(i, idx + 1, green as f32 / 255.0);
-                    testing_data.set(i, idx + 2, blue as f32 / 255.0);
-                }
-                idx += 3;
-                // debug!("i: {}, idx: {}", i, idx);
+    let manifest = match options.manifest {
+        Some(ref manifest_path) => {
+            info!("reading: {}", manifest_path.to_string_lossy());
+            manifest::Manifest::read(manifest_path.as_path())?
+        }
+        None => {
+            let mut test_metadata_path = options.base_dir.clone();
+            test_metadata_path.push("test");
+            test_metadata_path.push("metadata.json");
+
+            debug!("reading: {}", test_metadata_path.to_string_lossy());
+            let testing_metadata = rusty_herbarium::metadata::read_test_metadata_cached(test_metadata_path.as_path(), options.metadata_version)?;
+            manifest::Manifest::from_test_metadata(&testing_metadata)
+        }
+    };
+    let entries = manifest.entries(DatasetPart::Testing);
+
+    // failing images are left out of both the data & the per row manifest, so the rows stay aligned
+    let mut rows = manifest::Manifest::default();
+    let mut features_by_row = Vec::with_capacity(entries.len());
+    let mut failures = Vec::new();
+    for entry in entries.into_iter() {
+        let img = match pipeline::open_and_apply(&pipeline, options.base_dir.as_path(), entry) {
+            Ok(img) => img,
+            Err(failure) => {
+                warn!("skipping image_id: {}, {}: {}", failure.image_id, failure.file_path, failure.reason);
+                failures.push(failure);
+                continue;
             }
+        };
+        let features = submission::sparse_features(pipeline.features(&pipeline.resize(&img)));
+        features_by_row.push(features);
+        rows.entries.push(entry.clone());
+    }
+
+    let mut testing_data = array::sparse::SparseRowArray::zeros(rows.entries.len(), col_size);
+    for (i, features) in features_by_row.into_iter().enumerate() {
+        for (idx, feature) in features.into_iter() {
+            testing_data.set(i, idx, feature);
         }
     }
 
+    let mut failures_output = options.output_dir.clone();
+    failures_output.push(DatasetPart::Testing.failures_file_name(options.width, options.height));
+    if !failures.is_empty() {
+        warn!(
+            "skipped {} test images that failed to preprocess, writing: {}",
+            failures.len(),
+            failures_output.to_string_lossy()
+        );
+        pipeline::write_failures(failures_output.as_path(), &failures)?;
+    } else if failures_output.exists() {
+        fs::remove_file(failures_output.as_path())?;
+    }
+
     let mut testing_data_output = options.output_dir.clone();
-    testing_data_output.push(format!("herbarium-testing-data-{}x{}.ser.gz", options.width, options.height));
+    testing_data_output.push(DatasetPart::Testing.data_file_name(options.width, options.height));
     info!("writing: {}", testing_data_output.to_string_lossy());
 
     let testing_data_writer = io::BufWriter::new(fs::File::create(testing_data_output.as_path()).unwrap());
     let mut testing_data_encoder = GzEncoder::new(testing_data_writer, Compression::default());
     bincode::serialize_into(&mut testing_data_encoder, &testing_data).unwrap();
 
+    let mut rows_output = options.output_dir.clone();
+    rows_output.push(DatasetPart::Testing.manifest_file_name(options.width, options.height));
+    info!("writing: {}", rows_output.to_string_lossy());
+    rows.write(rows_output.as_path())?;
+
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
     Ok(())
 }
@@ -12,8 +12,14 @@ use bincode;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use humantime::format_duration;
-use itertools::Itertools;
 use log::Level;
+use rusty_herbarium::label_encoder::LabelEncoder;
+use rusty_herbarium::manifest;
+use rusty_herbarium::manifest::DatasetPart;
+use rusty_herbarium::pipeline;
+use rusty_herbarium::pipeline::{Pipeline, PipelineSpec, PreprocessFailure};
+use rusty_herbarium::sampling;
+use rusty_herbarium::split;
 use std::collections;
 use std::fs;
 use std::io;
@@ -34,12 +40,66 @@ struct Options {
     #[structopt(short = "c", long = "category_limit", long_help = "category limit", default_value = "0")]
     category_limit: usize,
 
+    #[structopt(short = "s", long = "split", long_help = "reuse a previously written split file instead of splitting", parse(from_os_str))]
+    split: Option<path::PathBuf>,
+
+    #[structopt(
+        short = "f",
+        long = "manifest",
+        long_help = "serialize the images listed in a manifest instead of splitting",
+        conflicts_with = "split",
+        parse(from_os_str)
+    )]
+    manifest: Option<path::PathBuf>,
+
+    #[structopt(short = "e", long = "seed", long_help = "split seed", default_value = "0")]
+    seed: u64,
+
+    #[structopt(
+        short = "z",
+        long = "sizes",
+        long_help = "train,validation,test per class counts (10,1,0) or ratios (0.8,0.1,0.1)",
+        default_value = "10,1,0"
+    )]
+    sizes: split::SplitSizes,
+
+    #[structopt(short = "m", long = "min_per_class", long_help = "minimum training images per class", default_value = "1")]
+    min_per_class: usize,
+
+    #[structopt(short = "r", long = "stratify_by_region", long_help = "stratify by region as well as by category")]
+    stratify_by_region: bool,
+
+    #[structopt(short = "x", long = "max_per_class", long_help = "cap the training images per class, 0 for no cap", default_value = "0")]
+    max_per_class: usize,
+
+    #[structopt(
+        short = "u",
+        long = "oversample_to",
+        long_help = "oversample rare classes with augmented copies up to this many training images, 0 for no oversampling",
+        default_value = "0"
+    )]
+    oversample_to: usize,
+
+    #[structopt(
+        short = "g",
+        long = "weighting",
+        long_help = "training sample weights (none, inverse_frequency or sqrt_inverse_frequency)",
+        default_value = "none"
+    )]
+    weighting: sampling::Weighting,
+
+    #[structopt(short = "p", long = "pipeline", long_help = "preprocessing pipeline spec (toml or json)", parse(from_os_str))]
+    pipeline: Option<path::PathBuf>,
+
     #[structopt(short = "b", long = "base_dir", long_help = "base directory for ", required = true, parse(from_os_str))]
     base_dir: path::PathBuf,
 
     #[structopt(short = "o", long = "output_dir", long_help = "output directory", required = true, parse(from_os_str))]
     output_dir: path::PathBuf,
 
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "debug")]
     log_level: String,
 }
@@ -51,174 +111,213 @@ fn main() -> io::Result<()> {
     simple_logger::init_with_level(log_level).unwrap();
     debug!("{:?}", options);
 
-    let mut train_dir = options.base_dir.clone();
-    train_dir.push("train");
-
-    let mut train_metadata_path = train_dir.clone();
-    train_metadata_path.push("metadata.json");
-
-    debug!("reading: {}", train_metadata_path.to_string_lossy());
-    let train_metadata_file = fs::File::open(train_metadata_path.as_path()).expect("missing file");
-    let br = io::BufReader::new(train_metadata_file);
-
-    let training_metadata: rusty_herbarium::TrainMetadata = serde_json::from_reader(br)?;
-
-    let mut category_ids: Vec<i32> = training_metadata.annotations.iter().map(|x| x.category_id).collect();
-    category_ids.sort();
-    category_ids.dedup();
-
-    if options.category_limit > 0 {
-        category_ids = category_ids.iter().cloned().take(options.category_limit).collect();
-    }
-
-    let image_ids_by_category_map: collections::HashMap<i32, Vec<i32>> = training_metadata.annotations.iter().map(|x| (x.category_id, x.image_id)).into_group_map();
-
-    let mut image_ids_by_category_map_keys: Vec<_> = image_ids_by_category_map.keys().cloned().collect();
-    image_ids_by_category_map_keys.sort();
-
-    let mut training_image_path_by_category_map: collections::BTreeMap<i32, Vec<path::PathBuf>> = collections::BTreeMap::new();
-    let mut validation_image_path_by_category_map: collections::BTreeMap<i32, Vec<path::PathBuf>> = collections::BTreeMap::new();
-
-    debug!("category_ids: {:?}", category_ids);
-
-    for category_id in category_ids.iter() {
-        let image_ids = image_ids_by_category_map.get(category_id).unwrap();
-        // only grabbing N images per category (species)
-        let filtered_images_ids: Vec<_> = image_ids.iter().take(10).collect();
-        for image_id in filtered_images_ids.iter() {
-            let image = training_metadata.images.iter().find(|e| &e.id == *image_id).unwrap();
-            let mut image_path = train_dir.clone();
-            image_path.push(image.file_name.clone());
-            training_image_path_by_category_map.entry(*category_id).or_insert(Vec::new()).push(image_path);
+    let manifest = match options.manifest {
+        Some(ref manifest_path) => {
+            info!("reading: {}", manifest_path.to_string_lossy());
+            manifest::Manifest::read(manifest_path.as_path())?
         }
-
-        if image_ids.len() > 25 {
-            let filtered_images_ids_for_validation: Vec<_> = image_ids.iter().filter(|image_id| !filtered_images_ids.contains(image_id)).take(1).collect();
-            for image_id in filtered_images_ids_for_validation.into_iter() {
-                let image = training_metadata.images.iter().find(|e| e.id == *image_id).unwrap();
-                let mut image_path = train_dir.clone();
-                image_path.push(image.file_name.clone());
-                validation_image_path_by_category_map.entry(*category_id).or_insert(Vec::new()).push(image_path);
-            }
+        None => {
+            let mut train_metadata_path = options.base_dir.clone();
+            train_metadata_path.push("train");
+            train_metadata_path.push("metadata.json");
+
+            debug!("reading: {}", train_metadata_path.to_string_lossy());
+            let training_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+            let catalog = rusty_herbarium::HerbariumCatalog::new(training_metadata);
+
+            let split = match options.split {
+                Some(ref split_path) => {
+                    info!("reading: {}", split_path.to_string_lossy());
+                    split::Split::read(split_path.as_path())?
+                }
+                None => {
+                    let split_config = split::SplitConfig {
+                        seed: options.seed,
+                        sizes: options.sizes.clone(),
+                        min_per_class: options.min_per_class,
+                        stratify_by_region: options.stratify_by_region,
+                        category_limit: options.category_limit,
+                    };
+                    split::stratified_split(&catalog, &split_config)
+                }
+            };
+            info!(
+                "train: {}, validation: {}, test: {}, skipped categories: {}",
+                split.train.len(),
+                split.validation.len(),
+                split.test.len(),
+                split.skipped_category_ids.len()
+            );
+
+            let mut split_output = options.output_dir.clone();
+            split_output.push(format!("herbarium-split-{}x{}.json", options.width, options.height));
+            info!("writing: {}", split_output.to_string_lossy());
+            split.write(split_output.as_path())?;
+
+            manifest::Manifest::from_split(&catalog, &split)
+        }
+    };
+
+    let mut manifest_output = options.output_dir.clone();
+    manifest_output.push(format!("herbarium-manifest-{}x{}.csv", options.width, options.height));
+    info!("writing: {}", manifest_output.to_string_lossy());
+    manifest.write(manifest_output.as_path())?;
+
+    let label_encoder: LabelEncoder = manifest.entries.iter().filter_map(|e| e.category_id).collect();
+    let mut label_encoder_output = options.output_dir.clone();
+    label_encoder_output.push(LabelEncoder::file_name(options.width, options.height));
+    info!("writing: {}, classes: {}", label_encoder_output.to_string_lossy(), label_encoder.class_count());
+    label_encoder.write(label_encoder_output.as_path())?;
+
+    let pipeline_spec = pipeline::load_spec(options.pipeline.as_ref().map(|e| e.as_path()), PipelineSpec::default())?;
+    let pipeline = Pipeline::new(pipeline_spec, options.width, options.height);
+    let mut pipeline_output = options.output_dir.clone();
+    pipeline_output.push(pipeline::PipelineRecord::file_name(options.width, options.height));
+    info!("writing: {}, pipeline: {}", pipeline_output.to_string_lossy(), pipeline.hash());
+    pipeline.record().write(pipeline_output.as_path())?;
+
+    let training_sampling_config = sampling::SamplingConfig {
+        seed: options.seed,
+        max_per_class: options.max_per_class,
+        oversample_to: options.oversample_to,
+        weighting: options.weighting,
+    };
+
+    for part in [DatasetPart::Training, DatasetPart::Validation, DatasetPart::Holdout].iter() {
+        let entries = manifest.entries(*part);
+        if entries.is_empty() && *part == DatasetPart::Holdout {
+            continue;
         }
-    }
 
-    let (training_data, training_labels) = get_data_and_labels(options.width, options.height, training_image_path_by_category_map)?;
+        // only the training part is resampled, validation & holdout have to keep the real class distribution
+        let sampling_config = match part {
+            DatasetPart::Training => training_sampling_config.clone(),
+            _ => sampling::SamplingConfig::default(),
+        };
+        let samples = sampling::plan_samples(entries.clone(), &sampling_config);
+        info!("{}: images: {}, samples: {}", part, entries.len(), samples.len());
+
+        let (data, labels, rows, failures) = get_data_and_labels(&pipeline, &options.base_dir, &label_encoder, &samples)?;
+
+        let mut failures_output = options.output_dir.clone();
+        failures_output.push(part.failures_file_name(options.width, options.height));
+        if !failures.is_empty() {
+            warn!(
+                "{}: skipped {} images that failed to preprocess, writing: {}",
+                part,
+                failures.len(),
+                failures_output.to_string_lossy()
+            );
+            pipeline::write_failures(failures_output.as_path(), &failures)?;
+        } else if failures_output.exists() {
+            fs::remove_file(failures_output.as_path())?;
+        }
 
-    let mut training_labels_output = options.output_dir.clone();
-    training_labels_output.push(format!("herbarium-training-labels-{}x{}.ser.gz", options.width, options.height));
-    info!("writing: {}", training_labels_output.to_string_lossy());
+        let mut labels_output = options.output_dir.clone();
+        labels_output.push(part.labels_file_name(options.width, options.height));
+        info!("writing: {}", labels_output.to_string_lossy());
 
-    let training_labels_writer = io::BufWriter::new(fs::File::create(training_labels_output.as_path()).unwrap());
-    let mut training_labels_encoder = GzEncoder::new(training_labels_writer, Compression::default());
-    bincode::serialize_into(&mut training_labels_encoder, &training_labels).unwrap();
+        let labels_writer = io::BufWriter::new(fs::File::create(labels_output.as_path()).unwrap());
+        let mut labels_encoder = GzEncoder::new(labels_writer, Compression::default());
+        bincode::serialize_into(&mut labels_encoder, &labels).unwrap();
 
-    let mut training_data_output = options.output_dir.clone();
-    training_data_output.push(format!("herbarium-training-data-{}x{}.ser.gz", options.width, options.height));
-    info!("writing: {}", training_data_output.to_string_lossy());
+        let mut data_output = options.output_dir.clone();
+        data_output.push(part.data_file_name(options.width, options.height));
+        info!("writing: {}", data_output.to_string_lossy());
 
-    let training_data_writer = io::BufWriter::new(fs::File::create(training_data_output.as_path()).unwrap());
-    let mut training_data_encoder = GzEncoder::new(training_data_writer, Compression::default());
-    bincode::serialize_into(&mut training_data_encoder, &training_data).unwrap();
+        let data_writer = io::BufWriter::new(fs::File::create(data_output.as_path()).unwrap());
+        let mut data_encoder = GzEncoder::new(data_writer, Compression::default());
+        bincode::serialize_into(&mut data_encoder, &data).unwrap();
 
-    let (validation_data, validation_labels) = get_data_and_labels(options.width, options.height, validation_image_path_by_category_map)?;
+        let mut rows_output = options.output_dir.clone();
+        rows_output.push(part.manifest_file_name(options.width, options.height));
+        info!("writing: {}", rows_output.to_string_lossy());
+        rows.write(rows_output.as_path())?;
 
-    let mut validation_labels_output = options.output_dir.clone();
-    validation_labels_output.push(format!("herbarium-validation-labels-{}x{}.ser.gz", options.width, options.height));
-    info!("writing: {}", validation_labels_output.to_string_lossy());
+        if *part == DatasetPart::Training {
+            let weights = sampling::sample_weights(&labels, sampling_config.weighting);
 
-    let validation_labels_writer = io::BufWriter::new(fs::File::create(validation_labels_output.as_path()).unwrap());
-    let mut validation_labels_encoder = GzEncoder::new(validation_labels_writer, Compression::default());
-    bincode::serialize_into(&mut validation_labels_encoder, &validation_labels).unwrap();
+            let mut weights_output = options.output_dir.clone();
+            weights_output.push(part.weights_file_name(options.width, options.height));
+            info!("writing: {}", weights_output.to_string_lossy());
 
-    let mut validation_data_output = options.output_dir.clone();
-    validation_data_output.push(format!("herbarium-validation-data-{}x{}.ser.gz", options.width, options.height));
-    info!("writing: {}", validation_data_output.to_string_lossy());
+            let weights_writer = io::BufWriter::new(fs::File::create(weights_output.as_path()).unwrap());
+            let mut weights_encoder = GzEncoder::new(weights_writer, Compression::default());
+            bincode::serialize_into(&mut weights_encoder, &weights).unwrap();
 
-    let validation_data_writer = io::BufWriter::new(fs::File::create(validation_data_output.as_path()).unwrap());
-    let mut validation_data_encoder = GzEncoder::new(validation_data_writer, Compression::default());
-    bincode::serialize_into(&mut validation_data_encoder, &validation_data).unwrap();
+            let mut sampling_report_output = options.output_dir.clone();
+            sampling_report_output.push(sampling::SamplingReport::file_name(options.width, options.height));
+            info!("writing: {}", sampling_report_output.to_string_lossy());
+            sampling::report(&rows, &entries, &weights, &sampling_config, ROTATIONS.len()).write(sampling_report_output.as_path())?;
+        }
+    }
 
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
     Ok(())
 }
 
-fn get_data_and_labels(width: u32, height: u32, image_path_by_category_map: collections::BTreeMap<i32, Vec<path::PathBuf>>) -> io::Result<(Vec<Vec<f32>>, Vec<f32>)> {
-    // let col_size = ((width * height) * 3) as usize;
-    let col_size = (width * height) as usize;
-
-    let mut image_path_by_category_map_keys: Vec<_> = image_path_by_category_map.keys().cloned().collect();
-    image_path_by_category_map_keys.sort();
-
+const ROTATIONS: [&str; 4] = ["none", "rotate90", "rotate180", "rotate270"];
+
+// every sample contributes its four rotations, the returned manifest has one entry per row
+// labels are the encoded class indices, not the category ids
+// images that fail to open or preprocess are left out and returned as failures, once per image however often it was sampled
+fn get_data_and_labels(
+    pipeline: &Pipeline,
+    base_dir: &path::Path,
+    label_encoder: &LabelEncoder,
+    samples: &[sampling::Sample],
+) -> io::Result<(Vec<Vec<f32>>, Vec<f32>, manifest::Manifest, Vec<PreprocessFailure>)> {
     let mut training_labels: Vec<f32> = Vec::new();
     let mut training_data: Vec<Vec<f32>> = Vec::new();
-
-    for category_id in image_path_by_category_map_keys.iter() {
-        debug!("category_id: {}", category_id);
-
-        for image_path in image_path_by_category_map.get(category_id).unwrap() {
-            for _ in 0..4 {
-                training_labels.push(*category_id as f32);
-            }
-            // debug!("image_path: {}", image_path.to_string_lossy());
-
-            let img = image::open(image_path.as_path()).unwrap();
-
-            let img = rusty_herbarium::preprocessing_step_1(img);
-            let img = rusty_herbarium::preprocessing_step_2(img);
-            // let img = preprocessing_step_3(img);
-            // let img = preprocessing_step_4(img);
-
-            let mut img = image::imageops::resize(&img, width, height, image::imageops::FilterType::CatmullRom);
-
-            let mut img = image::imageops::grayscale(&mut img);
-
-            let mut image_data = Vec::with_capacity(col_size);
-            for x in 0..img.width() {
-                for y in 0..img.height() {
-                    let pixel = img.get_pixel(x, y);
-                    image_data.push(pixel[0] as f32 / 255.0);
+    let mut rows = manifest::Manifest::default();
+    let mut failures: Vec<PreprocessFailure> = Vec::new();
+    let mut failed_image_ids = collections::HashSet::new();
+
+    for sample in samples.iter() {
+        let entry = sample.entry;
+        let category_id = entry.category_id.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("image_id: {} has no category_id, {} rows need one", entry.image_id, entry.split),
+            )
+        })?;
+        let label = label_encoder.encode_label(category_id).unwrap();
+        debug!(
+            "category_id: {}, label: {}, image_id: {}, augmentation: {}",
+            category_id, label, entry.image_id, sample.augmentation
+        );
+
+        let img = match pipeline::open_and_apply(pipeline, base_dir, entry) {
+            Ok(img) => img,
+            Err(failure) => {
+                if failed_image_ids.insert(entry.image_id) {
+                    warn!("skipping image_id: {}, {}: {}", failure.image_id, failure.file_path, failure.reason);
+                    failures.push(failure);
                 }
+                continue;
             }
-
-            training_data.push(image_data);
-
-            let rotated90_img = image::imageops::rotate90(&mut img);
-
-            let mut image_data = Vec::with_capacity(col_size);
-            for x in 0..rotated90_img.width() {
-                for y in 0..rotated90_img.height() {
-                    let pixel = rotated90_img.get_pixel(x, y);
-                    image_data.push(pixel[0] as f32 / 255.0);
-                }
-            }
-            training_data.push(image_data);
-
-            let rotated180_img = image::imageops::rotate180(&mut img);
-
-            let mut image_data = Vec::with_capacity(col_size);
-            for x in 0..rotated180_img.width() {
-                for y in 0..rotated180_img.height() {
-                    let pixel = rotated180_img.get_pixel(x, y);
-                    image_data.push(pixel[0] as f32 / 255.0);
-                }
-            }
-            training_data.push(image_data);
-
-            let rotated270_img = image::imageops::rotate270(&mut img);
-
-            let mut image_data = Vec::with_capacity(col_size);
-            for x in 0..rotated270_img.width() {
-                for y in 0..rotated270_img.height() {
-                    let pixel = rotated270_img.get_pixel(x, y);
-                    image_data.push(pixel[0] as f32 / 255.0);
-                }
-            }
-            training_data.push(image_data);
+        };
+        let img = sample.augmentation.apply(img);
+
+        let mut img = pipeline.resize(&img);
+
+        training_data.push(pipeline.features(&img));
+        training_data.push(pipeline.features(&image::imageops::rotate90(&mut img)));
+        training_data.push(pipeline.features(&image::imageops::rotate180(&mut img)));
+        training_data.push(pipeline.features(&image::imageops::rotate270(&mut img)));
+
+        for rotation in ROTATIONS.iter() {
+            training_labels.push(label);
+            let mut row = entry.clone();
+            row.augmentation = match sample.augmentation {
+                sampling::Augmentation::Identity => Some(rotation.to_string()),
+                augmentation => Some(format!("{}+{}", augmentation, rotation)),
+            };
+            rows.entries.push(row);
         }
     }
 
-    Ok((training_data, training_labels))
+    Ok((training_data, training_labels, rows, failures))
 }
 
 fn get_data_and_labels_orig(
@@ -254,8 +353,8 @@ fn get_data_and_labels_orig(
 
             let img = image::open(image_path.as_path()).unwrap();
 
-            let img = rusty_herbarium::preprocessing_step_1(img);
-            let img = rusty_herbarium::preprocessing_step_2(img);
+            let img = rusty_herbarium::preprocessing_step_1(img).unwrap();
+            let img = rusty_herbarium::preprocessing_step_2(img).unwrap();
             // let img = preprocessing_step_3(img);
             // let img = preprocessing_step_4(img);
 
@@ -0,0 +1,97 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::manifest;
+use rusty_herbarium::split;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "split_train_data", about = "seeded stratified train/validation/test split of the train metadata")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "o", long = "output", long_help = "split output file", required = true, parse(from_os_str))]
+    output: path::PathBuf,
+
+    #[structopt(short = "f", long = "manifest_output", long_help = "also write a csv manifest of every image in the split", parse(from_os_str))]
+    manifest_output: Option<path::PathBuf>,
+
+    #[structopt(short = "e", long = "seed", long_help = "seed", default_value = "0")]
+    seed: u64,
+
+    #[structopt(
+        short = "z",
+        long = "sizes",
+        long_help = "train,validation,test per class counts (10,1,0) or ratios (0.8,0.1,0.1)",
+        default_value = "0.8,0.1,0.1"
+    )]
+    sizes: split::SplitSizes,
+
+    #[structopt(short = "m", long = "min_per_class", long_help = "minimum training images per class", default_value = "1")]
+    min_per_class: usize,
+
+    #[structopt(short = "r", long = "stratify_by_region", long_help = "stratify by region as well as by category")]
+    stratify_by_region: bool,
+
+    #[structopt(short = "c", long = "category_limit", long_help = "category limit", default_value = "0")]
+    category_limit: usize,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let mut train_metadata_path = options.base_dir.clone();
+    train_metadata_path.push("train");
+    train_metadata_path.push("metadata.json");
+
+    let train_metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
+    let catalog = rusty_herbarium::HerbariumCatalog::new(train_metadata);
+
+    let split_config = split::SplitConfig {
+        seed: options.seed,
+        sizes: options.sizes.clone(),
+        min_per_class: options.min_per_class,
+        stratify_by_region: options.stratify_by_region,
+        category_limit: options.category_limit,
+    };
+    let split = split::stratified_split(&catalog, &split_config);
+    info!(
+        "train: {}, validation: {}, test: {}, skipped categories: {}",
+        split.train.len(),
+        split.validation.len(),
+        split.test.len(),
+        split.skipped_category_ids.len()
+    );
+
+    info!("writing: {}", options.output.to_string_lossy());
+    split.write(options.output.as_path())?;
+
+    if let Some(ref manifest_output) = options.manifest_output {
+        let manifest = manifest::Manifest::from_split(&catalog, &split);
+        info!("writing: {}", manifest_output.to_string_lossy());
+        manifest.write(manifest_output.as_path())?;
+    }
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    Ok(())
+}
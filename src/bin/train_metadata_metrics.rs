@@ -9,10 +9,9 @@ extern crate serde_derive;
 extern crate structopt;
 
 use humantime::format_duration;
-use itertools::Itertools;
 use log::Level;
-use std::collections;
-use std::fs;
+use rusty_herbarium::stream::MetadataCounts;
+use rusty_herbarium::taxonomy::Taxonomy;
 use std::io;
 use std::path;
 use std::str::FromStr;
@@ -25,6 +24,9 @@ struct Options {
     #[structopt(short = "i", long = "metadata_path", long_help = "metadata path", required = true, parse(from_os_str))]
     metadata_path: path::PathBuf,
 
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "Info")]
     log_level: String,
 }
@@ -36,42 +38,38 @@ fn main() -> io::Result<()> {
     simple_logger::init_with_level(log_level).unwrap();
     debug!("{:?}", options);
 
-    let train_metadata_file = fs::File::open(options.metadata_path.as_path()).expect("missing file");
-    let br = io::BufReader::new(train_metadata_file);
-
-    let metadata: rusty_herbarium::TrainMetadata = serde_json::from_reader(br)?;
-    //info!("metadata.info.year: {}", metadata.info.year);
-
-    let categories_by_region_map: collections::HashMap<i32, Vec<i32>> = metadata.annotations.iter().map(|x| (x.region_id, x.category_id)).into_group_map();
-    let mut categories_by_region_map_keys: Vec<_> = categories_by_region_map.keys().collect();
-    categories_by_region_map_keys.sort();
+    // only the counts are kept while the metadata is streamed, the annotations & images are never all in memory
+    let mut counts = MetadataCounts::default();
+    let version = rusty_herbarium::metadata::stream_metadata_file(options.metadata_path.as_path(), options.metadata_version, &mut counts)?;
+    info!("version: {}, images: {}, annotations: {}", version, counts.image_count, counts.annotation_count);
 
-    info!("region count: {}", categories_by_region_map_keys.len());
-    for k in categories_by_region_map_keys.iter() {
-        info!("region: {}, categories count: {}", k, categories_by_region_map.get(k).unwrap().len());
+    let region_ids = counts.annotated_region_ids();
+    info!("region count: {}", region_ids.len());
+    for region_id in region_ids.iter() {
+        info!("region: {}, categories count: {}", region_id, counts.category_count_for_region(*region_id));
     }
 
-    let mut images_by_family_map: collections::HashMap<String, Vec<i32>> = collections::HashMap::new();
-    for annotation in metadata.annotations.iter() {
-        let category = metadata.categories.iter().find(|e| e.id == annotation.category_id).unwrap();
-        images_by_family_map.entry(category.family.clone()).or_insert(Vec::new()).push(annotation.image_id);
+    let taxonomy = Taxonomy::from_counts(&counts);
+    info!("families count: {}", taxonomy.family_count());
+    for family in taxonomy.families() {
+        info!("family: {}, genera count: {}, images count: {}", family.name, family.genera.len(), family.image_count);
     }
 
-    let mut images_by_family_map_keys: Vec<_> = images_by_family_map.keys().collect();
-    images_by_family_map_keys.sort();
-
-    info!("families count: {}", images_by_family_map_keys.len());
-    for k in images_by_family_map_keys.into_iter() {
-        info!("family: {}, images count: {}", k, images_by_family_map.get(k).unwrap().len());
+    info!("genera count: {}", taxonomy.genus_count());
+    for genus in taxonomy.genera() {
+        info!(
+            "family: {}, genus: {}, categories count: {}, images count: {}",
+            genus.family,
+            genus.name,
+            genus.category_ids.len(),
+            genus.image_count
+        );
     }
 
-    let images_by_category_map: collections::HashMap<i32, Vec<i32>> = metadata.annotations.iter().map(|x| (x.category_id, x.image_id)).into_group_map();
-    let mut images_by_category_map_keys: Vec<_> = images_by_category_map.keys().collect();
-    images_by_category_map_keys.sort();
-    info!("categories count: {}", images_by_category_map_keys.len());
-
-    for k in images_by_category_map_keys.iter() {
-        info!("category: {}, images count: {}", k, images_by_category_map.get(k).unwrap().len());
+    let category_ids = counts.annotated_category_ids();
+    info!("categories count: {}", category_ids.len());
+    for category_id in category_ids.iter() {
+        info!("category: {}, images count: {}", category_id, counts.image_count_for_category(*category_id));
     }
 
     info!("Duration: {}", format_duration(start.elapsed()).to_string());
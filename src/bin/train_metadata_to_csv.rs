@@ -26,6 +26,9 @@ struct Options {
     #[structopt(short = "o", long = "output", long_help = "output", required = true, parse(from_os_str))]
     output: path::PathBuf,
 
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
     #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "Info")]
     log_level: String,
 }
@@ -43,11 +46,9 @@ fn main() -> io::Result<()> {
     let mut train_metadata_path = training_dir.clone();
     train_metadata_path.push("metadata.json");
 
-    let train_metadata_file = fs::File::open(train_metadata_path.as_path()).expect("missing file");
-    let br = io::BufReader::new(train_metadata_file);
-
-    let metadata: rusty_herbarium::TrainMetadata = serde_json::from_reader(br)?;
+    let metadata = rusty_herbarium::metadata::read_train_metadata_cached(train_metadata_path.as_path(), options.metadata_version)?;
     //info!("metadata.info.year: {}", metadata.info.year);
+    let catalog = rusty_herbarium::HerbariumCatalog::new(metadata);
 
     let output_options = fs::OpenOptions::new().write(true).append(false).create(true).open(&options.output.as_path()).unwrap();
 
@@ -66,20 +67,20 @@ fn main() -> io::Result<()> {
         "height",
         "file_name",
     ])?;
-    for annotation in metadata.annotations.iter() {
+    for annotation in catalog.train_metadata().annotations.iter() {
         let mut row = Vec::new();
         row.push(annotation.id.to_string());
 
-        let category = metadata.categories.iter().filter(|e| e.id == annotation.category_id).take(1).nth(0).unwrap();
+        let category = catalog.category(annotation.category_id).unwrap();
         row.push(category.id.to_string());
         row.push(category.family.to_string());
         row.push(category.genus.to_string());
 
-        let region = metadata.regions.iter().filter(|e| e.id == annotation.region_id).take(1).nth(0).unwrap();
+        let region = catalog.region(annotation.region_id).unwrap();
         row.push(region.id.to_string());
         row.push(region.name.to_string());
 
-        let image = metadata.images.iter().filter(|e| e.id == annotation.image_id).take(1).nth(0).unwrap();
+        let image = catalog.image(annotation.image_id).unwrap();
         // let mut image_path = training_dir.clone();
         // image_path.push(image.file_name.clone());
 
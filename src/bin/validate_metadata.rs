@@ -0,0 +1,105 @@
+#[macro_use]
+extern crate log;
+extern crate humantime;
+extern crate image;
+extern crate serde_derive;
+extern crate structopt;
+
+use humantime::format_duration;
+use log::Level;
+use rusty_herbarium::validation;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path;
+use std::process;
+use std::str::FromStr;
+use std::time::Instant;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "validate_metadata", about = "validate train & test metadata against each other and the image files")]
+struct Options {
+    #[structopt(short = "b", long = "base_dir", long_help = "base directory containing train & test", required = true, parse(from_os_str))]
+    base_dir: path::PathBuf,
+
+    #[structopt(short = "o", long = "output", long_help = "json report output file, defaults to stdout", parse(from_os_str))]
+    output: Option<path::PathBuf>,
+
+    #[structopt(short = "s", long = "skip_image_files", long_help = "skip checking the image files")]
+    skip_image_files: bool,
+
+    #[structopt(short = "v", long = "metadata_version", long_help = "metadata version (2020, 2021 or 2022), detected when omitted")]
+    metadata_version: Option<rusty_herbarium::metadata::MetadataVersion>,
+
+    #[structopt(short = "l", long = "log_level", long_help = "log level", default_value = "info")]
+    log_level: String,
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let options = Options::from_args();
+    let log_level = Level::from_str(options.log_level.as_str()).expect("Invalid log level");
+    simple_logger::init_with_level(log_level).unwrap();
+    debug!("{:?}", options);
+
+    let mut report = validation::ValidationReport::default();
+
+    let mut train_dir = options.base_dir.clone();
+    train_dir.push("train");
+
+    let mut train_metadata_path = train_dir.clone();
+    train_metadata_path.push("metadata.json");
+
+    info!("reading: {}", train_metadata_path.to_string_lossy());
+    match rusty_herbarium::metadata::read_train_metadata(train_metadata_path.as_path(), options.metadata_version) {
+        Ok(train_metadata) => {
+            report.train_issues.extend(validation::validate_train_metadata(&train_metadata));
+            if !options.skip_image_files {
+                report.train_issues.extend(validation::validate_image_files(train_dir.as_path(), &train_metadata.images));
+                report.train_images_checked = train_metadata.images.len();
+            }
+        }
+        Err(e) => report.train_issues.push(validation::unparsable_metadata(train_metadata_path.as_path(), e)),
+    }
+
+    let mut test_dir = options.base_dir.clone();
+    test_dir.push("test");
+
+    let mut test_metadata_path = test_dir.clone();
+    test_metadata_path.push("metadata.json");
+
+    info!("reading: {}", test_metadata_path.to_string_lossy());
+    match rusty_herbarium::metadata::read_test_metadata(test_metadata_path.as_path(), options.metadata_version) {
+        Ok(test_metadata) => {
+            report.test_issues.extend(validation::validate_test_metadata(&test_metadata));
+            if !options.skip_image_files {
+                report.test_issues.extend(validation::validate_image_files(test_dir.as_path(), &test_metadata.images));
+                report.test_images_checked = test_metadata.images.len();
+            }
+        }
+        Err(e) => report.test_issues.push(validation::unparsable_metadata(test_metadata_path.as_path(), e)),
+    }
+
+    info!("train issues: {}, test issues: {}", report.train_issues.len(), report.test_issues.len());
+
+    match options.output {
+        Some(output) => {
+            info!("writing: {}", output.to_string_lossy());
+            let writer = io::BufWriter::new(fs::File::create(output.as_path())?);
+            serde_json::to_writer_pretty(writer, &report)?;
+        }
+        None => {
+            let stdout = io::stdout();
+            let mut writer = stdout.lock();
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+    }
+
+    info!("Duration: {}", format_duration(start.elapsed()).to_string());
+    if report.has_errors() {
+        process::exit(1);
+    }
+    Ok(())
+}
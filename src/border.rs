@@ -0,0 +1,265 @@
+use crate::pipeline::RgbaImage;
+use crate::{PreprocessError, PreprocessStep};
+use image::GenericImageView;
+use serde::{Deserialize, Serialize};
+use std::ops;
+use strum_macros::{Display, EnumString};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum BorderDetectionMode {
+    // scan the configured rows & columns for the first bright pixel, what preprocessing_step_1 has always done
+    Fixed,
+    // find where the column & row intensity profiles climb from the scanner background to the sheet
+    Auto,
+}
+
+// ranges & margins are fractions of the image width or height, so the same params work on any scan size
+// the defaults are the pixel ranges step_1 was tuned with on 680x1000 scans & step_4 on the 600x800 images step_1 produces
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BorderDetectionParams {
+    pub mode: BorderDetectionMode,
+    // fixed: the columns scanned for the left & right borders and the rows they are scanned on
+    pub side_columns: (f32, f32),
+    pub side_rows: (f32, f32),
+    // fixed: the rows scanned for the top & bottom borders, counted from their own edge
+    pub top_rows: (f32, f32),
+    pub bottom_rows: (f32, f32),
+    // fixed: a pixel is part of the sheet once every channel is above this
+    pub brightness_threshold: u8,
+    // auto: how far in from each edge the sheet edge is looked for
+    pub max_border: f32,
+    // auto: how much brighter than the outermost column or row the sheet has to be for there to be a border at all
+    pub min_contrast: f32,
+    // step_4: the margins where gray pixels are blanked, gray meaning the channels are within gray_tolerance of each other
+    pub gray_margin_x: f32,
+    pub gray_margin_y: f32,
+    pub gray_tolerance: u8,
+    // step_4: where the blanking color is sampled
+    pub background_sample: (f32, f32),
+}
+
+impl Default for BorderDetectionParams {
+    fn default() -> BorderDetectionParams {
+        BorderDetectionParams {
+            mode: BorderDetectionMode::Fixed,
+            side_columns: (0.0147, 0.0588),
+            side_rows: (0.1, 0.9),
+            top_rows: (0.02, 0.1),
+            bottom_rows: (0.01, 0.05),
+            brightness_threshold: 100,
+            max_border: 0.15,
+            min_contrast: 40.0,
+            gray_margin_x: 0.0917,
+            gray_margin_y: 0.05,
+            gray_tolerance: 10,
+            background_sample: (0.0067, 0.05),
+        }
+    }
+}
+
+// pixels to crop off each side
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Borders {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+fn scaled(fraction: f32, size: u32) -> u32 {
+    ((fraction.max(0.0) * size as f32).round() as u32).min(size)
+}
+
+fn span(fractions: (f32, f32), size: u32) -> ops::Range<u32> {
+    scaled(fractions.0, size)..scaled(fractions.1, size)
+}
+
+fn is_bright(pixel: image::Rgba<u8>, threshold: u8) -> bool {
+    pixel[0] > threshold && pixel[1] > threshold && pixel[2] > threshold
+}
+
+fn intensity(pixel: image::Rgba<u8>) -> f32 {
+    pixel[0].min(pixel[1]).min(pixel[2]) as f32
+}
+
+fn leaves_nothing(borders: &Borders, width: u32, height: u32) -> bool {
+    borders.left + borders.right >= width || borders.top + borders.bottom >= height
+}
+
+fn crop_too_large(borders: &Borders, width: u32, height: u32) -> PreprocessError {
+    PreprocessError::CropTooLarge {
+        step: PreprocessStep::Step1,
+        width,
+        height,
+        from_left: borders.left,
+        from_right: borders.right,
+        from_top: borders.top,
+        from_bottom: borders.bottom,
+    }
+}
+
+// the first bright pixel is cropped along with the border, a scan that never gets out of the border crops the whole scanned range
+fn scan<F: Fn(u32) -> image::Rgba<u8>>(mut range: ops::Range<u32>, threshold: u8, pixel: F) -> u32 {
+    let last = range.end - 1;
+    range.find(|e| is_bright(pixel(*e), threshold)).unwrap_or(last) + 1
+}
+
+// left & right are the furthest in any of the scanned rows reaches, top & bottom are scanned down the first column inside the left border
+fn detect_fixed<I: GenericImageView<Pixel = image::Rgba<u8>>>(img: &I, params: &BorderDetectionParams) -> Result<Borders, PreprocessError> {
+    let (width, height) = img.dimensions();
+    let threshold = params.brightness_threshold;
+    let not_found = |border: &'static str| PreprocessError::BorderNotFound {
+        step: PreprocessStep::Step1,
+        border,
+    };
+
+    let columns = span(params.side_columns, width);
+    let rows = span(params.side_rows, height);
+    if columns.start >= columns.end || rows.start >= rows.end {
+        return Err(not_found("left"));
+    }
+    let left = rows.clone().map(|y| scan(columns.clone(), threshold, |x| img.get_pixel(x, y))).max().unwrap();
+    let right = rows.clone().map(|y| scan(columns.clone(), threshold, |x| img.get_pixel(width - 1 - x, y))).max().unwrap();
+
+    let mut borders = Borders { left, right, top: 0, bottom: 0 };
+    if leaves_nothing(&borders, width, height) {
+        return Err(crop_too_large(&borders, width, height));
+    }
+
+    let top_rows = span(params.top_rows, height);
+    if top_rows.start >= top_rows.end {
+        return Err(not_found("top"));
+    }
+    borders.top = scan(top_rows, threshold, |y| img.get_pixel(left, y));
+
+    let bottom_rows = span(params.bottom_rows, height);
+    if bottom_rows.start >= bottom_rows.end {
+        return Err(not_found("bottom"));
+    }
+    borders.bottom = scan(bottom_rows, threshold, |y| img.get_pixel(left, height - 1 - y));
+
+    debug!("fixed borders: {:?}", borders);
+    Ok(borders)
+}
+
+// how far into the profile the sheet starts, 0 when the outermost value is already close to the sheet's
+fn sheet_edge<'a, P: Iterator<Item = &'a f32> + Clone>(profile: P, params: &BorderDetectionParams) -> u32 {
+    let mut values: Vec<f32> = profile.clone().cloned().collect();
+    let len = values.len();
+    if len < 4 {
+        return 0;
+    }
+    let background = values[0];
+    // the middle half is taken to be sheet
+    let mut middle = values.drain(len / 4..len - len / 4).collect::<Vec<f32>>();
+    middle.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let sheet = middle[middle.len() / 2];
+    if sheet - background < params.min_contrast {
+        return 0;
+    }
+
+    let threshold = (background + sheet) / 2.0;
+    let search = scaled(params.max_border, len as u32) as usize;
+    profile.take(search).position(|e| *e >= threshold).unwrap_or(search) as u32
+}
+
+fn detect_auto<I: GenericImageView<Pixel = image::Rgba<u8>>>(img: &I, params: &BorderDetectionParams) -> Result<Borders, PreprocessError> {
+    let (width, height) = img.dimensions();
+
+    let rows = span(params.side_rows, height);
+    let row_count = rows.end.saturating_sub(rows.start).max(1) as f32;
+    let column_profile: Vec<f32> = (0..width).map(|x| rows.clone().map(|y| intensity(img.get_pixel(x, y))).sum::<f32>() / row_count).collect();
+    let left = sheet_edge(column_profile.iter(), params);
+    let right = sheet_edge(column_profile.iter().rev(), params);
+
+    let mut borders = Borders { left, right, top: 0, bottom: 0 };
+    if leaves_nothing(&borders, width, height) {
+        return Err(crop_too_large(&borders, width, height));
+    }
+
+    // only what's between the side borders counts for the rows
+    let columns = left..(width - right);
+    let column_count = (columns.end - columns.start) as f32;
+    let row_profile: Vec<f32> = (0..height)
+        .map(|y| columns.clone().map(|x| intensity(img.get_pixel(x, y))).sum::<f32>() / column_count)
+        .collect();
+    borders.top = sheet_edge(row_profile.iter(), params);
+    borders.bottom = sheet_edge(row_profile.iter().rev(), params);
+
+    debug!("auto borders: {:?}", borders);
+    Ok(borders)
+}
+
+pub fn detect_borders<I: GenericImageView<Pixel = image::Rgba<u8>>>(img: &I, params: &BorderDetectionParams) -> Result<Borders, PreprocessError> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Err(PreprocessError::ImageTooSmall {
+            step: PreprocessStep::Step1,
+            width,
+            height,
+            min_width: 1,
+            min_height: 1,
+        });
+    }
+    let borders = match params.mode {
+        BorderDetectionMode::Fixed => detect_fixed(img, params)?,
+        BorderDetectionMode::Auto => detect_auto(img, params)?,
+    };
+    if leaves_nothing(&borders, width, height) {
+        return Err(crop_too_large(&borders, width, height));
+    }
+    Ok(borders)
+}
+
+// preprocessing_step_1, crops the detected borders and resizes to the 600x800 the later steps expect
+pub fn remove_borders(mut img: image::DynamicImage, params: &BorderDetectionParams) -> Result<RgbaImage, PreprocessError> {
+    let borders = detect_borders(&img, params)?;
+    let (width, height) = img.dimensions();
+    let img = image::imageops::crop(
+        &mut img,
+        borders.left,
+        borders.top,
+        width - borders.left - borders.right,
+        height - borders.top - borders.bottom,
+    )
+    .to_image();
+    Ok(image::imageops::resize(&img, 600, 800, image::imageops::FilterType::CatmullRom))
+}
+
+// preprocessing_step_4, blanks the gray pixels in the margins with the color sampled from the background
+pub fn blank_gray_borders(mut img: RgbaImage, params: &BorderDetectionParams) -> Result<RgbaImage, PreprocessError> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Err(PreprocessError::ImageTooSmall {
+            step: PreprocessStep::Step4,
+            width,
+            height,
+            min_width: 1,
+            min_height: 1,
+        });
+    }
+    let margin_x = scaled(params.gray_margin_x, width);
+    let margin_y = scaled(params.gray_margin_y, height);
+    let replacement_pixel = *img.get_pixel(
+        scaled(params.background_sample.0, width).min(width - 1),
+        scaled(params.background_sample.1, height).min(height - 1),
+    );
+
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let in_margin = x < margin_x || x >= width.saturating_sub(margin_x) || y < margin_y || y >= height.saturating_sub(margin_y);
+        if !in_margin {
+            continue;
+        }
+        let min = pixel[0].min(pixel[1]).min(pixel[2]);
+        let max = pixel[0].max(pixel[1]).max(pixel[2]);
+        if (max - min) <= params.gray_tolerance {
+            pixel[0] = replacement_pixel[0];
+            pixel[1] = replacement_pixel[1];
+            pixel[2] = replacement_pixel[2];
+        }
+    }
+    Ok(img)
+}
@@ -0,0 +1,254 @@
+use crate::pipeline::RgbaImage;
+use crate::region;
+use crate::region::{BoundingBox, Mask};
+use serde::{Deserialize, Serialize};
+
+// sizes are fractions of the image width (areas of the image area), so the params carry over between scan sizes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CalibrationParams {
+    // color bars: saturated, nearly rectangular patches that together cover several hues
+    pub min_saturation: f32,
+    pub min_value: f32,
+    pub min_patch_area: f32,
+    pub min_patch_fill: f32,
+    // patches closer than this are taken to be part of the same bar
+    pub patch_gap: f32,
+    // how many 30 degree hue bins need at least min_hue_share of a bar's pixels
+    pub min_hues: usize,
+    pub min_hue_share: f32,
+    // rulers: runs of narrow ticks at a regular spacing, on at least min_ruler_lines neighboring rows (or columns)
+    pub ink_threshold: u8,
+    pub max_tick_width: f32,
+    pub max_tick_spacing: f32,
+    pub min_ticks: usize,
+    pub spacing_tolerance: f32,
+    pub min_ruler_lines: u32,
+    // added around every box before masking
+    pub padding: f32,
+}
+
+impl Default for CalibrationParams {
+    fn default() -> CalibrationParams {
+        CalibrationParams {
+            min_saturation: 0.45,
+            min_value: 0.3,
+            min_patch_area: 0.0002,
+            min_patch_fill: 0.7,
+            patch_gap: 0.01,
+            min_hues: 3,
+            min_hue_share: 0.05,
+            ink_threshold: 110,
+            max_tick_width: 0.006,
+            max_tick_spacing: 0.05,
+            min_ticks: 8,
+            spacing_tolerance: 0.25,
+            min_ruler_lines: 3,
+            padding: 0.01,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CalibrationTargets {
+    pub color_bars: Vec<BoundingBox>,
+    pub rulers: Vec<BoundingBox>,
+}
+
+impl CalibrationTargets {
+    pub fn boxes(&self) -> Vec<BoundingBox> {
+        self.color_bars.iter().chain(self.rulers.iter()).cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.color_bars.is_empty() && self.rulers.is_empty()
+    }
+}
+
+fn fraction_of(fraction: f32, size: u32) -> u32 {
+    (fraction * size as f32).round().max(1.0) as u32
+}
+
+struct Patch {
+    bbox: BoundingBox,
+    hues: [u32; 12],
+    area: u32,
+}
+
+pub fn detect_color_bars(img: &RgbaImage, params: &CalibrationParams) -> Vec<BoundingBox> {
+    let (width, height) = img.dimensions();
+    let mask = Mask::from_fn(width, height, |x, y| {
+        let (_, saturation, value) = region::rgb_to_hsv(*img.get_pixel(x, y));
+        saturation >= params.min_saturation && value >= params.min_value
+    });
+    let components = region::connected_components(&mask);
+    let min_area = (params.min_patch_area * (width * height) as f32) as u32;
+
+    let mut patches: Vec<Patch> = Vec::new();
+    for component in components.components.iter() {
+        if component.area < min_area || (component.area as f32) < params.min_patch_fill * component.bbox.area() as f32 {
+            continue;
+        }
+        let mut hues = [0u32; 12];
+        for (x, y) in components.pixels(component).into_iter() {
+            let (hue, _, _) = region::rgb_to_hsv(*img.get_pixel(x, y));
+            hues[(hue / 30.0) as usize % 12] += 1;
+        }
+        patches.push(Patch {
+            bbox: component.bbox,
+            hues,
+            area: component.area,
+        });
+    }
+
+    // patches separated by thin gaps (the usual checker layout) are grouped into one bar
+    let gap = fraction_of(params.patch_gap, width);
+    let mut grouped = true;
+    while grouped {
+        grouped = false;
+        let mut groups: Vec<Patch> = Vec::with_capacity(patches.len());
+        for patch in patches.into_iter() {
+            let reach = patch.bbox.padded(gap, width, height);
+            match groups.iter_mut().find(|e| e.bbox.padded(gap, width, height).intersects(&reach)) {
+                Some(group) => {
+                    group.bbox = group.bbox.union(&patch.bbox);
+                    group.area += patch.area;
+                    for (idx, count) in patch.hues.iter().enumerate() {
+                        group.hues[idx] += count;
+                    }
+                    grouped = true;
+                }
+                None => groups.push(patch),
+            }
+        }
+        patches = groups;
+    }
+
+    let padding = fraction_of(params.padding, width);
+    let bars = patches
+        .into_iter()
+        .filter(|e| e.hues.iter().filter(|count| **count as f32 >= params.min_hue_share * e.area as f32).count() >= params.min_hues)
+        .map(|e| e.bbox.padded(padding, width, height))
+        .collect();
+    region::merge_overlapping(bars)
+}
+
+// (start, end) of every stretch of a line covered by regularly spaced narrow ink runs
+fn tick_stretches(ink: &[bool], params: &CalibrationParams, max_tick_width: u32, max_spacing: u32) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    let mut start = None;
+    for (idx, is_ink) in ink.iter().chain([false].iter()).enumerate() {
+        match (start, *is_ink) {
+            (None, true) => start = Some(idx as u32),
+            (Some(run_start), false) => {
+                runs.push((run_start, idx as u32));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let mut stretches = Vec::new();
+    let mut ticks: Vec<(u32, u32)> = Vec::new();
+    let mut close = |ticks: &mut Vec<(u32, u32)>| {
+        if ticks.len() >= params.min_ticks {
+            stretches.push((ticks[0].0, ticks[ticks.len() - 1].1));
+        }
+        ticks.clear();
+    };
+    for run in runs.into_iter() {
+        if run.1 - run.0 > max_tick_width {
+            close(&mut ticks);
+            continue;
+        }
+        if let Some(last) = ticks.last() {
+            let spacing = run.0 - last.0;
+            let regular = match ticks.len() {
+                1 => spacing <= max_spacing,
+                _ => {
+                    let expected = (ticks[1].0 - ticks[0].0) as f32;
+                    (spacing as f32 - expected).abs() <= params.spacing_tolerance * expected + 1.0
+                }
+            };
+            if !regular {
+                close(&mut ticks);
+            }
+        }
+        ticks.push(run);
+    }
+    close(&mut ticks);
+    stretches
+}
+
+// keeps the components of the marked stretches that run over enough neighboring lines
+fn ruler_boxes(mask: &Mask, min_lines: u32, horizontal: bool) -> Vec<BoundingBox> {
+    region::connected_components(mask)
+        .components
+        .into_iter()
+        .filter(|e| if horizontal { e.bbox.height >= min_lines } else { e.bbox.width >= min_lines })
+        .map(|e| e.bbox)
+        .collect()
+}
+
+// scans rows for horizontal rulers & columns for vertical ones, dark ticks on a light ruler as well as light ticks on a dark one
+pub fn detect_rulers(img: &RgbaImage, params: &CalibrationParams) -> Vec<BoundingBox> {
+    let (width, height) = img.dimensions();
+    let max_tick_width = fraction_of(params.max_tick_width, width);
+    let max_spacing = fraction_of(params.max_tick_spacing, width);
+    let ink: Vec<bool> = img
+        .pixels()
+        .map(|e| ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 3) < params.ink_threshold as u32)
+        .collect();
+
+    let mut boxes = Vec::new();
+    for dark_ticks in [true, false].iter() {
+        let is_tick = |x: u32, y: u32| ink[(y * width + x) as usize] == *dark_ticks;
+
+        let mut rows = Mask::new(width, height);
+        for y in 0..height {
+            let line: Vec<bool> = (0..width).map(|x| is_tick(x, y)).collect();
+            for (start, end) in tick_stretches(&line, params, max_tick_width, max_spacing).into_iter() {
+                for x in start..end {
+                    rows.set(x, y, true);
+                }
+            }
+        }
+        boxes.extend(ruler_boxes(&rows, params.min_ruler_lines, true));
+
+        let mut columns = Mask::new(width, height);
+        for x in 0..width {
+            let line: Vec<bool> = (0..height).map(|y| is_tick(x, y)).collect();
+            for (start, end) in tick_stretches(&line, params, max_tick_width, max_spacing).into_iter() {
+                for y in start..end {
+                    columns.set(x, y, true);
+                }
+            }
+        }
+        boxes.extend(ruler_boxes(&columns, params.min_ruler_lines, false));
+    }
+
+    let padding = fraction_of(params.padding, width);
+    region::merge_overlapping(boxes.into_iter().map(|e| e.padded(padding, width, height)).collect())
+}
+
+pub fn detect_calibration_targets(img: &RgbaImage, params: &CalibrationParams) -> CalibrationTargets {
+    let targets = CalibrationTargets {
+        color_bars: detect_color_bars(img, params),
+        rulers: detect_rulers(img, params),
+    };
+    debug!("calibration targets: {:?}", targets);
+    targets
+}
+
+// paints the color bars & rulers over with the paper color
+pub fn mask_calibration_targets(mut img: RgbaImage, params: &CalibrationParams) -> RgbaImage {
+    let targets = detect_calibration_targets(&img, params);
+    if targets.is_empty() {
+        return img;
+    }
+    let background = region::background_color(&img);
+    for bbox in targets.boxes().iter() {
+        region::fill(&mut img, bbox, background);
+    }
+    img
+}
@@ -0,0 +1,178 @@
+use crate::{Annotation, Category, Image, License, Region, TestMetadata, TrainMetadata};
+use std::collections;
+use std::path;
+
+// id keyed view over the herbarium metadata
+// the raw metadata keeps everything in flat vectors, so resolving an annotation with iter().find() is quadratic over ~1M annotations
+pub struct HerbariumCatalog {
+    train_metadata: TrainMetadata,
+    test_metadata: Option<TestMetadata>,
+    image_idx_by_id: collections::HashMap<i32, usize>,
+    test_image_idx_by_id: collections::HashMap<i32, usize>,
+    category_idx_by_id: collections::HashMap<i32, usize>,
+    region_idx_by_id: collections::HashMap<i32, usize>,
+    license_idx_by_id: collections::HashMap<i32, usize>,
+    annotation_idxs_by_image: collections::HashMap<i32, Vec<usize>>,
+    image_ids_by_category: collections::HashMap<i32, Vec<i32>>,
+    image_ids_by_region_and_category: collections::HashMap<(i32, i32), Vec<i32>>,
+    category_ids_by_region: collections::HashMap<i32, Vec<i32>>,
+    category_ids_by_family: collections::BTreeMap<String, Vec<i32>>,
+    category_ids_by_genus: collections::BTreeMap<String, Vec<i32>>,
+}
+
+impl HerbariumCatalog {
+    pub fn new(train_metadata: TrainMetadata) -> HerbariumCatalog {
+        let image_idx_by_id = train_metadata.images.iter().enumerate().map(|(idx, e)| (e.id, idx)).collect();
+        let category_idx_by_id = train_metadata.categories.iter().enumerate().map(|(idx, e)| (e.id, idx)).collect();
+        let region_idx_by_id = train_metadata.regions.iter().enumerate().map(|(idx, e)| (e.id, idx)).collect();
+        let license_idx_by_id = train_metadata.licenses.iter().enumerate().map(|(idx, e)| (e.id, idx)).collect();
+
+        let mut annotation_idxs_by_image: collections::HashMap<i32, Vec<usize>> = collections::HashMap::new();
+        let mut image_ids_by_category: collections::HashMap<i32, Vec<i32>> = collections::HashMap::new();
+        let mut image_ids_by_region_and_category: collections::HashMap<(i32, i32), Vec<i32>> = collections::HashMap::new();
+        let mut category_ids_by_region: collections::HashMap<i32, Vec<i32>> = collections::HashMap::new();
+
+        for (idx, annotation) in train_metadata.annotations.iter().enumerate() {
+            annotation_idxs_by_image.entry(annotation.image_id).or_insert(Vec::new()).push(idx);
+            image_ids_by_category.entry(annotation.category_id).or_insert(Vec::new()).push(annotation.image_id);
+            image_ids_by_region_and_category
+                .entry((annotation.region_id, annotation.category_id))
+                .or_insert(Vec::new())
+                .push(annotation.image_id);
+            category_ids_by_region.entry(annotation.region_id).or_insert(Vec::new()).push(annotation.category_id);
+        }
+
+        for category_ids in category_ids_by_region.values_mut() {
+            category_ids.sort();
+            category_ids.dedup();
+        }
+
+        let mut category_ids_by_family: collections::BTreeMap<String, Vec<i32>> = collections::BTreeMap::new();
+        let mut category_ids_by_genus: collections::BTreeMap<String, Vec<i32>> = collections::BTreeMap::new();
+        for category in train_metadata.categories.iter() {
+            category_ids_by_family.entry(category.family.clone()).or_insert(Vec::new()).push(category.id);
+            category_ids_by_genus.entry(category.genus.clone()).or_insert(Vec::new()).push(category.id);
+        }
+
+        for category_ids in category_ids_by_family.values_mut().chain(category_ids_by_genus.values_mut()) {
+            category_ids.sort();
+        }
+
+        HerbariumCatalog {
+            train_metadata,
+            test_metadata: None,
+            image_idx_by_id,
+            test_image_idx_by_id: collections::HashMap::new(),
+            category_idx_by_id,
+            region_idx_by_id,
+            license_idx_by_id,
+            annotation_idxs_by_image,
+            image_ids_by_category,
+            image_ids_by_region_and_category,
+            category_ids_by_region,
+            category_ids_by_family,
+            category_ids_by_genus,
+        }
+    }
+
+    pub fn with_test_metadata(mut self, test_metadata: TestMetadata) -> HerbariumCatalog {
+        self.test_image_idx_by_id = test_metadata.images.iter().enumerate().map(|(idx, e)| (e.id, idx)).collect();
+        self.test_metadata = Some(test_metadata);
+        self
+    }
+
+    pub fn train_metadata(&self) -> &TrainMetadata {
+        &self.train_metadata
+    }
+
+    pub fn test_metadata(&self) -> Option<&TestMetadata> {
+        self.test_metadata.as_ref()
+    }
+
+    pub fn image(&self, image_id: i32) -> Option<&Image> {
+        self.image_idx_by_id.get(&image_id).map(|idx| &self.train_metadata.images[*idx])
+    }
+
+    pub fn test_image(&self, image_id: i32) -> Option<&Image> {
+        match (self.test_metadata.as_ref(), self.test_image_idx_by_id.get(&image_id)) {
+            (Some(test_metadata), Some(idx)) => Some(&test_metadata.images[*idx]),
+            _ => None,
+        }
+    }
+
+    pub fn category(&self, category_id: i32) -> Option<&Category> {
+        self.category_idx_by_id.get(&category_id).map(|idx| &self.train_metadata.categories[*idx])
+    }
+
+    pub fn region(&self, region_id: i32) -> Option<&Region> {
+        self.region_idx_by_id.get(&region_id).map(|idx| &self.train_metadata.regions[*idx])
+    }
+
+    pub fn license(&self, license_id: i32) -> Option<&License> {
+        self.license_idx_by_id.get(&license_id).map(|idx| &self.train_metadata.licenses[*idx])
+    }
+
+    pub fn annotations_for_image(&self, image_id: i32) -> Vec<&Annotation> {
+        match self.annotation_idxs_by_image.get(&image_id) {
+            Some(idxs) => idxs.iter().map(|idx| &self.train_metadata.annotations[*idx]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // image ids in annotation order
+    pub fn image_ids_for_category(&self, category_id: i32) -> &[i32] {
+        self.image_ids_by_category.get(&category_id).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn image_ids_for_region_and_category(&self, region_id: i32, category_id: i32) -> &[i32] {
+        self.image_ids_by_region_and_category.get(&(region_id, category_id)).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn category_ids_for_region(&self, region_id: i32) -> &[i32] {
+        self.category_ids_by_region.get(&region_id).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn category_ids_for_family(&self, family: &str) -> &[i32] {
+        self.category_ids_by_family.get(family).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn category_ids_for_genus(&self, genus: &str) -> &[i32] {
+        self.category_ids_by_genus.get(genus).map(|e| e.as_slice()).unwrap_or(&[])
+    }
+
+    // sorted ids of the categories that have at least one annotation
+    pub fn annotated_category_ids(&self) -> Vec<i32> {
+        let mut category_ids: Vec<i32> = self.image_ids_by_category.keys().cloned().collect();
+        category_ids.sort();
+        category_ids
+    }
+
+    // sorted ids of the regions that have at least one annotation
+    pub fn annotated_region_ids(&self) -> Vec<i32> {
+        let mut region_ids: Vec<i32> = self.category_ids_by_region.keys().cloned().collect();
+        region_ids.sort();
+        region_ids
+    }
+
+    pub fn region_and_category_ids(&self) -> Vec<(i32, i32)> {
+        let mut region_and_category_ids: Vec<(i32, i32)> = self.image_ids_by_region_and_category.keys().cloned().collect();
+        region_and_category_ids.sort();
+        region_and_category_ids
+    }
+
+    pub fn families(&self) -> Vec<&str> {
+        self.category_ids_by_family.keys().map(|e| e.as_str()).collect()
+    }
+
+    pub fn genera(&self) -> Vec<&str> {
+        self.category_ids_by_genus.keys().map(|e| e.as_str()).collect()
+    }
+
+    pub fn image_path(&self, train_dir: &path::Path, image_id: i32) -> Option<path::PathBuf> {
+        self.image(image_id).map(|image| train_dir.join(&image.file_name))
+    }
+
+    pub fn test_image_path(&self, test_dir: &path::Path, image_id: i32) -> Option<path::PathBuf> {
+        self.test_image(image_id).map(|image| test_dir.join(&image.file_name))
+    }
+}
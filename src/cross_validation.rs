@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+
+// what a model binary writes with --metrics_output, keyed by metric name so the runner doesn't need to know which model produced it
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ModelMetrics {
+    pub model: String,
+    pub metrics: collections::BTreeMap<String, f64>,
+}
+
+impl ModelMetrics {
+    pub fn new(model: &str) -> ModelMetrics {
+        ModelMetrics {
+            model: model.to_string(),
+            metrics: collections::BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, value: f64) {
+        self.metrics.insert(name.to_string(), value);
+    }
+
+    pub fn read(metrics_path: &path::Path) -> io::Result<ModelMetrics> {
+        let metrics_file = fs::File::open(metrics_path)?;
+        let metrics = serde_json::from_reader(io::BufReader::new(metrics_file))?;
+        Ok(metrics)
+    }
+
+    pub fn write(&self, metrics_path: &path::Path) -> io::Result<()> {
+        let metrics_file = fs::File::create(metrics_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(metrics_file), self)?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricSummary {
+    pub mean: f64,
+    pub standard_deviation: f64,
+    pub values: Vec<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrossValidationReport {
+    pub model: String,
+    pub folds: Vec<ModelMetrics>,
+    pub summary: collections::BTreeMap<String, MetricSummary>,
+}
+
+impl CrossValidationReport {
+    pub fn new(model: &str, folds: Vec<ModelMetrics>) -> CrossValidationReport {
+        let summary = summarize(&folds);
+        CrossValidationReport {
+            model: model.to_string(),
+            folds,
+            summary,
+        }
+    }
+
+    pub fn write(&self, report_path: &path::Path) -> io::Result<()> {
+        let report_file = fs::File::create(report_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(report_file), self)?;
+        Ok(())
+    }
+}
+
+// sample standard deviation across folds, a single fold reports 0
+pub fn summarize(folds: &[ModelMetrics]) -> collections::BTreeMap<String, MetricSummary> {
+    let mut values_by_name: collections::BTreeMap<String, Vec<f64>> = collections::BTreeMap::new();
+    for fold in folds.iter() {
+        for (name, value) in fold.metrics.iter() {
+            values_by_name.entry(name.clone()).or_insert_with(Vec::new).push(*value);
+        }
+    }
+
+    values_by_name
+        .into_iter()
+        .map(|(name, values)| {
+            let mean = statistical::mean(&values);
+            let standard_deviation = if values.len() > 1 { statistical::standard_deviation(&values, Some(mean)) } else { 0.0 };
+            (name, MetricSummary { mean, standard_deviation, values })
+        })
+        .collect()
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fs;
+use std::io;
+use std::iter;
+use std::path;
+
+// maps the (sparse) category ids onto 0..class_count, which is what juice's NegativeLogLikelihood and the confusion matrix expect
+// class indices follow the sorted category ids, so the same set of categories always encodes the same way
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LabelEncoder {
+    category_ids: Vec<i32>,
+    #[serde(skip)]
+    index_by_category_id: collections::HashMap<i32, usize>,
+}
+
+impl iter::FromIterator<i32> for LabelEncoder {
+    fn from_iter<I: IntoIterator<Item = i32>>(category_ids: I) -> LabelEncoder {
+        let mut category_ids: Vec<i32> = category_ids.into_iter().collect();
+        category_ids.sort();
+        category_ids.dedup();
+        LabelEncoder::from_sorted(category_ids)
+    }
+}
+
+impl LabelEncoder {
+    fn from_sorted(category_ids: Vec<i32>) -> LabelEncoder {
+        let index_by_category_id = category_ids.iter().enumerate().map(|(idx, e)| (*e, idx)).collect();
+        LabelEncoder {
+            category_ids,
+            index_by_category_id,
+        }
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.category_ids.len()
+    }
+
+    pub fn category_ids(&self) -> &[i32] {
+        &self.category_ids
+    }
+
+    pub fn encode(&self, category_id: i32) -> Option<usize> {
+        self.index_by_category_id.get(&category_id).cloned()
+    }
+
+    pub fn decode(&self, class_index: usize) -> Option<i32> {
+        self.category_ids.get(class_index).cloned()
+    }
+
+    // labels & predictions travel as f32 through the serialized data and the models
+    pub fn encode_label(&self, category_id: i32) -> Option<f32> {
+        self.encode(category_id).map(|e| e as f32)
+    }
+
+    pub fn decode_label(&self, label: f32) -> Option<i32> {
+        if label < 0.0 {
+            return None;
+        }
+        self.decode(label.round() as usize)
+    }
+
+    pub fn file_name(width: u32, height: u32) -> String {
+        format!("herbarium-label-encoder-{}x{}.json", width, height)
+    }
+
+    pub fn read(encoder_path: &path::Path) -> io::Result<LabelEncoder> {
+        let encoder_file = fs::File::open(encoder_path)?;
+        let encoder: LabelEncoder = serde_json::from_reader(io::BufReader::new(encoder_file))?;
+        Ok(LabelEncoder::from_sorted(encoder.category_ids))
+    }
+
+    pub fn write(&self, encoder_path: &path::Path) -> io::Result<()> {
+        let encoder_file = fs::File::create(encoder_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(encoder_file), self)?;
+        Ok(())
+    }
+}
+
+// reads the encoder written next to the serialized data
+pub fn read_label_encoder(serialization_dir: &path::Path, width: u32, height: u32) -> io::Result<LabelEncoder> {
+    let mut encoder_path = serialization_dir.to_path_buf();
+    encoder_path.push(LabelEncoder::file_name(width, height));
+    debug!("reading: {}", encoder_path.to_string_lossy());
+    LabelEncoder::read(encoder_path.as_path())
+}
@@ -5,46 +5,87 @@ extern crate libm;
 extern crate log;
 extern crate rayon;
 extern crate regex;
+extern crate rusqlite;
 extern crate rustlearn;
 extern crate rusty_machine;
 extern crate serde;
 extern crate serde_derive;
 
+pub mod attribution;
+pub mod border;
+pub mod calibration;
+pub mod catalog;
+pub mod cross_validation;
+pub mod label_encoder;
+pub mod manifest;
+pub mod metadata;
+pub mod metadata_diff;
+pub mod pipeline;
+pub mod region;
+pub mod sampling;
+pub mod segmentation;
+pub mod specimen_label;
+pub mod split;
+pub mod sqlite;
+pub mod statistics;
+pub mod stream;
+pub mod submission;
+pub mod subset;
+pub mod synthetic;
+pub mod taxonomy;
+pub mod validation;
+pub mod visualize;
+
+pub use catalog::HerbariumCatalog;
+
 use image::GenericImageView;
-use itertools::Itertools;
 use rustlearn::array;
 use serde::{Deserialize, Serialize};
-use std::collections;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::path;
+use strum_macros::Display;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Annotation {
     pub id: i32,
     pub image_id: i32,
     pub category_id: i32,
     pub region_id: i32,
+    #[serde(default)]
+    pub institution_id: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Category {
     pub id: i32,
-    //pub name: String,
+    #[serde(default)]
+    pub name: Option<String>,
     pub family: String,
     pub genus: String,
+    #[serde(default)]
+    pub species: Option<String>,
+    #[serde(default)]
+    pub order: Option<String>,
+    #[serde(default)]
+    pub authors: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Image {
     pub id: i32,
     pub width: i32,
     pub height: i32,
     pub file_name: String,
     pub license: i32,
+    // the 2022 release uses string image ids, those are renumbered and the original kept here
+    #[serde(default)]
+    pub source_id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 pub struct Info {
     pub year: i32,
     pub version: String,
@@ -54,20 +95,26 @@ pub struct Info {
     pub date_created: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct License {
     pub id: i32,
     pub name: String,
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Region {
     pub id: i32,
     pub name: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+pub struct Institution {
+    pub id: i32,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct TrainMetadata {
     pub annotations: Vec<Annotation>,
     pub categories: Vec<Category>,
@@ -75,9 +122,11 @@ pub struct TrainMetadata {
     pub info: Info,
     pub licenses: Vec<License>,
     pub regions: Vec<Region>,
+    #[serde(default)]
+    pub institutions: Vec<Institution>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct TestMetadata {
     pub images: Vec<Image>,
     pub info: Info,
@@ -91,10 +140,134 @@ pub struct PixelColor {
     pub blue: u8,
 }
 
-pub fn crop_image(mut img: image::DynamicImage, from_left: u32, from_right: u32, from_top: u32, from_bottom: u32) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+#[derive(Debug, Clone, Copy, PartialEq, Display)]
+pub enum PreprocessStep {
+    #[strum(serialize = "crop_image")]
+    CropImage,
+    #[strum(serialize = "preprocessing_step_1")]
+    Step1,
+    #[strum(serialize = "preprocessing_step_2")]
+    Step2,
+    #[strum(serialize = "preprocessing_step_3")]
+    Step3,
+    #[strum(serialize = "preprocessing_step_4")]
+    Step4,
+    #[strum(serialize = "segment_specimen")]
+    SegmentSpecimen,
+}
+
+// the preprocessing steps scan & sample fixed pixel positions tuned on ~680x1000 scans, these are the ways an image can fall outside of them
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreprocessError {
+    ImageTooSmall {
+        step: PreprocessStep,
+        width: u32,
+        height: u32,
+        min_width: u32,
+        min_height: u32,
+    },
+    CropTooLarge {
+        step: PreprocessStep,
+        width: u32,
+        height: u32,
+        from_left: u32,
+        from_right: u32,
+        from_top: u32,
+        from_bottom: u32,
+    },
+    BorderNotFound {
+        step: PreprocessStep,
+        border: &'static str,
+    },
+    NoForeground {
+        step: PreprocessStep,
+        threshold: u8,
+    },
+}
+
+impl PreprocessError {
+    pub fn step(&self) -> PreprocessStep {
+        match self {
+            PreprocessError::ImageTooSmall { step, .. } => *step,
+            PreprocessError::CropTooLarge { step, .. } => *step,
+            PreprocessError::BorderNotFound { step, .. } => *step,
+            PreprocessError::NoForeground { step, .. } => *step,
+        }
+    }
+
+    fn check_size(step: PreprocessStep, width: u32, height: u32, min_width: u32, min_height: u32) -> Result<(), PreprocessError> {
+        if width < min_width || height < min_height {
+            return Err(PreprocessError::ImageTooSmall {
+                step,
+                width,
+                height,
+                min_width,
+                min_height,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreprocessError::ImageTooSmall {
+                step,
+                width,
+                height,
+                min_width,
+                min_height,
+            } => write!(f, "{}: image is {}x{}, needs at least {}x{}", step, width, height, min_width, min_height),
+            PreprocessError::CropTooLarge {
+                step,
+                width,
+                height,
+                from_left,
+                from_right,
+                from_top,
+                from_bottom,
+            } => write!(
+                f,
+                "{}: cropping {} from the left, {} from the right, {} from the top & {} from the bottom leaves nothing of a {}x{} image",
+                step, from_left, from_right, from_top, from_bottom, width, height
+            ),
+            PreprocessError::BorderNotFound { step, border } => write!(f, "{}: no {} border found", step, border),
+            PreprocessError::NoForeground { step, threshold } => write!(f, "{}: no foreground left above the threshold of {}", step, threshold),
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+impl From<PreprocessError> for io::Error {
+    fn from(error: PreprocessError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+pub fn crop_image(
+    mut img: image::DynamicImage,
+    from_left: u32,
+    from_right: u32,
+    from_top: u32,
+    from_bottom: u32,
+) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, PreprocessError> {
     let img_height = img.height();
     let img_width = img.width();
 
+    if from_left + from_right >= img_width || from_top + from_bottom >= img_height {
+        return Err(PreprocessError::CropTooLarge {
+            step: PreprocessStep::CropImage,
+            width: img_width,
+            height: img_height,
+            from_left,
+            from_right,
+            from_top,
+            from_bottom,
+        });
+    }
+
     let mut left_border_cropped_image = image::imageops::crop(&mut img, from_left, 0, img_width, img_height).to_image();
     let left_border_cropped_image_width = left_border_cropped_image.width();
     let left_border_cropped_image_height = left_border_cropped_image.height();
@@ -131,7 +304,7 @@ pub fn crop_image(mut img: image::DynamicImage, from_left: u32, from_right: u32,
         top_border_cropped_image_height - from_bottom,
     )
     .to_image();
-    bottom_border_cropped_image
+    Ok(bottom_border_cropped_image)
 }
 
 // pub fn normalized_train_data(
@@ -142,24 +315,17 @@ pub fn crop_image(mut img: image::DynamicImage, from_left: u32, from_right: u32,
 // ) -> io::Result<(array::sparse::SparseRowArray, array::dense::Array)> {
 pub fn normalized_train_data(
     train_dir: &path::PathBuf,
-    train_metadata: &TrainMetadata,
+    catalog: &HerbariumCatalog,
     resized_width: u32,
     resized_height: u32,
 ) -> io::Result<(array::dense::Array, array::dense::Array)> {
     let mut file_entries = Vec::new();
 
-    let images_by_region_and_category_map: collections::HashMap<(i32, i32), Vec<i32>> =
-        train_metadata.annotations.iter().map(|x| ((x.region_id, x.category_id), x.image_id)).into_group_map();
-    let mut region_and_category_ids: Vec<_> = images_by_region_and_category_map.keys().collect();
-    region_and_category_ids.sort();
-
-    for k in region_and_category_ids.iter() {
-        let image_ids = images_by_region_and_category_map.get(k).unwrap();
+    for k in catalog.region_and_category_ids().iter() {
+        let image_ids = catalog.image_ids_for_region_and_category(k.0, k.1);
         let filtered_images_ids: Vec<_> = image_ids.iter().take(2).collect();
         for image_id in filtered_images_ids.into_iter() {
-            let image = train_metadata.images.iter().find(|e| e.id == *image_id).unwrap();
-            let mut image_path = train_dir.clone();
-            image_path.push(image.file_name.clone());
+            let image_path = catalog.image_path(train_dir, *image_id).unwrap();
             file_entries.push((k.1, image_path));
         }
     }
@@ -179,7 +345,7 @@ pub fn normalized_train_data(
         labels.push(entry.0 as f32);
         // rist cropping more from the bottom as roots don't offer identifying species features
         // stems, leafs, and flowers are where it's at
-        let mut cropped_image = crop_image(img, 30, 30, 80, 140);
+        let mut cropped_image = crop_image(img, 30, 30, 80, 140)?;
         // original images are roughly 680x1000
         // resulting cropping will return roughly 620x780
 
@@ -254,141 +420,136 @@ pub fn normalized_train_data(
     // Ok((data, array::dense::Array::from(labels)))
 }
 
-pub fn preprocessing_step_4(mut img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
-    let replacement_pixel = *img.get_pixel_mut(4, 40);
+pub fn preprocessing_step_4(img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, PreprocessError> {
+    border::blank_gray_borders(img, &border::BorderDetectionParams::default())
+}
 
-    // left border
-    for y in 0..img.height() {
-        for x in 0..55 {
-            let pixel = img.get_pixel_mut(x, y);
-            let red = pixel[0];
-            let green = pixel[1];
-            let blue = pixel[2];
+// preprocessing_step_3 blanks every run of 19 pixels in a row that is neither mostly white nor mostly black and has a stddev of at most 9 in every channel
+const UNIFORM_RUN: usize = 19;
 
-            let tmp = vec![red, green, blue];
-            let min = itertools::min(tmp.clone()).unwrap();
-            let max = itertools::max(tmp.clone()).unwrap();
+// window sums & sums of squares, kept per channel so each step of the window costs the same whatever its length
+#[derive(Debug, Clone, Copy, Default)]
+struct WindowSums {
+    sum: [i64; 3],
+    squares: [i64; 3],
+}
 
-            if (max - min) <= 10 {
-                pixel[0] = replacement_pixel[0];
-                pixel[1] = replacement_pixel[1];
-                pixel[2] = replacement_pixel[2];
-            }
+impl WindowSums {
+    fn add(&mut self, pixel: [u8; 3]) {
+        for channel in 0..3 {
+            self.sum[channel] += pixel[channel] as i64;
+            self.squares[channel] += pixel[channel] as i64 * pixel[channel] as i64;
         }
     }
 
-    // right border
-    for y in 0..img.height() {
-        for x in (img.width() - 55)..img.width() {
-            let pixel = img.get_pixel_mut(x, y);
-            let red = pixel[0];
-            let green = pixel[1];
-            let blue = pixel[2];
-
-            let tmp = vec![red, green, blue];
-            let min = itertools::min(tmp.clone()).unwrap();
-            let max = itertools::max(tmp.clone()).unwrap();
-
-            if (max - min) <= 10 {
-                pixel[0] = replacement_pixel[0];
-                pixel[1] = replacement_pixel[1];
-                pixel[2] = replacement_pixel[2];
-            }
+    fn remove(&mut self, pixel: [u8; 3]) {
+        for channel in 0..3 {
+            self.sum[channel] -= pixel[channel] as i64;
+            self.squares[channel] -= pixel[channel] as i64 * pixel[channel] as i64;
         }
     }
 
-    // bottom border
-    for y in (img.height() - 40)..img.height() {
-        for x in 0..img.width() {
-            let pixel = img.get_pixel_mut(x, y);
-            let red = pixel[0];
-            let green = pixel[1];
-            let blue = pixel[2];
-
-            let tmp = vec![red, green, blue];
-            let min = itertools::min(tmp.clone()).unwrap();
-            let max = itertools::max(tmp.clone()).unwrap();
-
-            if (max - min) <= 10 {
-                pixel[0] = replacement_pixel[0];
-                pixel[1] = replacement_pixel[1];
-                pixel[2] = replacement_pixel[2];
-            }
-        }
+    // n^2 times the population variance, n(n - 1) times the sample variance, exact
+    fn spread(&self, channel: usize) -> i64 {
+        UNIFORM_RUN as i64 * self.squares[channel] - self.sum[channel] * self.sum[channel]
     }
+}
 
-    // top border
-    for y in 0..40 {
-        for x in 0..img.width() {
-            let pixel = img.get_pixel_mut(x, y);
-            let red = pixel[0];
-            let green = pixel[1];
-            let blue = pixel[2];
-
-            let tmp = vec![red, green, blue];
-            let min = itertools::min(tmp.clone()).unwrap();
-            let max = itertools::max(tmp.clone()).unwrap();
+// the check the original per window statistics made, the reds with the sample stddev & the greens and blues with the population stddev
+fn is_uniform_run(window: &[[u8; 3]]) -> bool {
+    let channel = |idx: usize| window.iter().map(|e| e[idx] as f32).collect::<Vec<f32>>();
+    let (reds, greens, blues) = (channel(0), channel(1), channel(2));
+    let (reds_mean, greens_mean, blues_mean) = (statistical::mean(&reds), statistical::mean(&greens), statistical::mean(&blues));
+    if reds_mean > 210.0 && greens_mean > 210.0 && blues_mean > 210.0 {
+        return false;
+    }
+    if reds_mean < 52.0 && greens_mean < 52.0 && blues_mean < 52.0 {
+        return false;
+    }
+    !(statistical::standard_deviation(&reds, None) > 9.0
+        || statistical::population_standard_deviation(&greens, None) > 9.0
+        || statistical::population_standard_deviation(&blues, None) > 9.0)
+}
 
-            if (max - min) <= 10 {
-                pixel[0] = replacement_pixel[0];
-                pixel[1] = replacement_pixel[1];
-                pixel[2] = replacement_pixel[2];
-            }
-        }
+// same decision as is_uniform_run from the running sums, the means are compared exactly on the sums
+// the stddevs are exact too except right at 9, where the f32 rounding of the original decides, so those few windows go through is_uniform_run
+fn is_uniform_window(sums: &WindowSums, window: &[[u8; 3]]) -> bool {
+    let n = UNIFORM_RUN as i64;
+    if sums.sum.iter().all(|e| *e > 210 * n) || sums.sum.iter().all(|e| *e < 52 * n) {
+        return false;
     }
-    img
+    let limits = [81 * n * (n - 1), 81 * n * n, 81 * n * n];
+    if (0..3).any(|channel| (sums.spread(channel) - limits[channel]).abs() <= n) {
+        return is_uniform_run(window);
+    }
+    (0..3).all(|channel| sums.spread(channel) <= limits[channel])
 }
 
-pub fn preprocessing_step_3(mut img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+// windows are checked left to right and a blanked window is blanked before the next one is checked, so later windows see the replacement color
+pub fn preprocessing_step_3(mut img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, PreprocessError> {
+    PreprocessError::check_size(PreprocessStep::Step3, img.width(), img.height(), 5, 41)?;
     let replacement_pixel = *img.get_pixel_mut(4, 40);
+    let replacement = [replacement_pixel[0], replacement_pixel[1], replacement_pixel[2]];
+    let width = img.width() as usize;
+    if width < UNIFORM_RUN {
+        return Ok(img);
+    }
 
-    for y in 0..img.height() {
-        for x_window in (0..img.width()).collect::<Vec<u32>>().windows(19) {
-            let mut window_data = Vec::new();
+    // the sums of a window that has just been blanked
+    let mut blanked = WindowSums::default();
+    (0..UNIFORM_RUN).for_each(|_| blanked.add(replacement));
 
-            for x in x_window.iter() {
-                let pixel = img.get_pixel(*x, y);
-                let red = pixel[0];
-                let green = pixel[1];
-                let blue = pixel[2];
-                // debug!("x: {}, red: {}, green: {}, blue: {}", x, red, green, blue);
-                window_data.push((red as f32, green as f32, blue as f32));
+    let mut row: Vec<[u8; 3]> = Vec::with_capacity(width);
+    for y in 0..img.height() {
+        row.clear();
+        row.extend((0..img.width()).map(|x| {
+            let pixel = img.get_pixel(x, y);
+            [pixel[0], pixel[1], pixel[2]]
+        }));
+
+        let mut sums = WindowSums::default();
+        row[..UNIFORM_RUN].iter().for_each(|e| sums.add(*e));
+        for start in 0..=(width - UNIFORM_RUN) {
+            if start > 0 {
+                sums.remove(row[start - 1]);
+                sums.add(row[start + UNIFORM_RUN - 1]);
             }
-
-            let range = itertools::min(x_window.clone()).unwrap()..itertools::max(x_window.clone()).unwrap();
-
-            let reds: Vec<_> = window_data.iter().map(|e| e.0).collect();
-            let reds_mean = statistical::mean(&reds);
-            let reds_stddev = statistical::standard_deviation(&reds, None);
-
-            let greens: Vec<_> = window_data.iter().map(|e| e.1).collect();
-            let greens_mean = statistical::mean(&greens);
-            let greens_stddev = statistical::population_standard_deviation(&greens, None);
-
-            let blues: Vec<_> = window_data.iter().map(|e| e.2).collect();
-            let blues_mean = statistical::mean(&blues);
-            let blues_stddev = statistical::population_standard_deviation(&blues, None);
-
-            // filter out mostly white pixels
-            if reds_mean > 210.0 && greens_mean > 210.0 && blues_mean > 210.0 {
+            if !is_uniform_window(&sums, &row[start..start + UNIFORM_RUN]) {
                 continue;
             }
 
-            // filter out mostly black pixels
-            if reds_mean < 52.0 && greens_mean < 52.0 && blues_mean < 52.0 {
-                continue;
+            debug!("y: {}, range: {:?}, sums: {:?}", y, start..(start + UNIFORM_RUN - 1), sums.sum);
+            for x in start..(start + UNIFORM_RUN) {
+                row[x] = replacement;
+                let pixel = img.get_pixel_mut(x as u32, y);
+                pixel[0] = replacement[0];
+                pixel[1] = replacement[1];
+                pixel[2] = replacement[2];
             }
+            sums = blanked;
+        }
+    }
+
+    Ok(img)
+}
 
-            // filter out non-common color range
-            if reds_stddev > 9.0 || greens_stddev > 9.0 || blues_stddev > 9.0 {
+// preprocessing_step_3 as it was before the running sums, every window's statistics computed from scratch by is_uniform_run
+// the reference the tests & benchmark_preprocessing compare preprocessing_step_3 against, far too slow for anything else
+#[doc(hidden)]
+pub fn preprocessing_step_3_reference(mut img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+    let replacement_pixel = *img.get_pixel_mut(4, 40);
+    for y in 0..img.height() {
+        for x_window in (0..img.width()).collect::<Vec<u32>>().windows(UNIFORM_RUN) {
+            let window: Vec<[u8; 3]> = x_window
+                .iter()
+                .map(|x| {
+                    let pixel = img.get_pixel(*x, y);
+                    [pixel[0], pixel[1], pixel[2]]
+                })
+                .collect();
+            if !is_uniform_run(&window) {
                 continue;
             }
-
-            debug!(
-                "range: {:?}, reds_mean: {}, greens_mean: {}, blues_mean: {}, reds_stddev: {}, greens_stddev: {}, blues_stddev: {}",
-                range, reds_mean, greens_mean, blues_mean, reds_stddev, greens_stddev, blues_stddev
-            );
-
             for x in x_window.iter() {
                 let pixel = img.get_pixel_mut(*x, y);
                 pixel[0] = replacement_pixel[0];
@@ -397,15 +558,11 @@ pub fn preprocessing_step_3(mut img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>
             }
         }
     }
-
     img
 }
 
-pub fn preprocessing_step_2(img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
-    let mut img = image::imageops::flip_vertical(&img);
-    image::imageops::invert(&mut img);
-    let mut img = image::imageops::contrast(&mut img, 10.0);
-
+// scans the flipped, inverted & contrast stretched image from preprocessing_step_2 for the first row with anything bright left in it
+fn bottom_edge_scan(img: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> u32 {
     let mut cutoff = 0;
     for y in 10..(img.height() / 2) {
         let mut row_data = Vec::new();
@@ -424,14 +581,36 @@ pub fn preprocessing_step_2(img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -
             row_data.push(green);
             row_data.push(blue);
         }
-        let avg = row_data.iter().sum::<u8>() as f32 / row_data.len() as f32;
+        if row_data.is_empty() {
+            continue;
+        }
+        let avg = row_data.iter().map(|e| *e as u32).sum::<u32>() as f32 / row_data.len() as f32;
         debug!("y: {}, row_data average: {}", y, avg);
-        if !avg.is_nan() && avg > 1f32 {
+        if avg > 1f32 {
             cutoff = y;
             break;
         }
     }
     debug!("cutoff: {}", cutoff);
+    cutoff
+}
+
+fn bottom_edge_prepare(img: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, PreprocessError> {
+    // the scan skips 130 columns on each side and starts 10 rows in
+    PreprocessError::check_size(PreprocessStep::Step2, img.width(), img.height(), 261, 22)?;
+    let mut img = image::imageops::flip_vertical(img);
+    image::imageops::invert(&mut img);
+    Ok(image::imageops::contrast(&img, 10.0))
+}
+
+// how many rows preprocessing_step_2 crops off the bottom
+pub fn bottom_edge_cutoff(img: &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<u32, PreprocessError> {
+    Ok(bottom_edge_scan(&bottom_edge_prepare(img)?))
+}
+
+pub fn preprocessing_step_2(img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, PreprocessError> {
+    let mut img = bottom_edge_prepare(&img)?;
+    let cutoff = bottom_edge_scan(&img);
 
     let img_height = img.height();
     let img_width = img.width();
@@ -441,130 +620,129 @@ pub fn preprocessing_step_2(img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -
 
     let mut img = image::imageops::contrast(&mut img, -10.0);
     image::imageops::invert(&mut img);
-    img
+    Ok(img)
 }
 
-pub fn preprocessing_step_1(mut img: image::DynamicImage) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
-    let mut left_border = Vec::new();
+pub fn preprocessing_step_1(img: image::DynamicImage) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, PreprocessError> {
+    border::remove_borders(img, &border::BorderDetectionParams::default())
+}
 
-    'outer: for y in 0..img.height() {
-        if !(100u32..900u32).contains(&y) {
-            continue 'outer;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    type RgbaImage = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
+
+    fn assert_same_as_original(img: RgbaImage) {
+        let expected = preprocessing_step_3_reference(img.clone());
+        let actual = preprocessing_step_3(img).unwrap();
+        assert_eq!(actual.dimensions(), expected.dimensions());
+        for (x, y, pixel) in expected.enumerate_pixels() {
+            assert_eq!(actual.get_pixel(x, y), pixel, "x: {}, y: {}", x, y);
         }
-        'inner: for x in 0..img.width() {
-            if !(10u32..40u32).contains(&x) {
-                continue 'inner;
-            }
-
-            let pixel = img.get_pixel(x, y);
-            let red = pixel[0];
-            let green = pixel[1];
-            let blue = pixel[2];
+    }
 
-            debug!("x: {}, y: {}, red: {}, green: {}, blue: {}", x, y, red, green, blue);
-            left_border.push(x);
+    // every channel spread around its base by up to +/- spread, uniform noise of +/- 15 has a stddev of about 9
+    fn noisy_image(rng: &mut StdRng, width: u32, height: u32, bases: &[u8], spread: i32) -> RgbaImage {
+        image::ImageBuffer::from_fn(width, height, |_, y| {
+            let base = bases[y as usize % bases.len()] as i32;
+            let mut channel = || (base + rng.gen_range(-spread, spread + 1)).clamp(0, 255) as u8;
+            image::Rgba([channel(), channel(), channel(), 255])
+        })
+    }
 
-            if red > 100 && green > 100 && blue > 100 {
-                continue 'outer;
+    fn near_limit_windows(img: &RgbaImage) -> usize {
+        let n = UNIFORM_RUN as i64;
+        let limits = [81 * n * (n - 1), 81 * n * n, 81 * n * n];
+        let mut count = 0;
+        for y in 0..img.height() {
+            let row: Vec<[u8; 3]> = (0..img.width()).map(|x| img.get_pixel(x, y)).map(|e| [e[0], e[1], e[2]]).collect();
+            for window in row.windows(UNIFORM_RUN) {
+                let mut sums = WindowSums::default();
+                window.iter().for_each(|e| sums.add(*e));
+                if (0..3).any(|channel| (sums.spread(channel) - limits[channel]).abs() <= n) {
+                    count += 1;
+                }
             }
         }
+        count
     }
 
-    debug!("left_border max: {}", left_border.iter().max().unwrap());
-    let from_left = left_border.iter().max().unwrap() + 1;
-
-    let img_height = img.height();
-    let img_width = img.width();
-
-    let img = image::imageops::crop(&mut img, from_left, 0, img_width, img_height).to_image();
-
-    let mut img = image::imageops::flip_horizontal(&img);
-
-    let mut right_border = Vec::new();
-
-    'outer: for y in 0..img.height() {
-        if !(100u32..900u32).contains(&y) {
-            continue;
-        }
-        'inner: for x in 0..img.width() {
-            if !(10u32..40u32).contains(&x) {
-                continue;
-            }
-            let pixel = img.get_pixel(x, y);
-            let red = pixel[0];
-            let green = pixel[1];
-            let blue = pixel[2];
-
-            debug!("x: {}, y: {}, red: {}, green: {}, blue: {}", x, y, red, green, blue);
-            right_border.push(x);
-
-            if red > 100 && green > 100 && blue > 100 {
-                continue 'outer;
+    // a run whose stddev is as close to 9 as it gets, exactly 9 by the sample stddev for the reds
+    // the population stddev for the others can't be exactly 9 with whole pixel values, the runs are the ones just below & above it
+    fn run_at_the_limit(rng: &mut StdRng, base: i32, channel: usize) -> Vec<u8> {
+        let n = UNIFORM_RUN as i64;
+        let limit = if channel == 0 { 81 * n * (n - 1) } else { 81 * n * n };
+        loop {
+            let run: Vec<u8> = (0..UNIFORM_RUN).map(|_| (base + rng.gen_range(-15, 16)).clamp(0, 255) as u8).collect();
+            let mut sums = WindowSums::default();
+            run.iter().for_each(|e| sums.add([*e, *e, *e]));
+            if (sums.spread(0) - limit).abs() <= n {
+                return run;
             }
         }
     }
 
-    debug!("right_border max: {}", right_border.iter().max().unwrap());
-    let from_right = right_border.iter().max().unwrap() + 1;
-
-    let img = image::imageops::crop(&mut img, from_right, 0, img_width, img_height).to_image();
-
-    let mut img = image::imageops::flip_horizontal(&img);
-
-    let mut top_border = Vec::new();
-
-    for y in 0..img.height() {
-        if !(20u32..100u32).contains(&y) {
-            continue;
+    #[test]
+    fn step_3_matches_the_original_near_the_thresholds() {
+        let mut rng = StdRng::seed_from_u64(25);
+        for bases in [vec![52u8, 51, 53], vec![210u8, 209, 211], vec![130u8, 90]].iter() {
+            let img = noisy_image(&mut rng, 240, 60, bases, 15);
+            assert!(near_limit_windows(&img) > 0);
+            assert_same_as_original(img);
         }
-        let pixel = img.get_pixel(0u32, y);
-        let red = pixel[0];
-        let green = pixel[1];
-        let blue = pixel[2];
-
-        debug!("y: {}, red: {}, green: {}, blue: {}", y, red, green, blue);
-        top_border.push(y);
 
-        if red > 100 && green > 100 && blue > 100 {
-            break;
+        // one run per row, only one channel varies
+        for base in [52, 130, 210].iter() {
+            let runs: Vec<(usize, Vec<u8>)> = (0..45).map(|y| (y % 3, run_at_the_limit(&mut rng, *base, y % 3))).collect();
+            let img = image::ImageBuffer::from_fn(UNIFORM_RUN as u32, 45, |x, y| {
+                let (channel, run) = &runs[y as usize];
+                let mut pixel = image::Rgba([*base as u8, *base as u8, *base as u8, 255]);
+                pixel[*channel] = run[x as usize];
+                pixel
+            });
+            assert_same_as_original(img);
         }
     }
 
-    debug!("top_border max: {}", top_border.iter().max().unwrap());
-    let from_top = top_border.iter().max().unwrap() + 1;
-
-    let img_height = img.height();
-    let img_width = img.width();
-
-    let img = image::imageops::crop(&mut img, 0, from_top, img_width, img_height).to_image();
-
-    let mut img = image::imageops::flip_vertical(&img);
-
-    let mut bottom_border = Vec::new();
+    #[test]
+    fn step_3_matches_the_original_on_overlapping_blanked_windows() {
+        let mut rng = StdRng::seed_from_u64(25);
+        assert_same_as_original(noisy_image(&mut rng, 200, 50, &[120, 80, 170], 3));
+
+        // the replacement color itself is uniform, so every window after a blanked one is blanked again
+        let img = image::ImageBuffer::from_fn(120, 45, |x, _| {
+            if x % 40 < 25 {
+                image::Rgba([100, 100, 100, 255])
+            } else {
+                image::Rgba([100, 160, 40, 255])
+            }
+        });
+        assert_same_as_original(img);
+    }
 
-    for y in 0..img.height() {
-        if !(10u32..50u32).contains(&y) {
-            continue;
+    #[test]
+    fn step_3_matches_the_original_on_narrow_images() {
+        let mut rng = StdRng::seed_from_u64(25);
+        for width in [5, 12, 18, 19, 20].iter() {
+            assert_same_as_original(noisy_image(&mut rng, *width, 41, &[120, 60], 2));
         }
+    }
 
-        let pixel = img.get_pixel(0u32, y);
-        let red = pixel[0];
-        let green = pixel[1];
-        let blue = pixel[2];
-
-        debug!("y: {}, red: {}, green: {}, blue: {}", y, red, green, blue);
-        bottom_border.push(y);
-
-        if red > 100 && green > 100 && blue > 100 {
-            break;
+    #[test]
+    fn step_3_matches_the_original_on_synthetic_sheets() {
+        let config = synthetic::SyntheticConfig {
+            width: 136,
+            height: 200,
+            ..synthetic::SyntheticConfig::default()
+        };
+        let mut rng = StdRng::seed_from_u64(25);
+        for category_id in 0..3 {
+            let sheet = synthetic::draw_sheet(&config, category_id, &mut rng);
+            assert_same_as_original(image::DynamicImage::ImageRgb8(sheet).to_rgba8());
         }
     }
-
-    debug!("bottom_border max: {}", bottom_border.iter().max().unwrap());
-    let from_bottom = bottom_border.iter().max().unwrap() + 1;
-
-    let img = image::imageops::crop(&mut img, 0, from_bottom, img_width, img_height).to_image();
-    let img = image::imageops::flip_vertical(&img);
-    let img = image::imageops::resize(&img, 600, 800, image::imageops::FilterType::CatmullRom);
-    img
 }
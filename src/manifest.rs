@@ -0,0 +1,157 @@
+use crate::split::{Split, SplitKind};
+use crate::{HerbariumCatalog, TestMetadata};
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::io;
+use std::path;
+use strum_macros::{Display, EnumString};
+
+// the serialized files are named herbarium-{part}-{data,labels,weights,manifest,failures}-{w}x{h}
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DatasetPart {
+    Training,
+    Validation,
+    Holdout,
+    Testing,
+}
+
+impl DatasetPart {
+    pub fn data_file_name(&self, width: u32, height: u32) -> String {
+        format!("herbarium-{}-data-{}x{}.ser.gz", self, width, height)
+    }
+
+    pub fn labels_file_name(&self, width: u32, height: u32) -> String {
+        format!("herbarium-{}-labels-{}x{}.ser.gz", self, width, height)
+    }
+
+    pub fn weights_file_name(&self, width: u32, height: u32) -> String {
+        format!("herbarium-{}-weights-{}x{}.ser.gz", self, width, height)
+    }
+
+    pub fn manifest_file_name(&self, width: u32, height: u32) -> String {
+        format!("herbarium-{}-manifest-{}x{}.csv", self, width, height)
+    }
+
+    pub fn failures_file_name(&self, width: u32, height: u32) -> String {
+        format!("herbarium-{}-failures-{}x{}.csv", self, width, height)
+    }
+}
+
+impl From<SplitKind> for DatasetPart {
+    fn from(kind: SplitKind) -> DatasetPart {
+        match kind {
+            SplitKind::Train => DatasetPart::Training,
+            SplitKind::Validation => DatasetPart::Validation,
+            SplitKind::Test => DatasetPart::Holdout,
+        }
+    }
+}
+
+// file_path is relative to the base directory (train/... or test/...)
+// category_id & region_id are empty for the unlabeled test images, augmentation is only set in the per row manifests written next to the data
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub image_id: i32,
+    pub file_path: String,
+    pub category_id: Option<i32>,
+    pub region_id: Option<i32>,
+    pub split: DatasetPart,
+    #[serde(default)]
+    pub augmentation: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn from_split(catalog: &HerbariumCatalog, split: &Split) -> Manifest {
+        let mut entries = Vec::new();
+        for kind in [SplitKind::Train, SplitKind::Validation, SplitKind::Test].iter() {
+            for image_id in split.image_ids(*kind).iter() {
+                let image = match catalog.image(*image_id) {
+                    Some(image) => image,
+                    None => {
+                        warn!("image_id: {} is not in the train metadata", image_id);
+                        continue;
+                    }
+                };
+                let annotation = catalog.annotations_for_image(*image_id).first().cloned();
+                entries.push(ManifestEntry {
+                    image_id: *image_id,
+                    file_path: path::Path::new("train").join(&image.file_name).to_string_lossy().to_string(),
+                    category_id: annotation.map(|e| e.category_id),
+                    region_id: annotation.map(|e| e.region_id),
+                    split: DatasetPart::from(*kind),
+                    augmentation: None,
+                });
+            }
+        }
+        Manifest { entries }
+    }
+
+    pub fn from_test_metadata(test_metadata: &TestMetadata) -> Manifest {
+        let entries = test_metadata
+            .images
+            .iter()
+            .map(|image| ManifestEntry {
+                image_id: image.id,
+                file_path: path::Path::new("test").join(&image.file_name).to_string_lossy().to_string(),
+                category_id: None,
+                region_id: None,
+                split: DatasetPart::Testing,
+                augmentation: None,
+            })
+            .collect();
+        Manifest { entries }
+    }
+
+    pub fn entries(&self, part: DatasetPart) -> Vec<&ManifestEntry> {
+        self.entries.iter().filter(|e| e.split == part).collect()
+    }
+
+    pub fn image_count(&self) -> usize {
+        self.entries.iter().map(|e| e.image_id).collect::<collections::HashSet<i32>>().len()
+    }
+
+    pub fn category_count(&self) -> usize {
+        self.entries.iter().filter_map(|e| e.category_id).collect::<collections::HashSet<i32>>().len()
+    }
+
+    // the per row manifests are the only thing tying a data row back to its image, so a mismatch means the files are from different runs
+    pub fn check_rows(&self, part: DatasetPart, rows: usize) -> io::Result<()> {
+        if self.entries.len() != rows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} manifest has {} rows but the data has {}", part, self.entries.len(), rows),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn read(manifest_path: &path::Path) -> io::Result<Manifest> {
+        let mut reader = csv::Reader::from_path(manifest_path)?;
+        let entries = reader.deserialize().collect::<Result<Vec<ManifestEntry>, csv::Error>>()?;
+        Ok(Manifest { entries })
+    }
+
+    pub fn write(&self, manifest_path: &path::Path) -> io::Result<()> {
+        let mut writer = csv::Writer::from_path(manifest_path)?;
+        for entry in self.entries.iter() {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// reads the per row manifest written next to the serialized data
+pub fn read_part_manifest(serialization_dir: &path::Path, part: DatasetPart, width: u32, height: u32) -> io::Result<Manifest> {
+    let mut manifest_path = serialization_dir.to_path_buf();
+    manifest_path.push(part.manifest_file_name(width, height));
+    debug!("reading: {}", manifest_path.to_string_lossy());
+    Manifest::read(manifest_path.as_path())
+}
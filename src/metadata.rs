@@ -0,0 +1,394 @@
+use crate::stream;
+use crate::stream::MetadataSink;
+use crate::{Annotation, Category, Image, Institution, TestMetadata, TrainMetadata};
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::Write;
+use std::path;
+use std::process;
+use std::time;
+use strum_macros::{Display, EnumString};
+
+// the competition metadata layout changed with every release, everything is normalized into the 2020 shaped TrainMetadata/TestMetadata
+// releases after 2020 have no regions, the institutions take their place so region based tooling keeps working
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, Display)]
+pub enum MetadataVersion {
+    #[strum(serialize = "2020")]
+    Herbarium2020,
+    #[strum(serialize = "2021")]
+    Herbarium2021,
+    #[strum(serialize = "2022")]
+    Herbarium2022,
+}
+
+impl MetadataVersion {
+    pub fn detect(value: &serde_json::Value) -> Option<MetadataVersion> {
+        let year = value
+            .pointer("/info/year")
+            .and_then(|e| e.as_i64().or_else(|| e.as_str().and_then(|year| year.trim().parse::<i64>().ok())));
+        match year {
+            Some(2020) => return Some(MetadataVersion::Herbarium2020),
+            Some(2021) => return Some(MetadataVersion::Herbarium2021),
+            Some(2022) => return Some(MetadataVersion::Herbarium2022),
+            _ => {}
+        }
+
+        if let Some(version) = value.pointer("/info/version").and_then(|e| e.as_str()) {
+            if version.contains("2020") {
+                return Some(MetadataVersion::Herbarium2020);
+            } else if version.contains("2021") {
+                return Some(MetadataVersion::Herbarium2021);
+            } else if version.contains("2022") {
+                return Some(MetadataVersion::Herbarium2022);
+            }
+        }
+
+        // no usable info block, fall back on the layout
+        if value.is_array() || value.get("genera").is_some() || value.pointer("/categories/0/scientific_name").is_some() {
+            return Some(MetadataVersion::Herbarium2022);
+        }
+        if value.pointer("/images/0/image_id").is_some() {
+            return Some(MetadataVersion::Herbarium2022);
+        }
+        if value.get("institutions").is_some() {
+            return Some(MetadataVersion::Herbarium2021);
+        }
+        if value.get("regions").is_some() || value.get("images").is_some() {
+            return Some(MetadataVersion::Herbarium2020);
+        }
+        None
+    }
+}
+
+// the per entry layouts of the later releases, the stream converts every entry into the 2020 shape as soon as it's read
+pub mod v2021 {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug)]
+    pub struct Annotation {
+        pub id: i32,
+        pub image_id: i32,
+        pub category_id: i32,
+        pub institution_id: i32,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Category {
+        pub id: i32,
+        pub name: String,
+        pub family: String,
+        #[serde(default)]
+        pub order: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Institution {
+        pub id: i32,
+        pub name: String,
+    }
+}
+
+pub mod v2022 {
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+    #[serde(untagged)]
+    pub enum RawId {
+        Number(i64),
+        Text(String),
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Annotation {
+        pub image_id: RawId,
+        pub category_id: i32,
+        #[serde(default)]
+        pub genus_id: Option<i32>,
+        pub institution_id: i32,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Category {
+        pub category_id: i32,
+        pub scientific_name: String,
+        pub family: String,
+        pub genus: String,
+        #[serde(default)]
+        pub species: Option<String>,
+        #[serde(default)]
+        pub authors: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Image {
+        pub image_id: RawId,
+        pub file_name: String,
+        #[serde(default)]
+        pub license: i32,
+        #[serde(default)]
+        pub width: i32,
+        #[serde(default)]
+        pub height: i32,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Institution {
+        pub institution_id: i32,
+        pub collection_code: String,
+    }
+}
+
+impl From<v2021::Annotation> for Annotation {
+    fn from(annotation: v2021::Annotation) -> Annotation {
+        Annotation {
+            id: annotation.id,
+            image_id: annotation.image_id,
+            category_id: annotation.category_id,
+            region_id: annotation.institution_id,
+            institution_id: Some(annotation.institution_id),
+        }
+    }
+}
+
+impl From<v2021::Category> for Category {
+    fn from(category: v2021::Category) -> Category {
+        // names are "Genus species ..."
+        let mut name_parts = category.name.split_whitespace();
+        let genus = name_parts.next().unwrap_or("").to_string();
+        let species = name_parts.next().map(|e| e.to_string());
+        Category {
+            id: category.id,
+            name: Some(category.name.clone()),
+            family: category.family,
+            genus,
+            species,
+            order: category.order,
+            authors: None,
+        }
+    }
+}
+
+impl From<v2021::Institution> for Institution {
+    fn from(institution: v2021::Institution) -> Institution {
+        Institution {
+            id: institution.id,
+            name: institution.name,
+        }
+    }
+}
+
+impl From<v2022::Category> for Category {
+    fn from(category: v2022::Category) -> Category {
+        Category {
+            id: category.category_id,
+            name: Some(category.scientific_name),
+            family: category.family,
+            genus: category.genus,
+            species: category.species,
+            order: None,
+            authors: category.authors,
+        }
+    }
+}
+
+impl From<v2022::Institution> for Institution {
+    fn from(institution: v2022::Institution) -> Institution {
+        Institution {
+            id: institution.institution_id,
+            name: institution.collection_code,
+        }
+    }
+}
+
+// string ids are renumbered in order of first appearance, whether that's in the annotations or the images, numeric ids are kept as is
+// an annotation pointing at an unknown image gets an id no image has, which the validator reports
+#[derive(Debug, Default)]
+pub(crate) struct ImageIdMapper {
+    ids: collections::HashMap<String, i32>,
+    annotation_count: i32,
+}
+
+impl ImageIdMapper {
+    fn id(&mut self, raw_id: &v2022::RawId) -> i32 {
+        match raw_id {
+            v2022::RawId::Number(id) => *id as i32,
+            v2022::RawId::Text(id) => {
+                let next_id = self.ids.len() as i32 + 1;
+                *self.ids.entry(id.clone()).or_insert(next_id)
+            }
+        }
+    }
+
+    // 2022 annotations have no id of their own, they're numbered in file order
+    pub(crate) fn annotation(&mut self, annotation: v2022::Annotation) -> Annotation {
+        self.annotation_count += 1;
+        Annotation {
+            id: self.annotation_count,
+            image_id: self.id(&annotation.image_id),
+            category_id: annotation.category_id,
+            region_id: annotation.institution_id,
+            institution_id: Some(annotation.institution_id),
+        }
+    }
+
+    pub(crate) fn image(&mut self, image: v2022::Image) -> Image {
+        let source_id = match &image.image_id {
+            v2022::RawId::Number(_) => None,
+            v2022::RawId::Text(id) => Some(id.clone()),
+        };
+        Image {
+            id: self.id(&image.image_id),
+            width: image.width,
+            height: image.height,
+            file_name: image.file_name,
+            license: image.license,
+            source_id,
+        }
+    }
+}
+
+fn undetectable_version(metadata_path: &path::Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unable to detect the metadata version of {}", metadata_path.to_string_lossy()),
+    )
+}
+
+pub fn detect_version(metadata_path: &path::Path) -> io::Result<MetadataVersion> {
+    let metadata_file = fs::File::open(metadata_path)?;
+    let probe = stream::probe_metadata(io::BufReader::new(metadata_file))?;
+    MetadataVersion::detect(&probe).ok_or_else(|| undetectable_version(metadata_path))
+}
+
+// train or test metadata of any release, entry by entry into the sink, returns the version it was read as
+pub fn stream_metadata_file<S: MetadataSink>(metadata_path: &path::Path, version: Option<MetadataVersion>, sink: &mut S) -> io::Result<MetadataVersion> {
+    let version = match version {
+        Some(version) => version,
+        None => detect_version(metadata_path)?,
+    };
+
+    let metadata_file = fs::File::open(metadata_path)?;
+    stream::stream_metadata(io::BufReader::new(metadata_file), version, sink)?;
+    Ok(version)
+}
+
+pub fn read_train_metadata(metadata_path: &path::Path, version: Option<MetadataVersion>) -> io::Result<TrainMetadata> {
+    let mut metadata = TrainMetadata::default();
+    stream_metadata_file(metadata_path, version, &mut metadata)?;
+    Ok(metadata)
+}
+
+pub fn read_test_metadata(metadata_path: &path::Path, version: Option<MetadataVersion>) -> io::Result<TestMetadata> {
+    let mut metadata = TestMetadata::default();
+    stream_metadata_file(metadata_path, version, &mut metadata)?;
+    Ok(metadata)
+}
+
+// bump whenever the normalized structs change shape, older caches are then ignored
+const CACHE_FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct CacheHeader {
+    format_version: u32,
+    source_path: String,
+    source_len: u64,
+    source_modified: u64,
+    version: MetadataVersion,
+}
+
+impl CacheHeader {
+    fn new(metadata_path: &path::Path, version: MetadataVersion) -> io::Result<CacheHeader> {
+        let source_metadata = fs::metadata(metadata_path)?;
+        let source_modified = source_metadata.modified()?.duration_since(time::UNIX_EPOCH).map(|e| e.as_secs()).unwrap_or(0);
+        Ok(CacheHeader {
+            format_version: CACHE_FORMAT_VERSION,
+            source_path: fs::canonicalize(metadata_path)?.to_string_lossy().to_string(),
+            source_len: source_metadata.len(),
+            source_modified,
+            version,
+        })
+    }
+}
+
+pub fn cache_path(metadata_path: &path::Path) -> io::Result<path::PathBuf> {
+    let canonical_path = fs::canonicalize(metadata_path)?;
+    let mut hasher = collections::hash_map::DefaultHasher::new();
+    canonical_path.hash(&mut hasher);
+
+    let mut cache_path = dirs::cache_dir().unwrap_or_else(env::temp_dir);
+    cache_path.push("rusty-herbarium");
+    cache_path.push(format!("metadata-{:016x}.ser", hasher.finish()));
+    Ok(cache_path)
+}
+
+fn read_cached<T, F>(metadata_path: &path::Path, version: Option<MetadataVersion>, read: F) -> io::Result<T>
+where
+    T: Serialize + serde::de::DeserializeOwned,
+    F: FnOnce(&path::Path, Option<MetadataVersion>) -> io::Result<T>,
+{
+    let cache_path = cache_path(metadata_path)?;
+
+    if let Ok(cache_file) = fs::File::open(cache_path.as_path()) {
+        let mut br = io::BufReader::new(cache_file);
+        if let Ok(header) = bincode::deserialize_from::<_, CacheHeader>(&mut br) {
+            let expected_version = version.unwrap_or(header.version);
+            if header == CacheHeader::new(metadata_path, expected_version)? {
+                debug!("reading cache: {}", cache_path.to_string_lossy());
+                match bincode::deserialize_from(&mut br) {
+                    Ok(metadata) => return Ok(metadata),
+                    Err(e) => warn!("ignoring unreadable cache {}: {}", cache_path.to_string_lossy(), e),
+                }
+            }
+        }
+    }
+
+    let version = match version {
+        Some(version) => version,
+        None => detect_version(metadata_path)?,
+    };
+    let metadata = read(metadata_path, Some(version))?;
+
+    // the cache only saves time, a read only cache dir mustn't stop the run
+    if let Err(e) = write_cache(metadata_path, version, cache_path.as_path(), &metadata) {
+        warn!("unable to write cache {}: {}", cache_path.to_string_lossy(), e);
+    }
+    Ok(metadata)
+}
+
+// written to a temporary file next to the cache & renamed into place, so nobody reads a half written cache
+fn write_cache<T: Serialize>(metadata_path: &path::Path, version: MetadataVersion, cache_path: &path::Path, metadata: &T) -> io::Result<()> {
+    if let Some(cache_dir) = cache_path.parent() {
+        fs::create_dir_all(cache_dir)?;
+    }
+    let mut tmp_path = cache_path.to_path_buf();
+    tmp_path.set_extension(format!("ser.{}.tmp", process::id()));
+
+    debug!("writing cache: {}", cache_path.to_string_lossy());
+    let header = CacheHeader::new(metadata_path, version)?;
+    let written = fs::File::create(tmp_path.as_path()).and_then(|cache_file| {
+        let mut bw = io::BufWriter::new(cache_file);
+        bincode::serialize_into(&mut bw, &header).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        bincode::serialize_into(&mut bw, metadata).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        bw.flush()
+    });
+    match written.and_then(|_| fs::rename(tmp_path.as_path(), cache_path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            fs::remove_file(tmp_path.as_path()).ok();
+            Err(e)
+        }
+    }
+}
+
+// same as read_train_metadata, but keeps a bincode copy in the user cache dir that is reused until the json changes
+pub fn read_train_metadata_cached(metadata_path: &path::Path, version: Option<MetadataVersion>) -> io::Result<TrainMetadata> {
+    read_cached(metadata_path, version, read_train_metadata)
+}
+
+pub fn read_test_metadata_cached(metadata_path: &path::Path, version: Option<MetadataVersion>) -> io::Result<TestMetadata> {
+    read_cached(metadata_path, version, read_test_metadata)
+}
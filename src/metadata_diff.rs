@@ -0,0 +1,293 @@
+use crate::TrainMetadata;
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: serde_json::Value,
+    pub after: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordChange {
+    pub id: i32,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RecordDiff {
+    pub unchanged: usize,
+    pub added: Vec<i32>,
+    pub removed: Vec<i32>,
+    pub changed: Vec<RecordChange>,
+    // ids used by more than one record, only the first of those is compared
+    pub duplicated_before: Vec<i32>,
+    pub duplicated_after: Vec<i32>,
+}
+
+impl RecordDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.duplicated_before.is_empty() && self.duplicated_after.is_empty()
+    }
+}
+
+// an image's regions are the regions of its annotations
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegionAssignmentChange {
+    pub image_id: i32,
+    pub before: Vec<i32>,
+    pub after: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetadataDiff {
+    pub before_version: String,
+    pub after_version: String,
+    pub categories: RecordDiff,
+    pub images: RecordDiff,
+    pub annotations: RecordDiff,
+    pub regions: RecordDiff,
+    pub licenses: RecordDiff,
+    pub region_assignments: Vec<RegionAssignmentChange>,
+}
+
+// records by id, an id that occurs more than once keeps its first record and is returned instead of being overwritten
+fn index_records<T>(records: &[T], id: fn(&T) -> i32) -> (collections::BTreeMap<i32, &T>, Vec<i32>) {
+    let mut index = collections::BTreeMap::new();
+    let mut duplicated = collections::BTreeSet::new();
+    for record in records.iter() {
+        match index.entry(id(record)) {
+            collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(record);
+            }
+            collections::btree_map::Entry::Occupied(entry) => {
+                duplicated.insert(*entry.key());
+            }
+        }
+    }
+    (index, duplicated.into_iter().collect())
+}
+
+fn to_fields<T: Serialize>(record: &T) -> serde_json::Map<String, serde_json::Value> {
+    match serde_json::to_value(record).unwrap() {
+        serde_json::Value::Object(fields) => fields,
+        _ => serde_json::Map::new(),
+    }
+}
+
+// the fields are listed through the json form so every field is covered without naming them
+fn field_changes<T: Serialize>(before: &T, after: &T) -> Vec<FieldChange> {
+    let before_fields = to_fields(before);
+    let after_fields = to_fields(after);
+    let mut fields: collections::BTreeSet<&String> = before_fields.keys().collect();
+    fields.extend(after_fields.keys());
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let before_value = before_fields.get(field).cloned().unwrap_or(serde_json::Value::Null);
+            let after_value = after_fields.get(field).cloned().unwrap_or(serde_json::Value::Null);
+            if before_value == after_value {
+                None
+            } else {
+                Some(FieldChange {
+                    field: field.clone(),
+                    before: before_value,
+                    after: after_value,
+                })
+            }
+        })
+        .collect()
+}
+
+// records are compared as they are, only the ones that differ are turned into json
+fn diff_records<T: Serialize + PartialEq>(before: &[T], after: &[T], id: fn(&T) -> i32) -> RecordDiff {
+    let (before_records, duplicated_before) = index_records(before, id);
+    let (after_records, duplicated_after) = index_records(after, id);
+
+    let mut diff = RecordDiff {
+        duplicated_before,
+        duplicated_after,
+        ..RecordDiff::default()
+    };
+    for (record_id, before_record) in before_records.iter() {
+        match after_records.get(record_id) {
+            None => diff.removed.push(*record_id),
+            Some(after_record) if after_record == before_record => diff.unchanged += 1,
+            Some(after_record) => diff.changed.push(RecordChange {
+                id: *record_id,
+                changes: field_changes(*before_record, *after_record),
+            }),
+        }
+    }
+    diff.added = after_records.keys().filter(|e| !before_records.contains_key(e)).cloned().collect();
+    diff
+}
+
+fn regions_by_image(metadata: &TrainMetadata) -> collections::BTreeMap<i32, Vec<i32>> {
+    let mut regions: collections::BTreeMap<i32, Vec<i32>> = collections::BTreeMap::new();
+    for annotation in metadata.annotations.iter() {
+        regions.entry(annotation.image_id).or_insert(Vec::new()).push(annotation.region_id);
+    }
+    for region_ids in regions.values_mut() {
+        region_ids.sort();
+        region_ids.dedup();
+    }
+    regions
+}
+
+impl MetadataDiff {
+    pub fn new(before: &TrainMetadata, after: &TrainMetadata) -> MetadataDiff {
+        let before_regions = regions_by_image(before);
+        let after_regions = regions_by_image(after);
+        let region_assignments = before_regions
+            .iter()
+            .filter_map(|(image_id, region_ids)| match after_regions.get(image_id) {
+                Some(after_region_ids) if after_region_ids != region_ids => Some(RegionAssignmentChange {
+                    image_id: *image_id,
+                    before: region_ids.clone(),
+                    after: after_region_ids.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        MetadataDiff {
+            before_version: format!("{} {}", before.info.year, before.info.version).trim_end().to_string(),
+            after_version: format!("{} {}", after.info.year, after.info.version).trim_end().to_string(),
+            categories: diff_records(&before.categories, &after.categories, |e| e.id),
+            images: diff_records(&before.images, &after.images, |e| e.id),
+            annotations: diff_records(&before.annotations, &after.annotations, |e| e.id),
+            regions: diff_records(&before.regions, &after.regions, |e| e.id),
+            licenses: diff_records(&before.licenses, &after.licenses, |e| e.id),
+            region_assignments,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.categories.is_empty()
+            && self.images.is_empty()
+            && self.annotations.is_empty()
+            && self.regions.is_empty()
+            && self.licenses.is_empty()
+            && self.region_assignments.is_empty()
+    }
+
+    pub fn write(&self, diff_path: &path::Path) -> io::Result<()> {
+        let diff_file = fs::File::create(diff_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(diff_file), self)?;
+        Ok(())
+    }
+
+    // max_details caps the ids & changes listed per section, the counts are always complete
+    pub fn to_text(&self, max_details: usize) -> String {
+        let mut text = String::new();
+        writeln!(text, "before: {}", self.before_version).unwrap();
+        writeln!(text, "after: {}", self.after_version).unwrap();
+        if self.is_empty() {
+            writeln!(text, "no differences").unwrap();
+            return text;
+        }
+
+        let sections = vec![
+            ("categories", &self.categories),
+            ("images", &self.images),
+            ("annotations", &self.annotations),
+            ("regions", &self.regions),
+            ("licenses", &self.licenses),
+        ];
+        for (name, diff) in sections.into_iter() {
+            writeln!(
+                text,
+                "\n{}: {} added, {} removed, {} changed, {} unchanged",
+                name,
+                diff.added.len(),
+                diff.removed.len(),
+                diff.changed.len(),
+                diff.unchanged
+            )
+            .unwrap();
+            if !diff.duplicated_before.is_empty() {
+                writeln!(text, "  duplicate ids before: {}", format_ids(&diff.duplicated_before, max_details)).unwrap();
+            }
+            if !diff.duplicated_after.is_empty() {
+                writeln!(text, "  duplicate ids after: {}", format_ids(&diff.duplicated_after, max_details)).unwrap();
+            }
+            if !diff.added.is_empty() {
+                writeln!(text, "  added: {}", format_ids(&diff.added, max_details)).unwrap();
+            }
+            if !diff.removed.is_empty() {
+                writeln!(text, "  removed: {}", format_ids(&diff.removed, max_details)).unwrap();
+            }
+            for record in diff.changed.iter().take(max_details) {
+                let changes: Vec<String> = record.changes.iter().map(|e| format!("{}: {} -> {}", e.field, e.before, e.after)).collect();
+                writeln!(text, "  changed {}: {}", record.id, changes.join(", ")).unwrap();
+            }
+            if diff.changed.len() > max_details {
+                writeln!(text, "  ... {} more changed", diff.changed.len() - max_details).unwrap();
+            }
+        }
+
+        writeln!(text, "\nregion assignments: {} images changed", self.region_assignments.len()).unwrap();
+        for change in self.region_assignments.iter().take(max_details) {
+            writeln!(text, "  image {}: {:?} -> {:?}", change.image_id, change.before, change.after).unwrap();
+        }
+        if self.region_assignments.len() > max_details {
+            writeln!(text, "  ... {} more", self.region_assignments.len() - max_details).unwrap();
+        }
+        text
+    }
+}
+
+fn format_ids(ids: &[i32], max_details: usize) -> String {
+    let mut formatted: Vec<String> = ids.iter().take(max_details).map(|e| e.to_string()).collect();
+    if ids.len() > max_details {
+        formatted.push(format!("... {} more", ids.len() - max_details));
+    }
+    formatted.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Region;
+
+    fn region(id: i32, name: &str) -> Region {
+        Region { id, name: name.to_string() }
+    }
+
+    #[test]
+    fn only_the_changed_fields_of_changed_records_are_listed() {
+        let before = vec![region(1, "a"), region(2, "b"), region(3, "c")];
+        let after = vec![region(2, "b"), region(3, "d"), region(4, "e")];
+        let diff = diff_records(&before, &after, |e| e.id);
+
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.removed, vec![1]);
+        assert_eq!(diff.added, vec![4]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].id, 3);
+        assert_eq!(diff.changed[0].changes.len(), 1);
+        assert_eq!(diff.changed[0].changes[0].field, "name");
+        assert_eq!(diff.changed[0].changes[0].before, serde_json::json!("c"));
+        assert_eq!(diff.changed[0].changes[0].after, serde_json::json!("d"));
+        assert!(diff.duplicated_before.is_empty() && diff.duplicated_after.is_empty());
+    }
+
+    #[test]
+    fn duplicate_ids_are_reported_and_the_first_record_compared() {
+        let before = vec![region(1, "a"), region(2, "b"), region(1, "x"), region(1, "y")];
+        let after = vec![region(1, "a"), region(2, "b"), region(2, "z")];
+        let diff = diff_records(&before, &after, |e| e.id);
+
+        assert_eq!(diff.duplicated_before, vec![1]);
+        assert_eq!(diff.duplicated_after, vec![2]);
+        assert_eq!(diff.unchanged, 2);
+        assert!(diff.changed.is_empty() && diff.added.is_empty() && diff.removed.is_empty());
+        assert!(!diff.is_empty());
+    }
+}
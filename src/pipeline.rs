@@ -0,0 +1,579 @@
+use crate::border;
+use crate::border::BorderDetectionParams;
+use crate::calibration;
+use crate::calibration::CalibrationParams;
+use crate::manifest::{DatasetPart, ManifestEntry};
+use crate::region::BoundingBox;
+use crate::segmentation;
+use crate::segmentation::{SegmentationAction, SegmentationParams};
+use crate::specimen_label;
+use crate::specimen_label::{LabelAction, LabelParams};
+use crate::visualize;
+use crate::visualize::Annotation;
+use crate::PreprocessError;
+use image::GenericImageView;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use strum_macros::{Display, EnumString};
+
+pub type RgbaImage = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
+
+pub trait Transform: fmt::Debug + Send + Sync {
+    fn name(&self) -> String;
+    fn apply(&self, img: RgbaImage) -> Result<RgbaImage, PreprocessError>;
+
+    // what the step detects in the image it's given, for the debug overlays
+    fn annotate(&self, _img: &RgbaImage) -> Vec<Annotation> {
+        Vec::new()
+    }
+}
+
+// one entry per step of a pipeline spec, e.g. in toml:
+//   [[steps]]
+//   step = "crop"
+//   left = 30
+//   right = 30
+//   top = 80
+//   bottom = 140
+//
+//   [[steps]]
+//   step = "remove_borders"
+//   params = { mode = "auto" }
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum TransformSpec {
+    Crop {
+        left: u32,
+        right: u32,
+        top: u32,
+        bottom: u32,
+    },
+    // preprocessing_step_1
+    RemoveBorders {
+        #[serde(default)]
+        params: BorderDetectionParams,
+    },
+    // preprocessing_step_2
+    CropBottomEdge,
+    // preprocessing_step_3
+    BlankUniformRuns,
+    // preprocessing_step_4
+    BlankGrayBorders {
+        #[serde(default)]
+        params: BorderDetectionParams,
+    },
+    MaskCalibrationTargets {
+        #[serde(default)]
+        params: CalibrationParams,
+    },
+    // labels, stamps & barcodes, masked with the paper color or cropped off with the strip to the nearest edge
+    RemoveSpecimenLabels {
+        #[serde(default)]
+        action: LabelAction,
+        #[serde(default)]
+        params: LabelParams,
+        #[serde(default)]
+        calibration_params: CalibrationParams,
+    },
+    // plant mask by thresholding & morphology, the background masked with the paper color and/or cropped to the specimen
+    SegmentSpecimen {
+        #[serde(default)]
+        action: SegmentationAction,
+        #[serde(default)]
+        params: SegmentationParams,
+        #[serde(default)]
+        calibration_params: CalibrationParams,
+        #[serde(default)]
+        label_params: LabelParams,
+    },
+    Invert,
+    Brighten {
+        value: i32,
+    },
+    Contrast {
+        value: f32,
+    },
+}
+
+impl Transform for TransformSpec {
+    fn name(&self) -> String {
+        match self {
+            TransformSpec::Crop { left, right, top, bottom } => format!("crop({},{},{},{})", left, right, top, bottom),
+            TransformSpec::RemoveBorders { params } => format!("remove_borders({})", params.mode),
+            TransformSpec::CropBottomEdge => "crop_bottom_edge".to_string(),
+            TransformSpec::BlankUniformRuns => "blank_uniform_runs".to_string(),
+            TransformSpec::BlankGrayBorders { .. } => "blank_gray_borders".to_string(),
+            TransformSpec::MaskCalibrationTargets { .. } => "mask_calibration_targets".to_string(),
+            TransformSpec::RemoveSpecimenLabels { action, .. } => format!("remove_specimen_labels({})", action),
+            TransformSpec::SegmentSpecimen { action, params, .. } => format!("segment_specimen({},{})", params.space, action),
+            TransformSpec::Invert => "invert".to_string(),
+            TransformSpec::Brighten { value } => format!("brighten({})", value),
+            TransformSpec::Contrast { value } => format!("contrast({})", value),
+        }
+    }
+
+    fn apply(&self, mut img: RgbaImage) -> Result<RgbaImage, PreprocessError> {
+        match self {
+            TransformSpec::Crop { left, right, top, bottom } => crate::crop_image(image::DynamicImage::ImageRgba8(img), *left, *right, *top, *bottom),
+            TransformSpec::RemoveBorders { params } => border::remove_borders(image::DynamicImage::ImageRgba8(img), params),
+            TransformSpec::CropBottomEdge => crate::preprocessing_step_2(img),
+            TransformSpec::BlankUniformRuns => crate::preprocessing_step_3(img),
+            TransformSpec::BlankGrayBorders { params } => border::blank_gray_borders(img, params),
+            TransformSpec::MaskCalibrationTargets { params } => Ok(calibration::mask_calibration_targets(img, params)),
+            TransformSpec::RemoveSpecimenLabels {
+                action,
+                params,
+                calibration_params,
+            } => specimen_label::remove_labels(img, params, calibration_params, *action),
+            TransformSpec::SegmentSpecimen {
+                action,
+                params,
+                calibration_params,
+                label_params,
+            } => segmentation::segment(img, params, calibration_params, label_params, *action),
+            TransformSpec::Invert => {
+                image::imageops::invert(&mut img);
+                Ok(img)
+            }
+            TransformSpec::Brighten { value } => Ok(image::imageops::brighten(&img, *value)),
+            TransformSpec::Contrast { value } => Ok(image::imageops::contrast(&img, *value)),
+        }
+    }
+
+    fn annotate(&self, img: &RgbaImage) -> Vec<Annotation> {
+        let (width, height) = img.dimensions();
+        let kept = |left: u32, right: u32, top: u32, bottom: u32, color: image::Rgba<u8>| Annotation::Rect {
+            bbox: BoundingBox {
+                x: left,
+                y: top,
+                width: width.saturating_sub(left + right),
+                height: height.saturating_sub(top + bottom),
+            },
+            color,
+        };
+        let boxes = |boxes: Vec<BoundingBox>, color: image::Rgba<u8>| boxes.into_iter().map(|bbox| Annotation::Rect { bbox, color }).collect();
+
+        match self {
+            TransformSpec::Crop { left, right, top, bottom } => vec![kept(*left, *right, *top, *bottom, visualize::BORDER_COLOR)],
+            TransformSpec::RemoveBorders { params } => match border::detect_borders(img, params) {
+                Ok(borders) => vec![kept(borders.left, borders.right, borders.top, borders.bottom, visualize::BORDER_COLOR)],
+                Err(_) => Vec::new(),
+            },
+            TransformSpec::CropBottomEdge => match crate::bottom_edge_cutoff(img) {
+                Ok(cutoff) => vec![Annotation::Row {
+                    y: height - cutoff,
+                    color: visualize::CUTOFF_COLOR,
+                }],
+                Err(_) => Vec::new(),
+            },
+            TransformSpec::BlankGrayBorders { params } => {
+                let margin_x = (params.gray_margin_x * width as f32).round() as u32;
+                let margin_y = (params.gray_margin_y * height as f32).round() as u32;
+                vec![kept(margin_x, margin_x, margin_y, margin_y, visualize::BORDER_COLOR)]
+            }
+            TransformSpec::MaskCalibrationTargets { params } => boxes(calibration::detect_calibration_targets(img, params).boxes(), visualize::CALIBRATION_COLOR),
+            TransformSpec::RemoveSpecimenLabels { params, calibration_params, .. } => boxes(
+                specimen_label::detect_sheet_regions(img, calibration_params, params)
+                    .labels
+                    .into_iter()
+                    .map(|e| e.bbox)
+                    .collect(),
+                visualize::LABEL_COLOR,
+            ),
+            TransformSpec::SegmentSpecimen {
+                params,
+                calibration_params,
+                label_params,
+                ..
+            } => {
+                let exclude = segmentation::excluded_regions(img, params, calibration_params, label_params);
+                let result = segmentation::segment_specimen(img, params, &exclude);
+                let mut annotations = vec![Annotation::Mask {
+                    mask: result.mask,
+                    color: visualize::SEGMENTATION_COLOR,
+                }];
+                annotations.extend(exclude.into_iter().map(|bbox| Annotation::Rect {
+                    bbox,
+                    color: visualize::LABEL_COLOR,
+                }));
+                if let Some(bbox) = result.bbox {
+                    annotations.push(Annotation::Rect {
+                        bbox,
+                        color: visualize::SEGMENTATION_COLOR,
+                    });
+                }
+                annotations
+            }
+            TransformSpec::BlankUniformRuns | TransformSpec::Invert | TransformSpec::Brighten { .. } | TransformSpec::Contrast { .. } => Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for image::imageops::FilterType {
+    fn from(filter: ResizeFilter) -> image::imageops::FilterType {
+        match filter {
+            ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+            ResizeFilter::Triangle => image::imageops::FilterType::Triangle,
+            ResizeFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+            ResizeFilter::Gaussian => image::imageops::FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ColorSpace {
+    Grayscale,
+    Rgb,
+}
+
+impl ColorSpace {
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorSpace::Grayscale => 1,
+            ColorSpace::Rgb => 3,
+        }
+    }
+}
+
+// the steps run on the full size image, the resize to the output size & the color space conversion always come last
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PipelineSpec {
+    #[serde(default)]
+    pub steps: Vec<TransformSpec>,
+    pub filter: ResizeFilter,
+    pub color_space: ColorSpace,
+}
+
+impl Default for PipelineSpec {
+    // what serialize_train_and_label_data has always done
+    fn default() -> PipelineSpec {
+        PipelineSpec {
+            steps: vec![
+                TransformSpec::RemoveBorders {
+                    params: BorderDetectionParams::default(),
+                },
+                TransformSpec::CropBottomEdge,
+            ],
+            filter: ResizeFilter::CatmullRom,
+            color_space: ColorSpace::Grayscale,
+        }
+    }
+}
+
+impl PipelineSpec {
+    pub fn read(spec_path: &path::Path) -> io::Result<PipelineSpec> {
+        read_config(spec_path)
+    }
+}
+
+// toml when the extension says so, json otherwise
+pub fn read_config<T: DeserializeOwned>(config_path: &path::Path) -> io::Result<T> {
+    let content = fs::read_to_string(config_path)?;
+    match config_path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(content.as_str()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+        _ => Ok(serde_json::from_str(content.as_str())?),
+    }
+}
+
+// written next to the serialized data, the hash covers the spec and the output size
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PipelineRecord {
+    pub spec: PipelineSpec,
+    pub width: u32,
+    pub height: u32,
+    pub hash: String,
+}
+
+impl PipelineRecord {
+    pub fn file_name(width: u32, height: u32) -> String {
+        format!("herbarium-pipeline-{}x{}.json", width, height)
+    }
+
+    pub fn read(record_path: &path::Path) -> io::Result<PipelineRecord> {
+        let record_file = fs::File::open(record_path)?;
+        Ok(serde_json::from_reader(io::BufReader::new(record_file))?)
+    }
+
+    pub fn write(&self, record_path: &path::Path) -> io::Result<()> {
+        let record_file = fs::File::create(record_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(record_file), self)?;
+        Ok(())
+    }
+
+    // the features per row the serialized data was written with
+    pub fn feature_count(&self) -> usize {
+        Pipeline::new(self.spec.clone(), self.width, self.height).feature_count()
+    }
+}
+
+// None when nothing has been serialized into the directory at this size yet
+pub fn read_pipeline_record(serialization_dir: &path::Path, width: u32, height: u32) -> io::Result<Option<PipelineRecord>> {
+    let mut record_path = serialization_dir.to_path_buf();
+    record_path.push(PipelineRecord::file_name(width, height));
+    if !record_path.exists() {
+        return Ok(None);
+    }
+    debug!("reading: {}", record_path.to_string_lossy());
+    PipelineRecord::read(record_path.as_path()).map(Some)
+}
+
+// fnv-1a, unlike DefaultHasher it is the same on every build
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    spec: PipelineSpec,
+    width: u32,
+    height: u32,
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new(spec: PipelineSpec, width: u32, height: u32) -> Pipeline {
+        let transforms = spec.steps.iter().map(|e| Box::new(e.clone()) as Box<dyn Transform>).collect();
+        Pipeline { spec, width, height, transforms }
+    }
+
+    pub fn spec(&self) -> &PipelineSpec {
+        &self.spec
+    }
+
+    pub fn transforms(&self) -> &[Box<dyn Transform>] {
+        &self.transforms
+    }
+
+    pub fn hash(&self) -> String {
+        let canonical = serde_json::to_string(&(&self.spec, self.width, self.height)).unwrap();
+        format!("{:016x}", fnv1a(canonical.as_bytes()))
+    }
+
+    pub fn record(&self) -> PipelineRecord {
+        PipelineRecord {
+            spec: self.spec.clone(),
+            width: self.width,
+            height: self.height,
+            hash: self.hash(),
+        }
+    }
+
+    pub fn apply(&self, img: image::DynamicImage) -> Result<RgbaImage, PreprocessError> {
+        self.transforms.iter().try_fold(to_rgba(img), |img, transform| transform.apply(img))
+    }
+
+    // apply, keeping every intermediate image along with an overlay of what each step detected in it & the pixels it changed
+    // the stages up to a failing step are returned with the error, those are the ones worth looking at
+    pub fn apply_debug(&self, img: image::DynamicImage) -> (Vec<DebugStage>, Result<RgbaImage, PreprocessError>) {
+        let mut img = to_rgba(img);
+        let mut stages = vec![DebugStage {
+            name: "input".to_string(),
+            image: img.clone(),
+            overlay: None,
+        }];
+        for transform in self.transforms.iter() {
+            let mut annotations = transform.annotate(&img);
+            let output = match transform.apply(img.clone()) {
+                Ok(output) => output,
+                Err(e) => {
+                    stages.last_mut().unwrap().overlay = Some(visualize::draw_annotations(&img, &annotations));
+                    return (stages, Err(e));
+                }
+            };
+            if let Some(mask) = visualize::changed_pixels(&img, &output) {
+                annotations.insert(
+                    0,
+                    Annotation::Mask {
+                        mask,
+                        color: visualize::CHANGED_COLOR,
+                    },
+                );
+            }
+            // the overlay goes with the image the step was given, that's where its detections are
+            stages.last_mut().unwrap().overlay = Some(visualize::draw_annotations(&img, &annotations));
+            stages.push(DebugStage {
+                name: transform.name(),
+                image: output.clone(),
+                overlay: None,
+            });
+            img = output;
+        }
+        (stages, Ok(img))
+    }
+
+    pub fn resize(&self, img: &RgbaImage) -> RgbaImage {
+        image::imageops::resize(img, self.width, self.height, self.spec.filter.into())
+    }
+
+    // column by column, the order the serialized data has always used
+    pub fn features(&self, img: &RgbaImage) -> Vec<f32> {
+        let mut features = Vec::with_capacity((img.width() * img.height()) as usize * self.spec.color_space.channels());
+        match self.spec.color_space {
+            ColorSpace::Grayscale => {
+                let img = image::imageops::grayscale(img);
+                for x in 0..img.width() {
+                    for y in 0..img.height() {
+                        features.push(img.get_pixel(x, y)[0] as f32 / 255.0);
+                    }
+                }
+            }
+            ColorSpace::Rgb => {
+                for x in 0..img.width() {
+                    for y in 0..img.height() {
+                        let pixel = img.get_pixel(x, y);
+                        features.push(pixel[0] as f32 / 255.0);
+                        features.push(pixel[1] as f32 / 255.0);
+                        features.push(pixel[2] as f32 / 255.0);
+                    }
+                }
+            }
+        }
+        features
+    }
+
+    pub fn feature_count(&self) -> usize {
+        (self.width * self.height) as usize * self.spec.color_space.channels()
+    }
+
+    // the resized image in the pipeline's color space, for writing normalized images out
+    pub fn render(&self, img: &RgbaImage) -> image::DynamicImage {
+        match self.spec.color_space {
+            ColorSpace::Grayscale => image::DynamicImage::ImageLuma8(image::imageops::grayscale(img)),
+            ColorSpace::Rgb => image::DynamicImage::ImageRgba8(img.clone()),
+        }
+    }
+
+    pub fn process(&self, img: image::DynamicImage) -> Result<Vec<f32>, PreprocessError> {
+        Ok(self.features(&self.resize(&self.apply(img)?)))
+    }
+}
+
+fn to_rgba(img: image::DynamicImage) -> RgbaImage {
+    match img {
+        image::DynamicImage::ImageRgba8(img) => img,
+        img => RgbaImage::from_fn(img.width(), img.height(), |x, y| img.get_pixel(x, y)),
+    }
+}
+
+// the image after a step, the overlay shows what the next step did to it, the last stage has none
+#[derive(Debug, Clone)]
+pub struct DebugStage {
+    pub name: String,
+    pub image: RgbaImage,
+    pub overlay: Option<RgbaImage>,
+}
+
+impl DebugStage {
+    // file name friendly, numbered in pipeline order
+    pub fn file_stem(&self, idx: usize) -> String {
+        let name: String = self.name.chars().map(|e| if e.is_ascii_alphanumeric() || e == '_' { e } else { '-' }).collect();
+        format!("{:02}-{}", idx, name.trim_end_matches('-'))
+    }
+}
+
+// <nn>-<step>.png & <nn>-<step>-overlay.png for every stage
+pub fn write_debug_stages(debug_dir: &path::Path, stages: &[DebugStage]) -> io::Result<()> {
+    fs::create_dir_all(debug_dir)?;
+    let save = |img: &RgbaImage, file_name: String| {
+        let mut image_path = debug_dir.to_path_buf();
+        image_path.push(file_name);
+        img.save(image_path.as_path()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    };
+    for (idx, stage) in stages.iter().enumerate() {
+        let file_stem = stage.file_stem(idx);
+        save(&stage.image, format!("{}.png", file_stem))?;
+        if let Some(ref overlay) = stage.overlay {
+            save(overlay, format!("{}-overlay.png", file_stem))?;
+        }
+    }
+    Ok(())
+}
+
+// the spec given on the command line, otherwise the binary's default
+pub fn load_spec(spec_path: Option<&path::Path>, default: PipelineSpec) -> io::Result<PipelineSpec> {
+    match spec_path {
+        Some(spec_path) => {
+            info!("reading: {}", spec_path.to_string_lossy());
+            PipelineSpec::read(spec_path)
+        }
+        None => Ok(default),
+    }
+}
+
+// an image a batch run skipped, step is the preprocessing step that failed or "open" when the image couldn't be read at all
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreprocessFailure {
+    pub image_id: i32,
+    pub file_path: String,
+    pub split: DatasetPart,
+    pub step: String,
+    pub reason: String,
+}
+
+impl PreprocessFailure {
+    pub fn new(entry: &ManifestEntry, step: String, reason: String) -> PreprocessFailure {
+        PreprocessFailure {
+            image_id: entry.image_id,
+            file_path: entry.file_path.clone(),
+            split: entry.split,
+            step,
+            reason,
+        }
+    }
+}
+
+// opens & runs the pipeline on a manifest image, anything that goes wrong is turned into the failure to report for it
+pub fn open_and_apply(pipeline: &Pipeline, base_dir: &path::Path, entry: &ManifestEntry) -> Result<RgbaImage, PreprocessFailure> {
+    let mut image_path = base_dir.to_path_buf();
+    image_path.push(&entry.file_path);
+    let img = image::open(image_path.as_path()).map_err(|e| PreprocessFailure::new(entry, "open".to_string(), e.to_string()))?;
+    pipeline.apply(img).map_err(|e| PreprocessFailure::new(entry, e.step().to_string(), e.to_string()))
+}
+
+pub fn write_failures(failures_path: &path::Path, failures: &[PreprocessFailure]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_path(failures_path)?;
+    for failure in failures.iter() {
+        writer.serialize(failure)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_failures(failures_path: &path::Path) -> io::Result<Vec<PreprocessFailure>> {
+    let mut reader = csv::Reader::from_path(failures_path)?;
+    Ok(reader.deserialize().collect::<Result<Vec<PreprocessFailure>, csv::Error>>()?)
+}
+
+// the failures the serializer skipped for a part, none when it didn't write a failures file
+pub fn read_part_failures(serialization_dir: &path::Path, part: DatasetPart, width: u32, height: u32) -> io::Result<Vec<PreprocessFailure>> {
+    let mut failures_path = serialization_dir.to_path_buf();
+    failures_path.push(part.failures_file_name(width, height));
+    if !failures_path.exists() {
+        return Ok(Vec::new());
+    }
+    debug!("reading: {}", failures_path.to_string_lossy());
+    read_failures(failures_path.as_path())
+}
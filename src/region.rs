@@ -0,0 +1,497 @@
+use crate::pipeline::RgbaImage;
+use serde::{Deserialize, Serialize};
+use std::collections;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl BoundingBox {
+    pub fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    pub fn area(&self) -> u32 {
+        self.width * self.height
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.x < other.right() && other.x < self.right() && self.y < other.bottom() && other.y < self.bottom()
+    }
+
+    pub fn intersection(&self, other: &BoundingBox) -> Option<BoundingBox> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        Some(BoundingBox {
+            x,
+            y,
+            width: self.right().min(other.right()) - x,
+            height: self.bottom().min(other.bottom()) - y,
+        })
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        BoundingBox {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+
+    // grown by padding on every side, clipped to the image
+    pub fn padded(&self, padding: u32, width: u32, height: u32) -> BoundingBox {
+        let x = self.x.saturating_sub(padding);
+        let y = self.y.saturating_sub(padding);
+        BoundingBox {
+            x,
+            y,
+            width: (self.right() + padding).min(width) - x,
+            height: (self.bottom() + padding).min(height) - y,
+        }
+    }
+}
+
+// overlapping boxes are merged until none overlap
+pub fn merge_overlapping(mut boxes: Vec<BoundingBox>) -> Vec<BoundingBox> {
+    let mut merged = true;
+    while merged {
+        merged = false;
+        let mut result: Vec<BoundingBox> = Vec::with_capacity(boxes.len());
+        for bbox in boxes.into_iter() {
+            match result.iter_mut().find(|e| e.intersects(&bbox)) {
+                Some(existing) => {
+                    *existing = existing.union(&bbox);
+                    merged = true;
+                }
+                None => result.push(bbox),
+            }
+        }
+        boxes = result;
+    }
+    boxes.sort_by_key(|e| (e.y, e.x));
+    boxes
+}
+
+// one flag per pixel, row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mask {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<bool>,
+}
+
+impl Mask {
+    pub fn new(width: u32, height: u32) -> Mask {
+        Mask {
+            width,
+            height,
+            data: vec![false; (width * height) as usize],
+        }
+    }
+
+    pub fn from_fn<F: Fn(u32, u32) -> bool>(width: u32, height: u32, f: F) -> Mask {
+        let mut mask = Mask::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                mask.data[(y * width + x) as usize] = f(x, y);
+            }
+        }
+        mask
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> bool {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, value: bool) {
+        self.data[(y * self.width + x) as usize] = value;
+    }
+
+    pub fn count(&self) -> usize {
+        self.data.iter().filter(|e| **e).count()
+    }
+}
+
+// square structuring element of the given radius, separable so the cost doesn't grow with the radius
+// pixels outside the image are ignored rather than counted as set or unset
+fn morphology(mask: &Mask, radius: u32, dilate: bool) -> Mask {
+    let (width, height) = (mask.width as usize, mask.height as usize);
+    let radius = radius as usize;
+    let pass = |get: &dyn Fn(usize, usize) -> bool, lines: usize, length: usize| -> Vec<bool> {
+        let mut result = vec![false; lines * length];
+        let mut prefix = vec![0usize; length + 1];
+        for line in 0..lines {
+            for idx in 0..length {
+                prefix[idx + 1] = prefix[idx] + get(line, idx) as usize;
+            }
+            for idx in 0..length {
+                let start = idx.saturating_sub(radius);
+                let end = (idx + radius + 1).min(length);
+                let set = prefix[end] - prefix[start];
+                result[line * length + idx] = if dilate { set > 0 } else { set == end - start };
+            }
+        }
+        result
+    };
+
+    let rows = pass(&|y, x| mask.data[y * width + x], height, width);
+    let columns = pass(&|x, y| rows[y * width + x], width, height);
+    Mask::from_fn(mask.width, mask.height, |x, y| columns[x as usize * height + y as usize])
+}
+
+pub fn dilate(mask: &Mask, radius: u32) -> Mask {
+    morphology(mask, radius, true)
+}
+
+pub fn erode(mask: &Mask, radius: u32) -> Mask {
+    morphology(mask, radius, false)
+}
+
+// removes specks smaller than the element
+pub fn open(mask: &Mask, radius: u32) -> Mask {
+    dilate(&erode(mask, radius), radius)
+}
+
+// fills gaps narrower than the element
+pub fn close(mask: &Mask, radius: u32) -> Mask {
+    erode(&dilate(mask, radius), radius)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Component {
+    pub label: u32,
+    pub bbox: BoundingBox,
+    pub area: u32,
+}
+
+// labels holds the component label of every pixel, 0 for pixels outside the mask, labels start at 1
+#[derive(Debug, Clone)]
+pub struct Components {
+    pub width: u32,
+    pub height: u32,
+    pub labels: Vec<u32>,
+    pub components: Vec<Component>,
+}
+
+impl Components {
+    pub fn label(&self, x: u32, y: u32) -> u32 {
+        self.labels[(y * self.width + x) as usize]
+    }
+
+    // the (x, y) of every pixel of a component
+    pub fn pixels(&self, component: &Component) -> Vec<(u32, u32)> {
+        let bbox = component.bbox;
+        let mut pixels = Vec::with_capacity(component.area as usize);
+        for y in bbox.y..bbox.bottom() {
+            for x in bbox.x..bbox.right() {
+                if self.label(x, y) == component.label {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+}
+
+// 4-connected, flood filled with an explicit stack so large regions don't overflow
+pub fn connected_components(mask: &Mask) -> Components {
+    let (width, height) = (mask.width, mask.height);
+    let mut labels = vec![0u32; mask.data.len()];
+    let mut components = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..mask.data.len() {
+        if !mask.data[start] || labels[start] != 0 {
+            continue;
+        }
+        let label = components.len() as u32 + 1;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+        let mut area = 0;
+
+        labels[start] = label;
+        stack.push(start);
+        while let Some(idx) = stack.pop() {
+            let (x, y) = (idx as u32 % width, idx as u32 / width);
+            area += 1;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+
+            let mut visit = |neighbor: usize| {
+                if mask.data[neighbor] && labels[neighbor] == 0 {
+                    labels[neighbor] = label;
+                    stack.push(neighbor);
+                }
+            };
+            if x > 0 {
+                visit(idx - 1);
+            }
+            if x + 1 < width {
+                visit(idx + 1);
+            }
+            if y > 0 {
+                visit(idx - width as usize);
+            }
+            if y + 1 < height {
+                visit(idx + width as usize);
+            }
+        }
+
+        components.push(Component {
+            label,
+            bbox: BoundingBox {
+                x: min_x,
+                y: min_y,
+                width: max_x - min_x + 1,
+                height: max_y - min_y + 1,
+            },
+            area,
+        });
+    }
+
+    Components {
+        width,
+        height,
+        labels,
+        components,
+    }
+}
+
+// hue in degrees, saturation & value in 0..1
+pub fn rgb_to_hsv(pixel: image::Rgba<u8>) -> (f32, f32, f32) {
+    let (r, g, b) = (pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * (((g - b) / delta) % 6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    ((hue + 360.0) % 360.0, saturation, max)
+}
+
+// CIE L*a*b* under D65, L in 0..100
+pub fn rgb_to_lab(pixel: image::Rgba<u8>) -> (f32, f32, f32) {
+    let linear = |channel: u8| {
+        let c = channel as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+// pixel count & channel sums
+type ColorBin = (u64, u64, u64, u64);
+
+// the most common color after quantizing to 16 levels per channel, the paper dominates a sheet so that's the paper color
+pub fn background_color(img: &RgbaImage) -> image::Rgba<u8> {
+    let mut bins: collections::BTreeMap<(u8, u8, u8), ColorBin> = collections::BTreeMap::new();
+    for pixel in img.pixels() {
+        let bin = bins.entry((pixel[0] / 16, pixel[1] / 16, pixel[2] / 16)).or_insert((0, 0, 0, 0));
+        bin.0 += 1;
+        bin.1 += pixel[0] as u64;
+        bin.2 += pixel[1] as u64;
+        bin.3 += pixel[2] as u64;
+    }
+    match bins.values().max_by_key(|e| e.0) {
+        Some((count, red, green, blue)) => image::Rgba([(red / count) as u8, (green / count) as u8, (blue / count) as u8, 255]),
+        None => image::Rgba([255, 255, 255, 255]),
+    }
+}
+
+pub fn fill(img: &mut RgbaImage, bbox: &BoundingBox, color: image::Rgba<u8>) {
+    for y in bbox.y..bbox.bottom().min(img.height()) {
+        for x in bbox.x..bbox.right().min(img.width()) {
+            img.put_pixel(x, y, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // '#' is set, anything else unset
+    fn mask(rows: &[&str]) -> Mask {
+        Mask::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| rows[y as usize].as_bytes()[x as usize] == b'#')
+    }
+
+    fn bbox(x: u32, y: u32, width: u32, height: u32) -> BoundingBox {
+        BoundingBox { x, y, width, height }
+    }
+
+    #[test]
+    fn padded_grows_on_every_side() {
+        assert_eq!(bbox(20, 30, 40, 50).padded(5, 100, 100), bbox(15, 25, 50, 60));
+        assert_eq!(bbox(20, 30, 40, 50).padded(0, 100, 100), bbox(20, 30, 40, 50));
+    }
+
+    #[test]
+    fn padded_is_clipped_to_the_image() {
+        assert_eq!(bbox(1, 0, 3, 2).padded(3, 6, 4), bbox(0, 0, 6, 4));
+        assert_eq!(bbox(90, 2, 10, 10).padded(4, 100, 20), bbox(86, 0, 14, 16));
+    }
+
+    #[test]
+    fn intersection_of_overlapping_boxes() {
+        assert_eq!(bbox(0, 0, 10, 10).intersection(&bbox(5, 8, 10, 10)), Some(bbox(5, 8, 5, 2)));
+        assert_eq!(bbox(5, 8, 10, 10).intersection(&bbox(0, 0, 10, 10)), Some(bbox(5, 8, 5, 2)));
+        assert_eq!(bbox(0, 0, 10, 10).intersection(&bbox(2, 3, 4, 5)), Some(bbox(2, 3, 4, 5)));
+    }
+
+    #[test]
+    fn boxes_that_only_touch_dont_intersect() {
+        assert_eq!(bbox(0, 0, 10, 10).intersection(&bbox(10, 0, 10, 10)), None);
+        assert_eq!(bbox(0, 0, 10, 10).intersection(&bbox(0, 10, 10, 10)), None);
+        assert_eq!(bbox(0, 0, 10, 10).intersection(&bbox(20, 20, 5, 5)), None);
+    }
+
+    #[test]
+    fn dilate_grows_a_pixel_into_a_square() {
+        let dot = mask(&[".....", ".....", "..#..", ".....", "....."]);
+        assert_eq!(dilate(&dot, 1), mask(&[".....", ".###.", ".###.", ".###.", "....."]));
+        assert_eq!(dilate(&dot, 0), dot);
+    }
+
+    #[test]
+    fn dilate_stops_at_the_image_edge() {
+        let corner = mask(&["#...", "....", "...."]);
+        assert_eq!(dilate(&corner, 1), mask(&["##..", "##..", "...."]));
+    }
+
+    #[test]
+    fn erode_shrinks_a_square_into_a_pixel() {
+        let square = mask(&[".....", ".###.", ".###.", ".###.", "....."]);
+        assert_eq!(erode(&square, 1), mask(&[".....", ".....", "..#..", ".....", "....."]));
+        assert_eq!(erode(&square, 2).count(), 0);
+    }
+
+    #[test]
+    fn erode_ignores_pixels_outside_the_image() {
+        let full = mask(&["###", "###"]);
+        assert_eq!(erode(&full, 1), full);
+    }
+
+    #[test]
+    fn open_removes_specks_and_keeps_blocks() {
+        let speckled = mask(&[
+            "#........", //
+            ".........",
+            "....###..",
+            "....###..",
+            "....###..",
+            ".#.......",
+        ]);
+        let expected = mask(&[
+            ".........", //
+            ".........",
+            "....###..",
+            "....###..",
+            "....###..",
+            ".........",
+        ]);
+        assert_eq!(open(&speckled, 1), expected);
+    }
+
+    #[test]
+    fn close_fills_narrow_gaps() {
+        let split = mask(&[
+            "...........", //
+            "...........",
+            "..###.###..",
+            "..###.###..",
+            "..###.###..",
+            "...........",
+            "...........",
+        ]);
+        let expected = mask(&[
+            "...........", //
+            "...........",
+            "..#######..",
+            "..#######..",
+            "..#######..",
+            "...........",
+            "...........",
+        ]);
+        assert_eq!(close(&split, 1), expected);
+    }
+
+    #[test]
+    fn connected_components_are_four_connected() {
+        let grid = [
+            "##...", //
+            "#..#.", //
+            "..##.", //
+            ".#.##", //
+        ];
+        let components = connected_components(&mask(&grid));
+        assert_eq!(
+            components.components,
+            vec![
+                Component {
+                    label: 1,
+                    bbox: bbox(0, 0, 2, 2),
+                    area: 3
+                },
+                Component {
+                    label: 2,
+                    bbox: bbox(2, 1, 3, 3),
+                    area: 5
+                },
+                Component {
+                    label: 3,
+                    bbox: bbox(1, 3, 1, 1),
+                    area: 1
+                },
+            ]
+        );
+        // diagonal to component 2, still its own component
+        assert_eq!(components.label(1, 3), 3);
+        assert_eq!(components.label(2, 2), 2);
+        assert_eq!(components.label(2, 3), 0);
+        assert_eq!(components.pixels(&components.components[0]), vec![(0, 0), (1, 0), (0, 1)]);
+    }
+
+    #[test]
+    fn connected_components_of_an_empty_mask() {
+        let components = connected_components(&Mask::new(4, 3));
+        assert!(components.components.is_empty());
+        assert!(components.labels.iter().all(|e| *e == 0));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::split;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use strum_macros::{Display, EnumString};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum Weighting {
+    None,
+    InverseFrequency,
+    SqrtInverseFrequency,
+}
+
+// max_per_class caps the common classes, oversample_to repeats (augmented) images of the rare classes until they reach it, 0 turns either off
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SamplingConfig {
+    pub seed: u64,
+    pub max_per_class: usize,
+    pub oversample_to: usize,
+    pub weighting: Weighting,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> SamplingConfig {
+        SamplingConfig {
+            seed: 0,
+            max_per_class: 0,
+            oversample_to: 0,
+            weighting: Weighting::None,
+        }
+    }
+}
+
+// the originals are always Identity, the oversampled copies cycle through the others so no two copies of an image are the same
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Augmentation {
+    Identity,
+    FlipHorizontal,
+    FlipVertical,
+    Brighten(i32),
+}
+
+const COPY_AUGMENTATIONS: [Augmentation; 6] = [
+    Augmentation::FlipHorizontal,
+    Augmentation::FlipVertical,
+    Augmentation::Brighten(15),
+    Augmentation::Brighten(-15),
+    Augmentation::Brighten(30),
+    Augmentation::Brighten(-30),
+];
+
+impl Augmentation {
+    pub fn apply(&self, img: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> image::ImageBuffer<image::Rgba<u8>, Vec<u8>> {
+        match self {
+            Augmentation::Identity => img,
+            Augmentation::FlipHorizontal => image::imageops::flip_horizontal(&img),
+            Augmentation::FlipVertical => image::imageops::flip_vertical(&img),
+            Augmentation::Brighten(value) => image::imageops::brighten(&img, *value),
+        }
+    }
+}
+
+impl fmt::Display for Augmentation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Augmentation::Identity => write!(f, "identity"),
+            Augmentation::FlipHorizontal => write!(f, "flip_horizontal"),
+            Augmentation::FlipVertical => write!(f, "flip_vertical"),
+            Augmentation::Brighten(value) => write!(f, "brighten({})", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample<'a> {
+    pub entry: &'a ManifestEntry,
+    pub augmentation: Augmentation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassSampling {
+    pub category_id: i32,
+    pub available: usize,
+    pub sampled: usize,
+    pub weight: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SamplingReport {
+    pub config: SamplingConfig,
+    pub classes: Vec<ClassSampling>,
+}
+
+impl SamplingReport {
+    pub fn file_name(width: u32, height: u32) -> String {
+        format!("herbarium-sampling-{}x{}.json", width, height)
+    }
+
+    pub fn write(&self, report_path: &path::Path) -> io::Result<()> {
+        let report_file = fs::File::create(report_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(report_file), self)?;
+        Ok(())
+    }
+}
+
+// returns the samples grouped by category (in category order) so the rows come out the same way they always have
+pub fn plan_samples<'a>(entries: Vec<&'a ManifestEntry>, config: &SamplingConfig) -> Vec<Sample<'a>> {
+    let mut entries_by_category: collections::BTreeMap<i32, Vec<&ManifestEntry>> = collections::BTreeMap::new();
+    for entry in entries.into_iter() {
+        entries_by_category.entry(entry.category_id.unwrap_or(-1)).or_insert(Vec::new()).push(entry);
+    }
+
+    let mut samples = Vec::new();
+    for (category_id, mut class_entries) in entries_by_category.into_iter() {
+        if config.max_per_class > 0 && class_entries.len() > config.max_per_class {
+            let mut rng = split::class_rng(config.seed, category_id);
+            class_entries.shuffle(&mut rng);
+            class_entries.truncate(config.max_per_class);
+            class_entries.sort_by_key(|e| e.image_id);
+        }
+
+        samples.extend(class_entries.iter().map(|entry| Sample {
+            entry,
+            augmentation: Augmentation::Identity,
+        }));
+
+        if config.oversample_to > class_entries.len() {
+            let copies = config.oversample_to - class_entries.len();
+            for copy in 0..copies {
+                samples.push(Sample {
+                    entry: class_entries[copy % class_entries.len()],
+                    augmentation: COPY_AUGMENTATIONS[(copy / class_entries.len()) % COPY_AUGMENTATIONS.len()],
+                });
+            }
+        }
+    }
+    samples
+}
+
+// one weight per row, normalized so the weights average to 1
+pub fn sample_weights(labels: &[f32], weighting: Weighting) -> Vec<f32> {
+    let mut counts: collections::HashMap<i64, usize> = collections::HashMap::new();
+    for label in labels.iter() {
+        *counts.entry(*label as i64).or_insert(0) += 1;
+    }
+
+    let raw: Vec<f32> = labels
+        .iter()
+        .map(|label| {
+            let count = counts[&(*label as i64)] as f32;
+            match weighting {
+                Weighting::None => 1.0,
+                Weighting::InverseFrequency => 1.0 / count,
+                Weighting::SqrtInverseFrequency => 1.0 / count.sqrt(),
+            }
+        })
+        .collect();
+
+    let mean = raw.iter().sum::<f32>() / raw.len().max(1) as f32;
+    raw.into_iter().map(|e| e / mean).collect()
+}
+
+// built from the rows that were actually written, a sample whose image failed to preprocess has no rows
+// weights line up with the rows and every written sample is rows_per_sample consecutive rows
+pub fn report(rows: &Manifest, available: &[&ManifestEntry], weights: &[f32], config: &SamplingConfig, rows_per_sample: usize) -> SamplingReport {
+    let mut classes: collections::BTreeMap<i32, ClassSampling> = collections::BTreeMap::new();
+    for entry in available.iter() {
+        let category_id = entry.category_id.unwrap_or(-1);
+        classes
+            .entry(category_id)
+            .or_insert(ClassSampling {
+                category_id,
+                available: 0,
+                sampled: 0,
+                weight: 0.0,
+            })
+            .available += 1;
+    }
+
+    let mut rows_by_category: collections::HashMap<i32, usize> = collections::HashMap::new();
+    for (row, weight) in rows.entries.iter().zip(weights.iter()) {
+        let category_id = row.category_id.unwrap_or(-1);
+        *rows_by_category.entry(category_id).or_insert(0) += 1;
+        if let Some(class) = classes.get_mut(&category_id) {
+            class.weight = *weight;
+        }
+    }
+    for (category_id, row_count) in rows_by_category.into_iter() {
+        if let Some(class) = classes.get_mut(&category_id) {
+            class.sampled = row_count / rows_per_sample.max(1);
+        }
+    }
+
+    SamplingReport {
+        config: config.clone(),
+        classes: classes.into_iter().map(|(_, e)| e).collect(),
+    }
+}
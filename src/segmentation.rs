@@ -0,0 +1,334 @@
+use crate::calibration::CalibrationParams;
+use crate::pipeline::RgbaImage;
+use crate::region;
+use crate::region::{BoundingBox, Component, Mask};
+use crate::specimen_label;
+use crate::specimen_label::LabelParams;
+use crate::{PreprocessError, PreprocessStep};
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SegmentationSpace {
+    // HSV saturation, the plant is saturated & the paper isn't
+    Hsv,
+    // Lab distance from the paper color, also picks up dark & faded material the saturation misses
+    Lab,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SegmentationAction {
+    // everything outside the plant mask is painted with the paper color
+    #[default]
+    Mask,
+    // cropped to the specimen bounding box
+    Crop,
+    MaskAndCrop,
+}
+
+// radii & padding are fractions of the image width, areas fractions of the image area
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SegmentationParams {
+    pub space: SegmentationSpace,
+    // hsv: the saturation of pixels darker than this is too noisy to count
+    pub min_value: f32,
+    // a fixed threshold on the 0..255 foreground score, otsu's threshold when unset
+    pub threshold: Option<u8>,
+    // otsu splits whatever is there, on a sheet with next to no plant that's paper grain, so it's never taken below this
+    pub min_threshold: u8,
+    pub open_radius: f32,
+    pub close_radius: f32,
+    pub min_component_area: f32,
+    // thin strips along an image edge are scanner bed or sheet edge, a strip touches a side, is at most max_edge_strip_thickness across
+    // and runs along at least min_edge_strip_span of it, the specimen itself often reaches the edge too & is kept
+    pub drop_edge_components: bool,
+    pub max_edge_strip_thickness: f32,
+    pub min_edge_strip_span: f32,
+    // color bars, rulers & labels are left out of the mask, detected with the calibration & label params
+    pub exclude_sheet_regions: bool,
+    // added around the specimen bounding box
+    pub padding: f32,
+}
+
+impl Default for SegmentationParams {
+    fn default() -> SegmentationParams {
+        SegmentationParams {
+            space: SegmentationSpace::Hsv,
+            min_value: 0.2,
+            threshold: None,
+            min_threshold: 40,
+            open_radius: 0.002,
+            close_radius: 0.005,
+            min_component_area: 0.001,
+            drop_edge_components: true,
+            max_edge_strip_thickness: 0.05,
+            min_edge_strip_span: 0.5,
+            exclude_sheet_regions: true,
+            padding: 0.01,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Segmentation {
+    pub mask: Mask,
+    pub threshold: u8,
+    // the components kept in the mask
+    pub components: Vec<Component>,
+    // the padded union of the components, None when nothing was kept
+    pub bbox: Option<BoundingBox>,
+}
+
+impl Segmentation {
+    pub fn foreground_share(&self) -> f32 {
+        self.mask.count() as f32 / self.mask.data.len().max(1) as f32
+    }
+}
+
+fn scaled(fraction: f32, size: u32) -> u32 {
+    (fraction.max(0.0) * size as f32).round() as u32
+}
+
+// 0 for paper, up to 255 for whatever is least like paper
+pub fn foreground_scores(img: &RgbaImage, params: &SegmentationParams) -> Vec<u8> {
+    match params.space {
+        SegmentationSpace::Hsv => img
+            .pixels()
+            .map(|e| {
+                let (_, saturation, value) = region::rgb_to_hsv(*e);
+                if value < params.min_value {
+                    0
+                } else {
+                    (saturation * 255.0).round() as u8
+                }
+            })
+            .collect(),
+        SegmentationSpace::Lab => {
+            let (paper_l, paper_a, paper_b) = region::rgb_to_lab(region::background_color(img));
+            img.pixels()
+                .map(|e| {
+                    let (l, a, b) = region::rgb_to_lab(*e);
+                    let distance = ((l - paper_l).powi(2) + (a - paper_a).powi(2) + (b - paper_b).powi(2)).sqrt();
+                    (distance * 2.0).round().min(255.0) as u8
+                })
+                .collect()
+        }
+    }
+}
+
+// the threshold maximizing the between class variance, values above it are foreground
+pub fn otsu_threshold(histogram: &[u64; 256]) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let sum: f64 = histogram.iter().enumerate().map(|(value, count)| value as f64 * *count as f64).sum();
+    let (mut background_count, mut background_sum) = (0u64, 0f64);
+    let (mut best, mut best_variance) = (0u8, -1f64);
+    for (value, count) in histogram.iter().enumerate() {
+        background_count += count;
+        background_sum += value as f64 * *count as f64;
+        let foreground_count = total - background_count;
+        if background_count == 0 || foreground_count == 0 {
+            continue;
+        }
+        let background_mean = background_sum / background_count as f64;
+        let foreground_mean = (sum - background_sum) / foreground_count as f64;
+        let variance = background_count as f64 * foreground_count as f64 * (background_mean - foreground_mean).powi(2);
+        if variance > best_variance {
+            best = value as u8;
+            best_variance = variance;
+        }
+    }
+    best
+}
+
+fn is_edge_strip(bbox: &BoundingBox, params: &SegmentationParams, width: u32, height: u32) -> bool {
+    let along_side =
+        (bbox.x == 0 || bbox.right() >= width) && bbox.width <= scaled(params.max_edge_strip_thickness, width) && bbox.height >= scaled(params.min_edge_strip_span, height);
+    let along_top_or_bottom =
+        (bbox.y == 0 || bbox.bottom() >= height) && bbox.height <= scaled(params.max_edge_strip_thickness, height) && bbox.width >= scaled(params.min_edge_strip_span, width);
+    along_side || along_top_or_bottom
+}
+
+// the excluded boxes never end up in the mask
+pub fn segment_specimen(img: &RgbaImage, params: &SegmentationParams, exclude: &[BoundingBox]) -> Segmentation {
+    let (width, height) = img.dimensions();
+    let scores = foreground_scores(img, params);
+    let threshold = match params.threshold {
+        Some(threshold) => threshold,
+        None => {
+            let mut histogram = [0u64; 256];
+            for score in scores.iter() {
+                histogram[*score as usize] += 1;
+            }
+            otsu_threshold(&histogram).max(params.min_threshold)
+        }
+    };
+
+    let mut mask = Mask::from_fn(width, height, |x, y| scores[(y * width + x) as usize] > threshold);
+    for bbox in exclude.iter() {
+        for y in bbox.y..bbox.bottom().min(height) {
+            for x in bbox.x..bbox.right().min(width) {
+                mask.set(x, y, false);
+            }
+        }
+    }
+    let mask = region::close(&region::open(&mask, scaled(params.open_radius, width)), scaled(params.close_radius, width));
+
+    let components = region::connected_components(&mask);
+    let min_area = (params.min_component_area * (width * height) as f32) as u32;
+    let kept: Vec<Component> = components
+        .components
+        .iter()
+        .filter(|e| e.area >= min_area)
+        .filter(|e| !params.drop_edge_components || !is_edge_strip(&e.bbox, params, width, height))
+        .cloned()
+        .collect();
+
+    let mut keep = vec![false; components.components.len() + 1];
+    for component in kept.iter() {
+        keep[component.label as usize] = true;
+    }
+    let mask = Mask::from_fn(width, height, |x, y| keep[components.label(x, y) as usize]);
+    let padding = scaled(params.padding, width);
+    let bbox = kept.iter().map(|e| e.bbox).reduce(|a, b| a.union(&b)).map(|e| e.padded(padding, width, height));
+    debug!("segmentation: threshold: {}, components: {}, bbox: {:?}", threshold, kept.len(), bbox);
+
+    Segmentation {
+        mask,
+        threshold,
+        components: kept,
+        bbox,
+    }
+}
+
+// the boxes segment leaves out, empty unless params.exclude_sheet_regions
+pub fn excluded_regions(img: &RgbaImage, params: &SegmentationParams, calibration_params: &CalibrationParams, label_params: &LabelParams) -> Vec<BoundingBox> {
+    if !params.exclude_sheet_regions {
+        return Vec::new();
+    }
+    let regions = specimen_label::detect_sheet_regions(img, calibration_params, label_params);
+    regions
+        .color_bars
+        .into_iter()
+        .chain(regions.rulers)
+        .chain(regions.labels.into_iter().map(|e| e.bbox))
+        .collect()
+}
+
+// white plant on black, for writing out as png
+pub fn mask_image(mask: &Mask) -> image::GrayImage {
+    image::GrayImage::from_fn(mask.width, mask.height, |x, y| image::Luma([if mask.get(x, y) { 255 } else { 0 }]))
+}
+
+pub fn apply_mask(mut img: RgbaImage, mask: &Mask, background: image::Rgba<u8>) -> RgbaImage {
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        if !mask.get(x, y) {
+            *pixel = background;
+        }
+    }
+    img
+}
+
+pub fn crop_to(img: &RgbaImage, bbox: &BoundingBox) -> RgbaImage {
+    image::imageops::crop_imm(img, bbox.x, bbox.y, bbox.width, bbox.height).to_image()
+}
+
+pub fn segment(
+    img: RgbaImage,
+    params: &SegmentationParams,
+    calibration_params: &CalibrationParams,
+    label_params: &LabelParams,
+    action: SegmentationAction,
+) -> Result<RgbaImage, PreprocessError> {
+    let exclude = excluded_regions(&img, params, calibration_params, label_params);
+    let segmentation = segment_specimen(&img, params, &exclude);
+    let bbox = match segmentation.bbox {
+        Some(bbox) => bbox,
+        None => {
+            return Err(PreprocessError::NoForeground {
+                step: PreprocessStep::SegmentSpecimen,
+                threshold: segmentation.threshold,
+            })
+        }
+    };
+
+    let img = match action {
+        SegmentationAction::Crop => img,
+        SegmentationAction::Mask | SegmentationAction::MaskAndCrop => {
+            let background = region::background_color(&img);
+            apply_mask(img, &segmentation.mask, background)
+        }
+    };
+    match action {
+        SegmentationAction::Mask => Ok(img),
+        SegmentationAction::Crop | SegmentationAction::MaskAndCrop => Ok(crop_to(&img, &bbox)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(x: u32, y: u32, width: u32, height: u32) -> BoundingBox {
+        BoundingBox { x, y, width, height }
+    }
+
+    #[test]
+    fn otsu_splits_two_peaks() {
+        let mut histogram = [0u64; 256];
+        histogram[20] = 100;
+        histogram[200] = 100;
+        // everything up to the threshold is background, so the first value that separates the peaks
+        assert_eq!(otsu_threshold(&histogram), 20);
+    }
+
+    #[test]
+    fn otsu_splits_two_spread_out_modes() {
+        let mut histogram = [0u64; 256];
+        for count in histogram[10..=30].iter_mut() {
+            *count = 50;
+        }
+        for count in histogram[180..=220].iter_mut() {
+            *count = 20;
+        }
+        let threshold = otsu_threshold(&histogram);
+        assert!((30..180).contains(&threshold), "threshold: {}", threshold);
+    }
+
+    #[test]
+    fn otsu_of_a_single_value_has_nothing_to_split() {
+        let mut histogram = [0u64; 256];
+        histogram[128] = 1000;
+        assert_eq!(otsu_threshold(&histogram), 0);
+        assert_eq!(otsu_threshold(&[0u64; 256]), 0);
+    }
+
+    // 200x100 with the default params: a strip along a side is at most 10 wide & at least 50 tall,
+    // along the top or bottom at most 5 tall & at least 100 wide
+    #[test]
+    fn thin_strips_along_an_edge_are_edge_strips() {
+        let params = SegmentationParams::default();
+        assert!(is_edge_strip(&bbox(0, 10, 8, 60), &params, 200, 100));
+        assert!(is_edge_strip(&bbox(195, 0, 5, 100), &params, 200, 100));
+        assert!(is_edge_strip(&bbox(20, 0, 150, 4), &params, 200, 100));
+        assert!(is_edge_strip(&bbox(0, 97, 120, 3), &params, 200, 100));
+    }
+
+    #[test]
+    fn thick_short_or_inner_components_arent_edge_strips() {
+        let params = SegmentationParams::default();
+        // too thick
+        assert!(!is_edge_strip(&bbox(0, 10, 20, 60), &params, 200, 100));
+        assert!(!is_edge_strip(&bbox(20, 0, 150, 8), &params, 200, 100));
+        // too short
+        assert!(!is_edge_strip(&bbox(0, 10, 8, 30), &params, 200, 100));
+        assert!(!is_edge_strip(&bbox(0, 97, 60, 3), &params, 200, 100));
+        // not touching the edge
+        assert!(!is_edge_strip(&bbox(5, 10, 8, 60), &params, 200, 100));
+        assert!(!is_edge_strip(&bbox(20, 2, 150, 4), &params, 200, 100));
+    }
+}
@@ -0,0 +1,400 @@
+use crate::calibration;
+use crate::calibration::CalibrationParams;
+use crate::pipeline::RgbaImage;
+use crate::region;
+use crate::region::{BoundingBox, Mask};
+use crate::PreprocessError;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum LabelKind {
+    // typed or handwritten collection labels, dark ink on light paper
+    Label,
+    // colored ink on light paper
+    Stamp,
+    // dense vertical bars
+    Barcode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum LabelAction {
+    // paint the regions over with the paper color
+    #[default]
+    Mask,
+    // crop away the strip between each region and the image edge nearest to it
+    Crop,
+}
+
+// the image is classified in square blocks, block_size is a fraction of the width & the min areas fractions of the image area
+// pixels are paper (light & unsaturated), ink (dark & unsaturated), stamp ink (saturated red to violet) or other, the plant being other
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LabelParams {
+    pub block_size: f32,
+    pub paper_threshold: u8,
+    pub ink_threshold: u8,
+    pub max_ink_saturation: f32,
+    pub min_stamp_saturation: f32,
+    pub min_stamp_value: f32,
+    // a pixel is an edge when its intensity differs from the next pixel across or down by more than this
+    pub edge_threshold: u8,
+    // label blocks: some ink, enough paper around it, text-like edges and next to nothing that's neither paper nor ink
+    pub min_paper: f32,
+    pub min_ink: f32,
+    pub min_text_edges: f32,
+    pub max_other: f32,
+    // blocks with at least this much ink are scanner bed or other solid dark areas, label blocks next to them are sheet edges
+    pub solid_ink: f32,
+    // stamp blocks: more colored ink than dark ink, dark blue or purple text bleeds into the stamp colors at its edges
+    pub min_stamp_ink: f32,
+    // barcode blocks: dense edges, nearly all of them from vertical bars
+    pub min_barcode_edges: f32,
+    pub min_vertical_share: f32,
+    pub min_label_area: f32,
+    pub min_stamp_area: f32,
+    pub min_barcode_area: f32,
+    // share of the blocks inside a region's box that have to be of its kind
+    pub min_fill: f32,
+    // gaps between text lines up to this many blocks are closed
+    pub close_blocks: u32,
+}
+
+impl Default for LabelParams {
+    fn default() -> LabelParams {
+        LabelParams {
+            block_size: 0.02,
+            paper_threshold: 150,
+            ink_threshold: 110,
+            max_ink_saturation: 0.4,
+            min_stamp_saturation: 0.35,
+            min_stamp_value: 0.45,
+            edge_threshold: 48,
+            min_paper: 0.2,
+            min_ink: 0.03,
+            min_text_edges: 0.04,
+            max_other: 0.1,
+            solid_ink: 0.9,
+            min_stamp_ink: 0.05,
+            min_barcode_edges: 0.2,
+            min_vertical_share: 0.85,
+            min_label_area: 0.005,
+            min_stamp_area: 0.001,
+            min_barcode_area: 0.001,
+            min_fill: 0.4,
+            close_blocks: 1,
+        }
+    }
+}
+
+impl LabelParams {
+    fn min_area(&self, kind: LabelKind) -> f32 {
+        match kind {
+            LabelKind::Label => self.min_label_area,
+            LabelKind::Stamp => self.min_stamp_area,
+            LabelKind::Barcode => self.min_barcode_area,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LabelRegion {
+    pub kind: LabelKind,
+    pub bbox: BoundingBox,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BlockStats {
+    paper: f32,
+    ink: f32,
+    stamp_ink: f32,
+    edges: f32,
+    vertical_edges: f32,
+}
+
+impl BlockStats {
+    fn other(&self) -> f32 {
+        1.0 - self.paper - self.ink - self.stamp_ink
+    }
+
+    fn kind(&self, params: &LabelParams) -> Option<LabelKind> {
+        if self.other() > params.max_other || self.paper < params.min_paper {
+            return None;
+        }
+        if self.edges >= params.min_barcode_edges && self.vertical_edges >= params.min_vertical_share * self.edges {
+            Some(LabelKind::Barcode)
+        } else if self.stamp_ink >= params.min_stamp_ink && self.stamp_ink > self.ink {
+            Some(LabelKind::Stamp)
+        } else if self.ink >= params.min_ink && self.edges >= params.min_text_edges {
+            Some(LabelKind::Label)
+        } else {
+            None
+        }
+    }
+}
+
+fn intensity(pixel: &image::Rgba<u8>) -> i32 {
+    (pixel[0] as i32 + pixel[1] as i32 + pixel[2] as i32) / 3
+}
+
+fn block_stats(img: &RgbaImage, bbox: &BoundingBox, params: &LabelParams) -> BlockStats {
+    let mut stats = BlockStats::default();
+    for y in bbox.y..bbox.bottom() {
+        for x in bbox.x..bbox.right() {
+            let pixel = img.get_pixel(x, y);
+            let value = intensity(pixel);
+            let (hue, saturation, brightness) = region::rgb_to_hsv(*pixel);
+            if value >= params.paper_threshold as i32 && saturation < params.min_stamp_saturation {
+                stats.paper += 1.0;
+            } else if value < params.ink_threshold as i32 && saturation < params.max_ink_saturation {
+                stats.ink += 1.0;
+            } else if saturation >= params.min_stamp_saturation && brightness >= params.min_stamp_value && !(20.0..=190.0).contains(&hue) {
+                stats.stamp_ink += 1.0;
+            }
+
+            // a vertical edge is a jump from one column to the next
+            if x + 1 < img.width() && (value - intensity(img.get_pixel(x + 1, y))).abs() > params.edge_threshold as i32 {
+                stats.edges += 1.0;
+                stats.vertical_edges += 1.0;
+            } else if y + 1 < img.height() && (value - intensity(img.get_pixel(x, y + 1))).abs() > params.edge_threshold as i32 {
+                stats.edges += 1.0;
+            }
+        }
+    }
+    let count = bbox.area() as f32;
+    BlockStats {
+        paper: stats.paper / count,
+        ink: stats.ink / count,
+        stamp_ink: stats.stamp_ink / count,
+        edges: stats.edges / count,
+        vertical_edges: stats.vertical_edges / count,
+    }
+}
+
+pub fn detect_labels(img: &RgbaImage, params: &LabelParams) -> Vec<LabelRegion> {
+    let (width, height) = img.dimensions();
+    let block = ((params.block_size * width as f32).round() as u32).max(4);
+    let (columns, rows) = (width.div_ceil(block), height.div_ceil(block));
+    let block_box = |column: u32, row: u32| {
+        let (x, y) = (column * block, row * block);
+        BoundingBox {
+            x,
+            y,
+            width: block.min(width - x),
+            height: block.min(height - y),
+        }
+    };
+
+    let mut stats = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            stats.push(block_stats(img, &block_box(column, row), params));
+        }
+    }
+    let solid = Mask::from_fn(columns, rows, |column, row| stats[(row * columns + column) as usize].ink >= params.solid_ink);
+    let next_to_solid = region::dilate(&solid, 1);
+    let kinds: Vec<Option<LabelKind>> = stats
+        .iter()
+        .zip(next_to_solid.data.iter())
+        .map(|(block, near_solid)| match block.kind(params) {
+            Some(LabelKind::Label) if *near_solid => None,
+            kind => kind,
+        })
+        .collect();
+
+    let mut regions = Vec::new();
+    for kind in [LabelKind::Label, LabelKind::Stamp, LabelKind::Barcode].iter() {
+        let blocks = Mask::from_fn(columns, rows, |column, row| kinds[(row * columns + column) as usize] == Some(*kind));
+        let components = region::connected_components(&region::close(&blocks, params.close_blocks));
+        let min_area = params.min_area(*kind) * (width * height) as f32;
+
+        for component in components.components.iter() {
+            let grid = component.bbox;
+            let filled = (grid.y..grid.bottom())
+                .flat_map(|row| (grid.x..grid.right()).map(move |column| (column, row)))
+                .filter(|(column, row)| blocks.get(*column, *row))
+                .count();
+            if (filled as f32) < params.min_fill * grid.area() as f32 {
+                continue;
+            }
+            let top_left = block_box(grid.x, grid.y);
+            let bottom_right = block_box(grid.right() - 1, grid.bottom() - 1);
+            let bbox = top_left.union(&bottom_right);
+            if (bbox.area() as f32) < min_area {
+                continue;
+            }
+            regions.push(LabelRegion { kind: *kind, bbox });
+        }
+    }
+    regions.sort_by_key(|e| (e.bbox.y, e.bbox.x, e.kind));
+    debug!("label regions: {:?}", regions);
+    regions
+}
+
+// the calibration params are only used to leave out color bars & rulers, see detect_sheet_regions
+pub fn remove_labels(mut img: RgbaImage, params: &LabelParams, calibration_params: &CalibrationParams, action: LabelAction) -> Result<RgbaImage, PreprocessError> {
+    let regions = detect_sheet_regions(&img, calibration_params, params).labels;
+    if regions.is_empty() {
+        return Ok(img);
+    }
+    match action {
+        LabelAction::Mask => {
+            let background = region::background_color(&img);
+            for label in regions.iter() {
+                region::fill(&mut img, &label.bbox, background);
+            }
+            Ok(img)
+        }
+        LabelAction::Crop => {
+            let (width, height) = img.dimensions();
+            let (mut from_left, mut from_right, mut from_top, mut from_bottom) = (0, 0, 0, 0);
+            for label in regions.iter() {
+                let bbox = label.bbox;
+                let distances = [bbox.x, width - bbox.right(), bbox.y, height - bbox.bottom()];
+                match (0..4).min_by_key(|e| distances[*e]).unwrap() {
+                    0 => from_left = from_left.max(bbox.right()),
+                    1 => from_right = from_right.max(width - bbox.x),
+                    2 => from_top = from_top.max(bbox.bottom()),
+                    _ => from_bottom = from_bottom.max(height - bbox.y),
+                }
+            }
+            crate::crop_image(image::DynamicImage::ImageRgba8(img), from_left, from_right, from_top, from_bottom)
+        }
+    }
+}
+
+// everything detect_sheet_regions reports for a sheet
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SheetRegions {
+    pub width: u32,
+    pub height: u32,
+    pub color_bars: Vec<BoundingBox>,
+    pub rulers: Vec<BoundingBox>,
+    pub labels: Vec<LabelRegion>,
+}
+
+// color bar patches look like stamps & rulers like barcodes or labels to the block classification, so label regions that are mostly calibration target are left out
+// and the others are clipped against the targets, a label next to a ruler often gets merged with the end of it
+pub fn detect_sheet_regions(img: &RgbaImage, calibration_params: &CalibrationParams, label_params: &LabelParams) -> SheetRegions {
+    let targets = calibration::detect_calibration_targets(img, calibration_params);
+    let target_boxes = targets.boxes();
+    let labels = detect_labels(img, label_params)
+        .into_iter()
+        .filter_map(|e| clip_to_targets(&e.bbox, &target_boxes).map(|bbox| LabelRegion { kind: e.kind, bbox }))
+        .collect();
+    SheetRegions {
+        width: img.width(),
+        height: img.height(),
+        color_bars: targets.color_bars,
+        rulers: targets.rulers,
+        labels,
+    }
+}
+
+// None when at least half of the box is covered by targets
+// a target reaching across the whole box from one of its sides is cut off, other overlaps (a corner, a target inside the label) can't be cut off and are kept
+fn clip_to_targets(bbox: &BoundingBox, target_boxes: &[BoundingBox]) -> Option<BoundingBox> {
+    let covered = (bbox.y..bbox.bottom())
+        .flat_map(|y| (bbox.x..bbox.right()).map(move |x| (x, y)))
+        .filter(|(x, y)| target_boxes.iter().any(|target| target.contains(*x, *y)))
+        .count();
+    if covered * 2 >= bbox.area() as usize {
+        return None;
+    }
+
+    let mut clipped = *bbox;
+    for target in target_boxes.iter() {
+        let overlap = match clipped.intersection(target) {
+            Some(overlap) => overlap,
+            None => continue,
+        };
+        if overlap.height == clipped.height && overlap.x == clipped.x {
+            clipped.width -= overlap.width;
+            clipped.x = overlap.right();
+        } else if overlap.height == clipped.height && overlap.right() == clipped.right() {
+            clipped.width -= overlap.width;
+        } else if overlap.width == clipped.width && overlap.y == clipped.y {
+            clipped.height -= overlap.height;
+            clipped.y = overlap.bottom();
+        } else if overlap.width == clipped.width && overlap.bottom() == clipped.bottom() {
+            clipped.height -= overlap.height;
+        }
+    }
+    Some(clipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(x: u32, y: u32, width: u32, height: u32) -> BoundingBox {
+        BoundingBox { x, y, width, height }
+    }
+
+    // 40x20, 800 pixels
+    const LABEL: BoundingBox = BoundingBox {
+        x: 100,
+        y: 50,
+        width: 40,
+        height: 20,
+    };
+
+    #[test]
+    fn a_label_without_targets_is_kept_whole() {
+        assert_eq!(clip_to_targets(&LABEL, &[]), Some(LABEL));
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(0, 0, 50, 50)]), Some(LABEL));
+    }
+
+    #[test]
+    fn a_label_at_least_half_covered_is_dropped() {
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(100, 50, 20, 20)]), None);
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(90, 40, 60, 40)]), None);
+        // 200 + 200 from two targets
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(90, 40, 20, 40), bbox(130, 40, 20, 40)]), None);
+    }
+
+    #[test]
+    fn overlapping_targets_are_only_counted_once() {
+        // both cover the same 300 pixels
+        let clipped = clip_to_targets(&LABEL, &[bbox(90, 40, 25, 40), bbox(95, 45, 20, 30)]);
+        assert_eq!(clipped, Some(bbox(115, 50, 25, 20)));
+    }
+
+    #[test]
+    fn a_target_across_the_left_side_is_cut_off() {
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(90, 40, 20, 40)]), Some(bbox(110, 50, 30, 20)));
+    }
+
+    #[test]
+    fn a_target_across_the_right_side_is_cut_off() {
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(130, 45, 20, 30)]), Some(bbox(100, 50, 30, 20)));
+    }
+
+    #[test]
+    fn a_target_across_the_top_is_cut_off() {
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(95, 40, 50, 15)]), Some(bbox(100, 55, 40, 15)));
+    }
+
+    #[test]
+    fn a_target_across_the_bottom_is_cut_off() {
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(100, 65, 40, 10)]), Some(bbox(100, 50, 40, 15)));
+    }
+
+    #[test]
+    fn targets_on_two_sides_are_both_cut_off() {
+        let clipped = clip_to_targets(&LABEL, &[bbox(90, 40, 20, 40), bbox(100, 65, 40, 10)]);
+        assert_eq!(clipped, Some(bbox(110, 50, 30, 15)));
+    }
+
+    #[test]
+    fn corner_and_inner_overlaps_are_kept() {
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(90, 40, 20, 15)]), Some(LABEL));
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(110, 55, 5, 5)]), Some(LABEL));
+        // reaches across the whole height but not from a side
+        assert_eq!(clip_to_targets(&LABEL, &[bbox(115, 40, 10, 40)]), Some(LABEL));
+    }
+}
@@ -0,0 +1,273 @@
+use crate::HerbariumCatalog;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SplitKind {
+    Train,
+    Validation,
+    Test,
+}
+
+// ratios are fractions of each class, counts are images per class
+// in both cases train gets whatever is left once validation & test are taken, a train count of 0 means no cap
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum SplitSizes {
+    Ratios { validation: f64, test: f64 },
+    Counts { train: usize, validation: usize, test: usize },
+}
+
+impl SplitSizes {
+    // returns (train, validation, test) for a class of the given size or None when train would drop below min_per_class
+    fn allocate(&self, available: usize, min_per_class: usize) -> Option<(usize, usize, usize)> {
+        let min_train = min_per_class.max(1);
+        if available < min_train {
+            return None;
+        }
+        let spare = available - min_train;
+
+        let (requested_validation, requested_test) = match self {
+            SplitSizes::Ratios { validation, test } => ((available as f64 * validation).round() as usize, (available as f64 * test).round() as usize),
+            SplitSizes::Counts { validation, test, .. } => (*validation, *test),
+        };
+
+        let validation = requested_validation.min(spare);
+        let test = requested_test.min(spare - validation);
+        let remaining = available - validation - test;
+        let train = match self {
+            SplitSizes::Counts { train, .. } if *train > 0 => remaining.min((*train).max(min_train)),
+            _ => remaining,
+        };
+        Some((train, validation, test))
+    }
+}
+
+impl fmt::Display for SplitSizes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitSizes::Ratios { validation, test } => write!(f, "{},{},{}", 1.0 - validation - test, validation, test),
+            SplitSizes::Counts { train, validation, test } => write!(f, "{},{},{}", train, validation, test),
+        }
+    }
+}
+
+// "0.8,0.1,0.1" are ratios, "10,1,0" are per class counts
+impl FromStr for SplitSizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SplitSizes, String> {
+        let parts: Vec<&str> = s.split(',').map(|e| e.trim()).collect();
+        if parts.len() != 3 {
+            return Err(format!("expected train,validation,test sizes, got: {}", s));
+        }
+
+        if parts.iter().any(|e| e.contains('.')) {
+            let ratios = parts.iter().map(|e| e.parse::<f64>()).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+            if ratios.iter().any(|e| *e < 0.0) || (ratios.iter().sum::<f64>() - 1.0).abs() > 1e-6 {
+                return Err(format!("ratios have to be positive and add up to 1, got: {}", s));
+            }
+            Ok(SplitSizes::Ratios {
+                validation: ratios[1],
+                test: ratios[2],
+            })
+        } else {
+            let counts = parts.iter().map(|e| e.parse::<usize>()).collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?;
+            Ok(SplitSizes::Counts {
+                train: counts[0],
+                validation: counts[1],
+                test: counts[2],
+            })
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SplitConfig {
+    pub seed: u64,
+    pub sizes: SplitSizes,
+    // every class keeps at least this many training images, classes with fewer images are left out entirely
+    pub min_per_class: usize,
+    pub stratify_by_region: bool,
+    // only the first N categories (by id) when > 0
+    pub category_limit: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Fold {
+    pub index: usize,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Split {
+    pub config: SplitConfig,
+    #[serde(default)]
+    pub fold: Option<Fold>,
+    pub train: Vec<i32>,
+    pub validation: Vec<i32>,
+    pub test: Vec<i32>,
+    pub skipped_category_ids: Vec<i32>,
+}
+
+impl Split {
+    pub fn image_ids(&self, kind: SplitKind) -> &[i32] {
+        match kind {
+            SplitKind::Train => &self.train,
+            SplitKind::Validation => &self.validation,
+            SplitKind::Test => &self.test,
+        }
+    }
+
+    pub fn read(split_path: &path::Path) -> io::Result<Split> {
+        let split_file = fs::File::open(split_path)?;
+        let split = serde_json::from_reader(io::BufReader::new(split_file))?;
+        Ok(split)
+    }
+
+    pub fn write(&self, split_path: &path::Path) -> io::Result<()> {
+        let split_file = fs::File::create(split_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(split_file), self)?;
+        Ok(())
+    }
+}
+
+// every class is shuffled with its own rng derived from the seed, so a class always splits the same way no matter which other classes are selected
+pub(crate) fn class_rng(seed: u64, category_id: i32) -> StdRng {
+    StdRng::seed_from_u64(seed ^ (category_id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+// shuffles within each region and then deals the regions out round robin, so any prefix holds the regions in proportion
+fn region_interleaved(catalog: &HerbariumCatalog, category_id: i32, rng: &mut StdRng) -> Vec<i32> {
+    let mut region_ids: Vec<i32> = catalog
+        .annotated_region_ids()
+        .into_iter()
+        .filter(|region_id| !catalog.image_ids_for_region_and_category(*region_id, category_id).is_empty())
+        .collect();
+    region_ids.shuffle(rng);
+
+    let mut image_ids_by_region: Vec<Vec<i32>> = region_ids
+        .iter()
+        .map(|region_id| {
+            let mut image_ids = catalog.image_ids_for_region_and_category(*region_id, category_id).to_vec();
+            image_ids.sort();
+            image_ids.dedup();
+            image_ids.shuffle(rng);
+            image_ids
+        })
+        .collect();
+
+    let total: usize = image_ids_by_region.iter().map(|e| e.len()).sum();
+    let mut interleaved = Vec::with_capacity(total);
+    let mut cursor = 0;
+    while interleaved.len() < total {
+        for image_ids in image_ids_by_region.iter_mut() {
+            if cursor < image_ids.len() {
+                interleaved.push(image_ids[cursor]);
+            }
+        }
+        cursor += 1;
+    }
+    interleaved
+}
+
+pub fn stratified_split(catalog: &HerbariumCatalog, config: &SplitConfig) -> Split {
+    let mut category_ids = catalog.annotated_category_ids();
+    if config.category_limit > 0 {
+        category_ids.truncate(config.category_limit);
+    }
+
+    let mut split = Split {
+        config: config.clone(),
+        fold: None,
+        train: Vec::new(),
+        validation: Vec::new(),
+        test: Vec::new(),
+        skipped_category_ids: Vec::new(),
+    };
+
+    for category_id in category_ids.into_iter() {
+        let mut rng = class_rng(config.seed, category_id);
+
+        let image_ids = if config.stratify_by_region {
+            region_interleaved(catalog, category_id, &mut rng)
+        } else {
+            let mut image_ids = catalog.image_ids_for_category(category_id).to_vec();
+            image_ids.sort();
+            image_ids.dedup();
+            image_ids.shuffle(&mut rng);
+            image_ids
+        };
+
+        match config.sizes.allocate(image_ids.len(), config.min_per_class) {
+            Some((train, validation, test)) => {
+                debug!("category_id: {}, train: {}, validation: {}, test: {}", category_id, train, validation, test);
+                split.validation.extend_from_slice(&image_ids[..validation]);
+                split.test.extend_from_slice(&image_ids[validation..validation + test]);
+                split.train.extend_from_slice(&image_ids[validation + test..validation + test + train]);
+            }
+            None => {
+                debug!("category_id: {}, skipped with {} images", category_id, image_ids.len());
+                split.skipped_category_ids.push(category_id);
+            }
+        }
+    }
+
+    split
+}
+
+// every class is dealt out over the folds round robin, starting where the previous class stopped so the folds stay the same size
+// fold i validates on its own share and trains on the rest, classes with fewer than k images are missing from some validation folds
+pub fn stratified_k_fold(catalog: &HerbariumCatalog, k: usize, seed: u64, category_limit: usize) -> Vec<Split> {
+    let mut category_ids = catalog.annotated_category_ids();
+    if category_limit > 0 {
+        category_ids.truncate(category_limit);
+    }
+
+    let config = SplitConfig {
+        seed,
+        sizes: SplitSizes::Ratios {
+            validation: 1.0 / k as f64,
+            test: 0.0,
+        },
+        min_per_class: 1,
+        stratify_by_region: false,
+        category_limit,
+    };
+
+    let mut folds: Vec<Vec<i32>> = vec![Vec::new(); k];
+    let mut next_fold = 0;
+    for category_id in category_ids.into_iter() {
+        let mut rng = class_rng(seed, category_id);
+        let mut image_ids = catalog.image_ids_for_category(category_id).to_vec();
+        image_ids.sort();
+        image_ids.dedup();
+        image_ids.shuffle(&mut rng);
+
+        for image_id in image_ids.into_iter() {
+            folds[next_fold].push(image_id);
+            next_fold = (next_fold + 1) % k;
+        }
+    }
+
+    (0..k)
+        .map(|index| Split {
+            config: config.clone(),
+            fold: Some(Fold { index, count: k }),
+            train: folds.iter().enumerate().filter(|(i, _)| *i != index).flat_map(|(_, e)| e.iter().cloned()).collect(),
+            validation: folds[index].clone(),
+            test: Vec::new(),
+            skipped_category_ids: Vec::new(),
+        })
+        .collect()
+}
@@ -0,0 +1,247 @@
+use crate::{License, TestMetadata, TrainMetadata};
+use rusqlite::{params, Connection};
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+
+// family & genus get their own tables so the names aren't repeated on every category
+// the foreign keys are deferred so the tables can be filled in any order, a dangling reference fails the commit instead (see validate_metadata)
+const SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+
+CREATE TABLE info (
+    split TEXT PRIMARY KEY CHECK (split IN ('train', 'test')),
+    year INTEGER NOT NULL,
+    version TEXT NOT NULL,
+    url TEXT NOT NULL,
+    description TEXT NOT NULL,
+    contributor TEXT NOT NULL,
+    date_created TEXT NOT NULL
+);
+
+CREATE TABLE licenses (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    url TEXT NOT NULL
+);
+
+CREATE TABLE regions (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE institutions (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+
+CREATE TABLE families (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE genera (
+    id INTEGER PRIMARY KEY,
+    family_id INTEGER NOT NULL REFERENCES families (id) DEFERRABLE INITIALLY DEFERRED,
+    name TEXT NOT NULL,
+    UNIQUE (family_id, name)
+);
+
+CREATE TABLE categories (
+    id INTEGER PRIMARY KEY,
+    genus_id INTEGER NOT NULL REFERENCES genera (id) DEFERRABLE INITIALLY DEFERRED,
+    name TEXT,
+    species TEXT,
+    taxon_order TEXT,
+    authors TEXT
+);
+
+CREATE TABLE images (
+    id INTEGER PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    license_id INTEGER NOT NULL REFERENCES licenses (id) DEFERRABLE INITIALLY DEFERRED,
+    source_id TEXT
+);
+
+CREATE TABLE test_images (
+    id INTEGER PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    license_id INTEGER NOT NULL REFERENCES licenses (id) DEFERRABLE INITIALLY DEFERRED,
+    source_id TEXT
+);
+
+CREATE TABLE annotations (
+    id INTEGER PRIMARY KEY,
+    image_id INTEGER NOT NULL REFERENCES images (id) DEFERRABLE INITIALLY DEFERRED,
+    category_id INTEGER NOT NULL REFERENCES categories (id) DEFERRABLE INITIALLY DEFERRED,
+    region_id INTEGER NOT NULL REFERENCES regions (id) DEFERRABLE INITIALLY DEFERRED,
+    institution_id INTEGER REFERENCES institutions (id) DEFERRABLE INITIALLY DEFERRED
+);
+
+CREATE INDEX genera_family_id_idx ON genera (family_id);
+CREATE INDEX categories_genus_id_idx ON categories (genus_id);
+CREATE INDEX images_license_id_idx ON images (license_id);
+CREATE INDEX test_images_license_id_idx ON test_images (license_id);
+CREATE INDEX annotations_image_id_idx ON annotations (image_id);
+CREATE INDEX annotations_category_id_idx ON annotations (category_id);
+CREATE INDEX annotations_region_id_idx ON annotations (region_id);
+CREATE INDEX annotations_institution_id_idx ON annotations (institution_id);
+
+CREATE VIEW annotation_details AS
+SELECT a.id AS annotation_id, i.id AS image_id, i.file_name, i.width, i.height,
+       c.id AS category_id, c.name AS category_name, g.name AS genus, f.name AS family,
+       r.id AS region_id, r.name AS region, n.name AS institution, l.name AS license
+FROM annotations a
+JOIN images i ON i.id = a.image_id
+JOIN categories c ON c.id = a.category_id
+JOIN genera g ON g.id = c.genus_id
+JOIN families f ON f.id = g.family_id
+JOIN regions r ON r.id = a.region_id
+LEFT JOIN institutions n ON n.id = a.institution_id
+JOIN licenses l ON l.id = i.license_id;
+";
+
+#[derive(Debug, Default)]
+pub struct ExportCounts {
+    pub annotations: usize,
+    pub categories: usize,
+    pub families: usize,
+    pub genera: usize,
+    pub images: usize,
+    pub test_images: usize,
+    pub licenses: usize,
+    pub regions: usize,
+    pub institutions: usize,
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+// replaces whatever is at database_path
+pub fn export_metadata(database_path: &path::Path, train_metadata: &TrainMetadata, test_metadata: Option<&TestMetadata>) -> io::Result<ExportCounts> {
+    if database_path.exists() {
+        fs::remove_file(database_path)?;
+    }
+
+    let licenses = merged_licenses(train_metadata, test_metadata)?;
+
+    let mut connection = Connection::open(database_path).map_err(sql_error)?;
+    connection.execute_batch(SCHEMA).map_err(sql_error)?;
+
+    let transaction = connection.transaction().map_err(sql_error)?;
+    let counts = insert_metadata(&transaction, train_metadata, test_metadata, &licenses).map_err(sql_error)?;
+    transaction.commit().map_err(sql_error)?;
+    Ok(counts)
+}
+
+// train & test share the license ids, a license listed twice has to be the same license both times
+fn merged_licenses<'a>(train_metadata: &'a TrainMetadata, test_metadata: Option<&'a TestMetadata>) -> io::Result<Vec<&'a License>> {
+    let mut licenses: collections::BTreeMap<i32, &License> = collections::BTreeMap::new();
+    let test_licenses = test_metadata.map(|e| e.licenses.iter()).into_iter().flatten();
+    for license in train_metadata.licenses.iter().chain(test_licenses) {
+        match licenses.get(&license.id) {
+            Some(known) if known.name != license.name || known.url != license.url => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("license {} is both {} ({}) and {} ({})", license.id, known.name, known.url, license.name, license.url),
+                ));
+            }
+            Some(_) => {}
+            None => {
+                licenses.insert(license.id, license);
+            }
+        }
+    }
+    Ok(licenses.into_iter().map(|(_, license)| license).collect())
+}
+
+fn insert_metadata(connection: &Connection, train_metadata: &TrainMetadata, test_metadata: Option<&TestMetadata>, licenses: &[&License]) -> rusqlite::Result<ExportCounts> {
+    let mut counts = ExportCounts::default();
+
+    let mut info_statement = connection.prepare("INSERT INTO info (split, year, version, url, description, contributor, date_created) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+    let mut info_rows = vec![("train", &train_metadata.info)];
+    if let Some(test_metadata) = test_metadata {
+        info_rows.push(("test", &test_metadata.info));
+    }
+    for (split, info) in info_rows.into_iter() {
+        info_statement.execute(params![split, info.year, info.version, info.url, info.description, info.contributor, info.date_created])?;
+    }
+
+    let mut license_statement = connection.prepare("INSERT INTO licenses (id, name, url) VALUES (?1, ?2, ?3)")?;
+    for license in licenses.iter() {
+        counts.licenses += license_statement.execute(params![license.id, license.name, license.url])?;
+    }
+
+    let mut region_statement = connection.prepare("INSERT INTO regions (id, name) VALUES (?1, ?2)")?;
+    for region in train_metadata.regions.iter() {
+        counts.regions += region_statement.execute(params![region.id, region.name])?;
+    }
+
+    let mut institution_statement = connection.prepare("INSERT INTO institutions (id, name) VALUES (?1, ?2)")?;
+    for institution in train_metadata.institutions.iter() {
+        counts.institutions += institution_statement.execute(params![institution.id, institution.name])?;
+    }
+
+    let mut family_ids: collections::HashMap<&str, i64> = collections::HashMap::new();
+    let mut genus_ids: collections::HashMap<(&str, &str), i64> = collections::HashMap::new();
+    let mut family_statement = connection.prepare("INSERT INTO families (name) VALUES (?1)")?;
+    let mut genus_statement = connection.prepare("INSERT INTO genera (family_id, name) VALUES (?1, ?2)")?;
+    let mut category_statement = connection.prepare("INSERT INTO categories (id, genus_id, name, species, taxon_order, authors) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for category in train_metadata.categories.iter() {
+        let family_id = match family_ids.get(category.family.as_str()) {
+            Some(family_id) => *family_id,
+            None => {
+                family_statement.execute(params![category.family])?;
+                let family_id = connection.last_insert_rowid();
+                family_ids.insert(category.family.as_str(), family_id);
+                family_id
+            }
+        };
+
+        let genus_key = (category.family.as_str(), category.genus.as_str());
+        let genus_id = match genus_ids.get(&genus_key) {
+            Some(genus_id) => *genus_id,
+            None => {
+                genus_statement.execute(params![family_id, category.genus])?;
+                let genus_id = connection.last_insert_rowid();
+                genus_ids.insert(genus_key, genus_id);
+                genus_id
+            }
+        };
+
+        counts.categories += category_statement.execute(params![category.id, genus_id, category.name, category.species, category.order, category.authors])?;
+    }
+    counts.families = family_ids.len();
+    counts.genera = genus_ids.len();
+
+    let mut image_statement = connection.prepare("INSERT INTO images (id, file_name, width, height, license_id, source_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+    for image in train_metadata.images.iter() {
+        counts.images += image_statement.execute(params![image.id, image.file_name, image.width, image.height, image.license, image.source_id])?;
+    }
+
+    if let Some(test_metadata) = test_metadata {
+        let mut test_image_statement = connection.prepare("INSERT INTO test_images (id, file_name, width, height, license_id, source_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        for image in test_metadata.images.iter() {
+            counts.test_images += test_image_statement.execute(params![image.id, image.file_name, image.width, image.height, image.license, image.source_id])?;
+        }
+    }
+
+    let mut annotation_statement = connection.prepare("INSERT INTO annotations (id, image_id, category_id, region_id, institution_id) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    for annotation in train_metadata.annotations.iter() {
+        counts.annotations += annotation_statement.execute(params![
+            annotation.id,
+            annotation.image_id,
+            annotation.category_id,
+            annotation.region_id,
+            annotation.institution_id
+        ])?;
+    }
+
+    Ok(counts)
+}
@@ -0,0 +1,549 @@
+use crate::taxonomy::Taxonomy;
+use crate::{HerbariumCatalog, Image};
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fmt::Write;
+
+// upper bounds (exclusive) of the images-per-class histogram buckets, the last bucket is open ended
+const CLASS_SIZE_BUCKETS: [usize; 8] = [2, 5, 10, 20, 50, 100, 500, 1000];
+const EXTREME_CLASS_COUNT: usize = 10;
+const COMMON_SIZE_COUNT: usize = 10;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Summary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub standard_deviation: f64,
+}
+
+impl Summary {
+    pub fn new(values: &[f64]) -> Summary {
+        if values.is_empty() {
+            return Summary::default();
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let middle = sorted.len() / 2;
+        let median = if sorted.len() % 2 == 0 {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        };
+        let mean = statistical::mean(&sorted);
+        Summary {
+            count: sorted.len(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean,
+            median,
+            standard_deviation: if sorted.len() > 1 {
+                statistical::population_standard_deviation(&sorted, Some(mean))
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistogramBucket {
+    pub label: String,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassCount {
+    pub category_id: i32,
+    pub name: Option<String>,
+    pub family: String,
+    pub genus: String,
+    pub image_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClassDistribution {
+    pub class_count: usize,
+    pub images_per_class: Summary,
+    pub histogram: Vec<HistogramBucket>,
+    pub largest: Vec<ClassCount>,
+    pub smallest: Vec<ClassCount>,
+}
+
+// gini is 0 for perfectly balanced classes and approaches 1 as the images concentrate in a few classes
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Imbalance {
+    pub max_to_min: f64,
+    pub max_to_median: f64,
+    pub gini: f64,
+    pub singleton_classes: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegionCount {
+    pub region_id: i32,
+    pub name: String,
+    pub image_count: usize,
+    pub category_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaxonomyStatistics {
+    pub family_count: usize,
+    pub genus_count: usize,
+    pub species_count: usize,
+    pub genera_per_family: Summary,
+    pub species_per_family: Summary,
+    pub species_per_genus: Summary,
+    pub monotypic_genera: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SizeCount {
+    pub width: i32,
+    pub height: i32,
+    pub count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageSizeStatistics {
+    pub widths: Summary,
+    pub heights: Summary,
+    pub aspect_ratios: Summary,
+    pub most_common: Vec<SizeCount>,
+}
+
+impl ImageSizeStatistics {
+    pub fn new(images: &[Image]) -> ImageSizeStatistics {
+        let widths: Vec<f64> = images.iter().map(|e| e.width as f64).collect();
+        let heights: Vec<f64> = images.iter().map(|e| e.height as f64).collect();
+        let aspect_ratios: Vec<f64> = images.iter().filter(|e| e.height > 0).map(|e| e.width as f64 / e.height as f64).collect();
+
+        let mut counts: collections::HashMap<(i32, i32), usize> = collections::HashMap::new();
+        for image in images.iter() {
+            *counts.entry((image.width, image.height)).or_insert(0) += 1;
+        }
+        let mut most_common: Vec<SizeCount> = counts.into_iter().map(|((width, height), count)| SizeCount { width, height, count }).collect();
+        most_common.sort_by(|a, b| b.count.cmp(&a.count).then((a.width, a.height).cmp(&(b.width, b.height))));
+        most_common.truncate(COMMON_SIZE_COUNT);
+
+        ImageSizeStatistics {
+            widths: Summary::new(&widths),
+            heights: Summary::new(&heights),
+            aspect_ratios: Summary::new(&aspect_ratios),
+            most_common,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LicenseCount {
+    pub license_id: i32,
+    pub name: String,
+    pub url: String,
+    pub image_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatasetStatistics {
+    pub year: i32,
+    pub version: String,
+    pub annotation_count: usize,
+    pub image_count: usize,
+    pub test_image_count: usize,
+    pub class_distribution: ClassDistribution,
+    pub imbalance: Imbalance,
+    pub regions: Vec<RegionCount>,
+    pub taxonomy: TaxonomyStatistics,
+    pub image_sizes: ImageSizeStatistics,
+    pub test_image_sizes: Option<ImageSizeStatistics>,
+    pub licenses: Vec<LicenseCount>,
+}
+
+fn gini(sorted_counts: &[usize]) -> f64 {
+    let total: usize = sorted_counts.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let n = sorted_counts.len() as f64;
+    let weighted: f64 = sorted_counts.iter().enumerate().map(|(i, e)| (i as f64 + 1.0) * *e as f64).sum();
+    (2.0 * weighted) / (n * total as f64) - (n + 1.0) / n
+}
+
+fn class_size_histogram(counts: &[usize]) -> Vec<HistogramBucket> {
+    let mut lower = 1;
+    let mut histogram = Vec::new();
+    for upper in CLASS_SIZE_BUCKETS.iter() {
+        histogram.push(HistogramBucket {
+            label: if *upper == lower + 1 { lower.to_string() } else { format!("{}-{}", lower, upper - 1) },
+            count: counts.iter().filter(|e| **e >= lower && **e < *upper).count(),
+        });
+        lower = *upper;
+    }
+    histogram.push(HistogramBucket {
+        label: format!("{}+", lower),
+        count: counts.iter().filter(|e| **e >= lower).count(),
+    });
+    histogram
+}
+
+impl DatasetStatistics {
+    pub fn new(catalog: &HerbariumCatalog) -> DatasetStatistics {
+        let train_metadata = catalog.train_metadata();
+
+        let mut class_counts: Vec<ClassCount> = catalog
+            .annotated_category_ids()
+            .into_iter()
+            .map(|category_id| {
+                let category = catalog.category(category_id);
+                ClassCount {
+                    category_id,
+                    name: category.and_then(|e| e.name.clone()),
+                    family: category.map(|e| e.family.clone()).unwrap_or_default(),
+                    genus: category.map(|e| e.genus.clone()).unwrap_or_default(),
+                    image_count: catalog.image_ids_for_category(category_id).len(),
+                }
+            })
+            .collect();
+        class_counts.sort_by(|a, b| a.image_count.cmp(&b.image_count).then(a.category_id.cmp(&b.category_id)));
+
+        let sorted_counts: Vec<usize> = class_counts.iter().map(|e| e.image_count).collect();
+        let images_per_class = Summary::new(&sorted_counts.iter().map(|e| *e as f64).collect::<Vec<f64>>());
+
+        let imbalance = if class_counts.is_empty() {
+            Imbalance::default()
+        } else {
+            Imbalance {
+                max_to_min: images_per_class.max / images_per_class.min,
+                max_to_median: images_per_class.max / images_per_class.median,
+                gini: gini(&sorted_counts),
+                singleton_classes: sorted_counts.iter().filter(|e| **e == 1).count(),
+            }
+        };
+
+        let class_distribution = ClassDistribution {
+            class_count: class_counts.len(),
+            images_per_class,
+            histogram: class_size_histogram(&sorted_counts),
+            largest: class_counts.iter().rev().take(EXTREME_CLASS_COUNT).cloned().collect(),
+            smallest: class_counts.iter().take(EXTREME_CLASS_COUNT).cloned().collect(),
+        };
+
+        let mut image_counts_by_region: collections::HashMap<i32, usize> = collections::HashMap::new();
+        for annotation in train_metadata.annotations.iter() {
+            *image_counts_by_region.entry(annotation.region_id).or_insert(0) += 1;
+        }
+        let regions = catalog
+            .annotated_region_ids()
+            .into_iter()
+            .map(|region_id| RegionCount {
+                region_id,
+                name: catalog.region(region_id).map(|e| e.name.clone()).unwrap_or_default(),
+                image_count: image_counts_by_region.get(&region_id).cloned().unwrap_or(0),
+                category_count: catalog.category_ids_for_region(region_id).len(),
+            })
+            .collect();
+
+        let taxonomy = Taxonomy::new(catalog);
+        let genera_per_family: Vec<f64> = taxonomy.families().map(|e| e.genera.len() as f64).collect();
+        let species_per_family: Vec<f64> = taxonomy.families().map(|e| e.category_count as f64).collect();
+        let species_per_genus: Vec<f64> = taxonomy.genera().map(|e| e.category_ids.len() as f64).collect();
+        let taxonomy_statistics = TaxonomyStatistics {
+            family_count: taxonomy.family_count(),
+            genus_count: taxonomy.genus_count(),
+            species_count: taxonomy.species_count(),
+            genera_per_family: Summary::new(&genera_per_family),
+            species_per_family: Summary::new(&species_per_family),
+            species_per_genus: Summary::new(&species_per_genus),
+            monotypic_genera: taxonomy.genera().filter(|e| e.category_ids.len() == 1).count(),
+        };
+
+        let mut image_counts_by_license: collections::HashMap<i32, usize> = collections::HashMap::new();
+        for image in train_metadata.images.iter() {
+            *image_counts_by_license.entry(image.license).or_insert(0) += 1;
+        }
+        let licenses = train_metadata
+            .licenses
+            .iter()
+            .map(|license| LicenseCount {
+                license_id: license.id,
+                name: license.name.clone(),
+                url: license.url.clone(),
+                image_count: image_counts_by_license.get(&license.id).cloned().unwrap_or(0),
+            })
+            .collect();
+
+        DatasetStatistics {
+            year: train_metadata.info.year,
+            version: train_metadata.info.version.clone(),
+            annotation_count: train_metadata.annotations.len(),
+            image_count: train_metadata.images.len(),
+            test_image_count: catalog.test_metadata().map(|e| e.images.len()).unwrap_or(0),
+            class_distribution,
+            imbalance,
+            regions,
+            taxonomy: taxonomy_statistics,
+            image_sizes: ImageSizeStatistics::new(&train_metadata.images),
+            test_image_sizes: catalog.test_metadata().map(|e| ImageSizeStatistics::new(&e.images)),
+            licenses,
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        writeln!(md, "# Herbarium {} dataset statistics\n", self.year).unwrap();
+        writeln!(md, "| | |\n|---|---|").unwrap();
+        writeln!(md, "| version | {} |", self.version).unwrap();
+        writeln!(md, "| annotations | {} |", self.annotation_count).unwrap();
+        writeln!(md, "| images | {} |", self.image_count).unwrap();
+        writeln!(md, "| test images | {} |", self.test_image_count).unwrap();
+        writeln!(md, "| classes | {} |\n", self.class_distribution.class_count).unwrap();
+
+        writeln!(md, "## Class distribution\n").unwrap();
+        writeln!(md, "{}", markdown_summary_table(&[("images per class", &self.class_distribution.images_per_class)])).unwrap();
+        writeln!(md, "| images per class | classes |\n|---|---|").unwrap();
+        for bucket in self.class_distribution.histogram.iter() {
+            writeln!(md, "| {} | {} |", bucket.label, bucket.count).unwrap();
+        }
+        writeln!(md).unwrap();
+        writeln!(md, "### Largest classes\n\n{}", markdown_class_table(&self.class_distribution.largest)).unwrap();
+        writeln!(md, "### Smallest classes\n\n{}", markdown_class_table(&self.class_distribution.smallest)).unwrap();
+
+        writeln!(md, "## Imbalance\n").unwrap();
+        writeln!(md, "| | |\n|---|---|").unwrap();
+        writeln!(md, "| max / min | {:.2} |", self.imbalance.max_to_min).unwrap();
+        writeln!(md, "| max / median | {:.2} |", self.imbalance.max_to_median).unwrap();
+        writeln!(md, "| gini | {:.4} |", self.imbalance.gini).unwrap();
+        writeln!(md, "| classes with a single image | {} |\n", self.imbalance.singleton_classes).unwrap();
+
+        writeln!(md, "## Regions\n").unwrap();
+        writeln!(md, "| id | region | images | classes |\n|---|---|---|---|").unwrap();
+        for region in self.regions.iter() {
+            writeln!(md, "| {} | {} | {} | {} |", region.region_id, region.name, region.image_count, region.category_count).unwrap();
+        }
+        writeln!(md).unwrap();
+
+        writeln!(md, "## Taxonomy\n").unwrap();
+        writeln!(md, "| | |\n|---|---|").unwrap();
+        writeln!(md, "| families | {} |", self.taxonomy.family_count).unwrap();
+        writeln!(md, "| genera | {} |", self.taxonomy.genus_count).unwrap();
+        writeln!(md, "| species | {} |", self.taxonomy.species_count).unwrap();
+        writeln!(md, "| monotypic genera | {} |\n", self.taxonomy.monotypic_genera).unwrap();
+        writeln!(
+            md,
+            "{}",
+            markdown_summary_table(&[
+                ("genera per family", &self.taxonomy.genera_per_family),
+                ("species per family", &self.taxonomy.species_per_family),
+                ("species per genus", &self.taxonomy.species_per_genus),
+            ])
+        )
+        .unwrap();
+
+        writeln!(md, "## Image sizes\n").unwrap();
+        let mut sizes = vec![("train", &self.image_sizes)];
+        if let Some(test_image_sizes) = self.test_image_sizes.as_ref() {
+            sizes.push(("test", test_image_sizes));
+        }
+        for (name, image_sizes) in sizes.into_iter() {
+            writeln!(md, "### {}\n", name).unwrap();
+            writeln!(
+                md,
+                "{}",
+                markdown_summary_table(&[
+                    ("width", &image_sizes.widths),
+                    ("height", &image_sizes.heights),
+                    ("aspect ratio", &image_sizes.aspect_ratios)
+                ])
+            )
+            .unwrap();
+            writeln!(md, "| size | images |\n|---|---|").unwrap();
+            for size in image_sizes.most_common.iter() {
+                writeln!(md, "| {}x{} | {} |", size.width, size.height, size.count).unwrap();
+            }
+            writeln!(md).unwrap();
+        }
+
+        writeln!(md, "## Licenses\n").unwrap();
+        writeln!(md, "| id | license | images |\n|---|---|---|").unwrap();
+        for license in self.licenses.iter() {
+            writeln!(md, "| {} | [{}]({}) | {} |", license.license_id, license.name, license.url, license.image_count).unwrap();
+        }
+        md
+    }
+
+    // no external css or scripts so the file can be attached as is
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">").unwrap();
+        writeln!(html, "<title>Herbarium {} dataset statistics</title>", self.year).unwrap();
+        writeln!(
+            html,
+            "<style>body {{ font-family: sans-serif; margin: 2em; }} table {{ border-collapse: collapse; margin-bottom: 1em; }} \
+             td, th {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }} .bar {{ background: #4a7; height: 1em; }}</style>"
+        )
+        .unwrap();
+        writeln!(html, "</head>\n<body>\n<h1>Herbarium {} dataset statistics</h1>", self.year).unwrap();
+
+        writeln!(html, "<table>").unwrap();
+        writeln!(html, "<tr><td>version</td><td>{}</td></tr>", escape_html(&self.version)).unwrap();
+        writeln!(html, "<tr><td>annotations</td><td>{}</td></tr>", self.annotation_count).unwrap();
+        writeln!(html, "<tr><td>images</td><td>{}</td></tr>", self.image_count).unwrap();
+        writeln!(html, "<tr><td>test images</td><td>{}</td></tr>", self.test_image_count).unwrap();
+        writeln!(html, "<tr><td>classes</td><td>{}</td></tr>", self.class_distribution.class_count).unwrap();
+        writeln!(html, "</table>").unwrap();
+
+        writeln!(html, "<h2>Class distribution</h2>").unwrap();
+        html.push_str(&html_summary_table(&[("images per class", &self.class_distribution.images_per_class)]));
+        let max_bucket = self.class_distribution.histogram.iter().map(|e| e.count).max().unwrap_or(0).max(1);
+        writeln!(html, "<table>\n<tr><th>images per class</th><th>classes</th><th></th></tr>").unwrap();
+        for bucket in self.class_distribution.histogram.iter() {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td style=\"width: 300px\"><div class=\"bar\" style=\"width: {:.1}%\"></div></td></tr>",
+                bucket.label,
+                bucket.count,
+                100.0 * bucket.count as f64 / max_bucket as f64
+            )
+            .unwrap();
+        }
+        writeln!(html, "</table>").unwrap();
+        writeln!(html, "<h3>Largest classes</h3>\n{}", html_class_table(&self.class_distribution.largest)).unwrap();
+        writeln!(html, "<h3>Smallest classes</h3>\n{}", html_class_table(&self.class_distribution.smallest)).unwrap();
+
+        writeln!(html, "<h2>Imbalance</h2>\n<table>").unwrap();
+        writeln!(html, "<tr><td>max / min</td><td>{:.2}</td></tr>", self.imbalance.max_to_min).unwrap();
+        writeln!(html, "<tr><td>max / median</td><td>{:.2}</td></tr>", self.imbalance.max_to_median).unwrap();
+        writeln!(html, "<tr><td>gini</td><td>{:.4}</td></tr>", self.imbalance.gini).unwrap();
+        writeln!(html, "<tr><td>classes with a single image</td><td>{}</td></tr>", self.imbalance.singleton_classes).unwrap();
+        writeln!(html, "</table>").unwrap();
+
+        writeln!(html, "<h2>Regions</h2>\n<table>\n<tr><th>id</th><th>region</th><th>images</th><th>classes</th></tr>").unwrap();
+        for region in self.regions.iter() {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                region.region_id,
+                escape_html(&region.name),
+                region.image_count,
+                region.category_count
+            )
+            .unwrap();
+        }
+        writeln!(html, "</table>").unwrap();
+
+        writeln!(html, "<h2>Taxonomy</h2>\n<table>").unwrap();
+        writeln!(html, "<tr><td>families</td><td>{}</td></tr>", self.taxonomy.family_count).unwrap();
+        writeln!(html, "<tr><td>genera</td><td>{}</td></tr>", self.taxonomy.genus_count).unwrap();
+        writeln!(html, "<tr><td>species</td><td>{}</td></tr>", self.taxonomy.species_count).unwrap();
+        writeln!(html, "<tr><td>monotypic genera</td><td>{}</td></tr>", self.taxonomy.monotypic_genera).unwrap();
+        writeln!(html, "</table>").unwrap();
+        html.push_str(&html_summary_table(&[
+            ("genera per family", &self.taxonomy.genera_per_family),
+            ("species per family", &self.taxonomy.species_per_family),
+            ("species per genus", &self.taxonomy.species_per_genus),
+        ]));
+
+        writeln!(html, "<h2>Image sizes</h2>").unwrap();
+        let mut sizes = vec![("train", &self.image_sizes)];
+        if let Some(test_image_sizes) = self.test_image_sizes.as_ref() {
+            sizes.push(("test", test_image_sizes));
+        }
+        for (name, image_sizes) in sizes.into_iter() {
+            writeln!(html, "<h3>{}</h3>", name).unwrap();
+            html.push_str(&html_summary_table(&[
+                ("width", &image_sizes.widths),
+                ("height", &image_sizes.heights),
+                ("aspect ratio", &image_sizes.aspect_ratios),
+            ]));
+            writeln!(html, "<table>\n<tr><th>size</th><th>images</th></tr>").unwrap();
+            for size in image_sizes.most_common.iter() {
+                writeln!(html, "<tr><td>{}x{}</td><td>{}</td></tr>", size.width, size.height, size.count).unwrap();
+            }
+            writeln!(html, "</table>").unwrap();
+        }
+
+        writeln!(html, "<h2>Licenses</h2>\n<table>\n<tr><th>id</th><th>license</th><th>images</th></tr>").unwrap();
+        for license in self.licenses.iter() {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td></tr>",
+                license.license_id,
+                escape_html(&license.url),
+                escape_html(&license.name),
+                license.image_count
+            )
+            .unwrap();
+        }
+        writeln!(html, "</table>\n</body>\n</html>").unwrap();
+        html
+    }
+}
+
+fn markdown_summary_table(rows: &[(&str, &Summary)]) -> String {
+    let mut md = String::from("| | min | max | mean | median | std dev |\n|---|---|---|---|---|---|\n");
+    for (name, summary) in rows.iter() {
+        writeln!(
+            md,
+            "| {} | {:.2} | {:.2} | {:.2} | {:.2} | {:.2} |",
+            name, summary.min, summary.max, summary.mean, summary.median, summary.standard_deviation
+        )
+        .unwrap();
+    }
+    md
+}
+
+fn markdown_class_table(classes: &[ClassCount]) -> String {
+    let mut md = String::from("| category | name | family | genus | images |\n|---|---|---|---|---|\n");
+    for class in classes.iter() {
+        writeln!(
+            md,
+            "| {} | {} | {} | {} | {} |",
+            class.category_id,
+            class.name.as_ref().map(|e| e.as_str()).unwrap_or(""),
+            class.family,
+            class.genus,
+            class.image_count
+        )
+        .unwrap();
+    }
+    md
+}
+
+fn html_summary_table(rows: &[(&str, &Summary)]) -> String {
+    let mut html = String::from("<table>\n<tr><th></th><th>min</th><th>max</th><th>mean</th><th>median</th><th>std dev</th></tr>\n");
+    for (name, summary) in rows.iter() {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td></tr>",
+            name, summary.min, summary.max, summary.mean, summary.median, summary.standard_deviation
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn html_class_table(classes: &[ClassCount]) -> String {
+    let mut html = String::from("<table>\n<tr><th>category</th><th>name</th><th>family</th><th>genus</th><th>images</th></tr>\n");
+    for class in classes.iter() {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            class.category_id,
+            escape_html(class.name.as_ref().map(|e| e.as_str()).unwrap_or("")),
+            escape_html(&class.family),
+            escape_html(&class.genus),
+            class.image_count
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
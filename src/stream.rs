@@ -0,0 +1,307 @@
+use crate::metadata::{v2021, v2022, ImageIdMapper, MetadataVersion};
+use crate::{Annotation, Category, Image, Info, Institution, License, Region, TestMetadata, TrainMetadata};
+use serde::de;
+use serde::Deserialize;
+use std::collections;
+use std::fmt;
+use std::io;
+use std::marker;
+
+// receives the metadata entries one at a time while the json is being read, so nothing but the current entry has to be held in memory
+pub trait MetadataSink {
+    fn info(&mut self, _info: Info) {}
+    fn annotation(&mut self, _annotation: Annotation) {}
+    fn category(&mut self, _category: Category) {}
+    fn image(&mut self, _image: Image) {}
+    fn license(&mut self, _license: License) {}
+    fn region(&mut self, _region: Region) {}
+    fn institution(&mut self, _institution: Institution) {}
+}
+
+impl MetadataSink for TrainMetadata {
+    fn info(&mut self, info: Info) {
+        self.info = info;
+    }
+
+    fn annotation(&mut self, annotation: Annotation) {
+        self.annotations.push(annotation);
+    }
+
+    fn category(&mut self, category: Category) {
+        self.categories.push(category);
+    }
+
+    fn image(&mut self, image: Image) {
+        self.images.push(image);
+    }
+
+    fn license(&mut self, license: License) {
+        self.licenses.push(license);
+    }
+
+    fn region(&mut self, region: Region) {
+        self.regions.push(region);
+    }
+
+    fn institution(&mut self, institution: Institution) {
+        self.institutions.push(institution);
+    }
+}
+
+impl MetadataSink for TestMetadata {
+    fn info(&mut self, info: Info) {
+        self.info = info;
+    }
+
+    fn image(&mut self, image: Image) {
+        self.images.push(image);
+    }
+
+    fn license(&mut self, license: License) {
+        self.licenses.push(license);
+    }
+}
+
+// keeps nothing but the counts the metrics need, memory grows with the categories & regions, not with the annotations or images
+#[derive(Debug, Default)]
+pub struct MetadataCounts {
+    pub categories: Vec<Category>,
+    pub image_count: usize,
+    pub annotation_count: usize,
+    image_count_by_category: collections::BTreeMap<i32, usize>,
+    category_ids_by_region: collections::BTreeMap<i32, collections::BTreeSet<i32>>,
+}
+
+impl MetadataSink for MetadataCounts {
+    fn annotation(&mut self, annotation: Annotation) {
+        self.annotation_count += 1;
+        *self.image_count_by_category.entry(annotation.category_id).or_insert(0) += 1;
+        self.category_ids_by_region.entry(annotation.region_id).or_default().insert(annotation.category_id);
+    }
+
+    fn category(&mut self, category: Category) {
+        self.categories.push(category);
+    }
+
+    fn image(&mut self, _image: Image) {
+        self.image_count += 1;
+    }
+}
+
+impl MetadataCounts {
+    // sorted ids of the regions that have at least one annotation
+    pub fn annotated_region_ids(&self) -> Vec<i32> {
+        self.category_ids_by_region.keys().cloned().collect()
+    }
+
+    pub fn category_count_for_region(&self, region_id: i32) -> usize {
+        self.category_ids_by_region.get(&region_id).map(|e| e.len()).unwrap_or(0)
+    }
+
+    // sorted ids of the categories that have at least one annotation
+    pub fn annotated_category_ids(&self) -> Vec<i32> {
+        self.image_count_by_category.keys().cloned().collect()
+    }
+
+    // annotated images, the same count HerbariumCatalog::image_ids_for_category gives
+    pub fn image_count_for_category(&self, category_id: i32) -> usize {
+        self.image_count_by_category.get(&category_id).cloned().unwrap_or(0)
+    }
+}
+
+// releases after 2020 have no regions, every institution is passed on as a region as well
+fn institution<S: MetadataSink>(sink: &mut S, institution: Institution) {
+    sink.region(Region {
+        id: institution.id,
+        name: institution.name.clone(),
+    });
+    sink.institution(institution);
+}
+
+// every release is read entry by entry, the entries reach the sink already normalized into the 2020 shapes
+pub fn stream_metadata<R: io::Read, S: MetadataSink>(reader: R, version: MetadataVersion, sink: &mut S) -> io::Result<()> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    de::Deserializer::deserialize_any(&mut deserializer, MetadataVisitor { sink, version })?;
+    deserializer.end()?;
+    Ok(())
+}
+
+struct MetadataVisitor<'a, S> {
+    sink: &'a mut S,
+    version: MetadataVersion,
+}
+
+impl<'de, 'a, S: MetadataSink> de::Visitor<'de> for MetadataVisitor<'a, S> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a herbarium metadata object")
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let (sink, version) = (self.sink, self.version);
+        let mut image_ids = ImageIdMapper::default();
+        while let Some(key) = map.next_key::<String>()? {
+            match (version, key.as_str()) {
+                (_, "info") => {
+                    let info: Info = map.next_value()?;
+                    sink.info(info);
+                }
+                (_, "licenses") | (_, "license") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.license(e)))?,
+                (MetadataVersion::Herbarium2020, "annotations") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.annotation(e)))?,
+                (MetadataVersion::Herbarium2020, "categories") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.category(e)))?,
+                (MetadataVersion::Herbarium2020, "images") | (MetadataVersion::Herbarium2021, "images") => {
+                    map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.image(e)))?
+                }
+                (MetadataVersion::Herbarium2020, "regions") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.region(e)))?,
+                (MetadataVersion::Herbarium2021, "annotations") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e: v2021::Annotation| s.annotation(e.into())))?,
+                (MetadataVersion::Herbarium2021, "categories") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e: v2021::Category| s.category(e.into())))?,
+                (MetadataVersion::Herbarium2021, "institutions") => {
+                    map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e: v2021::Institution| institution(s, e.into())))?
+                }
+                (MetadataVersion::Herbarium2022, "annotations") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.annotation(image_ids.annotation(e))))?,
+                (MetadataVersion::Herbarium2022, "categories") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e: v2022::Category| s.category(e.into())))?,
+                (MetadataVersion::Herbarium2022, "images") => map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e| s.image(image_ids.image(e))))?,
+                (MetadataVersion::Herbarium2022, "institutions") => {
+                    map.next_value_seed(ElementsSeed::new(&mut *sink, |s: &mut S, e: v2022::Institution| institution(s, e.into())))?
+                }
+                _ => {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the 2022 test metadata is a bare list of images
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+        let mut image_ids = ImageIdMapper::default();
+        de::Visitor::visit_seq(ElementsSeed::new(self.sink, |s: &mut S, e| s.image(image_ids.image(e))), seq)
+    }
+}
+
+struct ElementsSeed<'a, S, T, F> {
+    sink: &'a mut S,
+    emit: F,
+    marker: marker::PhantomData<T>,
+}
+
+impl<'a, S, T, F: FnMut(&mut S, T)> ElementsSeed<'a, S, T, F> {
+    fn new(sink: &'a mut S, emit: F) -> ElementsSeed<'a, S, T, F> {
+        ElementsSeed {
+            sink,
+            emit,
+            marker: marker::PhantomData,
+        }
+    }
+}
+
+impl<'de, 'a, S, T: Deserialize<'de>, F: FnMut(&mut S, T)> de::DeserializeSeed<'de> for ElementsSeed<'a, S, T, F> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a, S, T: Deserialize<'de>, F: FnMut(&mut S, T)> de::Visitor<'de> for ElementsSeed<'a, S, T, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of metadata entries")
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(element) = seq.next_element::<T>()? {
+            (self.emit)(self.sink, element);
+        }
+        Ok(())
+    }
+}
+
+// keeps the info block and the first entry of every list, which is all the version detection needs
+pub fn probe_metadata<R: io::Read>(reader: R) -> io::Result<serde_json::Value> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let value = de::Deserializer::deserialize_any(&mut deserializer, ProbeVisitor)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+struct ProbeVisitor;
+
+impl<'de> de::Visitor<'de> for ProbeVisitor {
+    type Value = serde_json::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a herbarium metadata object or list")
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<serde_json::Value, A::Error> {
+        let mut probe = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = if key == "info" { map.next_value()? } else { map.next_value_seed(FirstElementSeed)? };
+            probe.insert(key, value);
+        }
+        Ok(serde_json::Value::Object(probe))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<serde_json::Value, A::Error> {
+        de::Visitor::visit_seq(FirstElementSeed, seq)
+    }
+}
+
+struct FirstElementSeed;
+
+impl<'de> de::DeserializeSeed<'de> for FirstElementSeed {
+    type Value = serde_json::Value;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<serde_json::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> de::Visitor<'de> for FirstElementSeed {
+    type Value = serde_json::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any json value")
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<serde_json::Value, A::Error> {
+        let mut elements = Vec::new();
+        if let Some(first) = seq.next_element::<serde_json::Value>()? {
+            elements.push(first);
+        }
+        while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+        Ok(serde_json::Value::Array(elements))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<serde_json::Value, A::Error> {
+        while map.next_entry::<de::IgnoredAny, de::IgnoredAny>()?.is_some() {}
+        Ok(serde_json::Value::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, _v: bool) -> Result<serde_json::Value, E> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn visit_i64<E: de::Error>(self, _v: i64) -> Result<serde_json::Value, E> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn visit_u64<E: de::Error>(self, _v: u64) -> Result<serde_json::Value, E> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn visit_f64<E: de::Error>(self, _v: f64) -> Result<serde_json::Value, E> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn visit_str<E: de::Error>(self, _v: &str) -> Result<serde_json::Value, E> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<serde_json::Value, E> {
+        Ok(serde_json::Value::Null)
+    }
+}
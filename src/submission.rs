@@ -0,0 +1,254 @@
+use crate::label_encoder::LabelEncoder;
+use crate::manifest::{DatasetPart, Manifest};
+use crate::pipeline::PreprocessFailure;
+use crate::TestMetadata;
+use flate2::read::GzDecoder;
+use rustlearn::array;
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubmissionRow {
+    #[serde(rename = "Id")]
+    pub id: i32,
+    #[serde(rename = "Predicted")]
+    pub predicted: i32,
+}
+
+// image id -> predicted category id, kept ordered so the csv comes out in image id order
+#[derive(Debug, Clone, Default)]
+pub struct Submission {
+    predictions: collections::BTreeMap<i32, i32>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Submission {
+    // predictions are class indices (as the models return them), one per testing manifest row
+    pub fn from_predictions(testing_manifest: &Manifest, predictions: &[f32], label_encoder: &LabelEncoder) -> io::Result<Submission> {
+        let rows = testing_manifest.entries(DatasetPart::Testing);
+        if rows.len() != predictions.len() {
+            return Err(invalid_data(format!("{} predictions for {} testing rows", predictions.len(), rows.len())));
+        }
+
+        let mut submission = Submission::default();
+        for (entry, prediction) in rows.into_iter().zip(predictions.iter()) {
+            let category_id = label_encoder.decode_label(*prediction).ok_or_else(|| {
+                invalid_data(format!(
+                    "image_id: {} has a prediction outside the {} classes: {}",
+                    entry.image_id,
+                    label_encoder.class_count(),
+                    prediction
+                ))
+            })?;
+            if submission.predictions.insert(entry.image_id, category_id).is_some() {
+                return Err(invalid_data(format!("image_id: {} is predicted more than once", entry.image_id)));
+            }
+        }
+        Ok(submission)
+    }
+
+    // the test images serialize_test_data couldn't preprocess have no rows to predict, they get category_id instead so the submission stays complete
+    pub fn add_fallback(&mut self, failures: &[PreprocessFailure], category_id: i32) -> io::Result<()> {
+        for failure in failures.iter() {
+            if self.predictions.insert(failure.image_id, category_id).is_some() {
+                return Err(invalid_data(format!("image_id: {} has both a prediction and a preprocessing failure", failure.image_id)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.predictions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.predictions.is_empty()
+    }
+
+    pub fn rows(&self) -> Vec<SubmissionRow> {
+        self.predictions.iter().map(|(id, predicted)| SubmissionRow { id: *id, predicted: *predicted }).collect()
+    }
+
+    // every test image exactly once and nothing else
+    pub fn validate(&self, test_metadata: &TestMetadata) -> io::Result<()> {
+        let test_image_ids: collections::BTreeSet<i32> = test_metadata.images.iter().map(|e| e.id).collect();
+        let missing: Vec<i32> = test_image_ids.iter().filter(|e| !self.predictions.contains_key(e)).cloned().collect();
+        let unknown: Vec<i32> = self.predictions.keys().filter(|e| !test_image_ids.contains(e)).cloned().collect();
+        if !missing.is_empty() || !unknown.is_empty() {
+            return Err(invalid_data(format!(
+                "submission does not match the test images, missing: {} (first: {:?}), unknown: {} (first: {:?})",
+                missing.len(),
+                missing.first(),
+                unknown.len(),
+                unknown.first()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, submission_path: &path::Path) -> io::Result<()> {
+        let mut writer = csv::Writer::from_path(submission_path)?;
+        for row in self.rows().iter() {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// the category with the most training images, ties go to the lowest category id
+// the rows repeat an image for every rotation & oversampled copy, so images are counted once whatever the sampling did
+pub fn most_frequent_category_id(training_manifest: &Manifest) -> Option<i32> {
+    let mut image_ids_by_category: collections::BTreeMap<i32, collections::BTreeSet<i32>> = collections::BTreeMap::new();
+    for entry in training_manifest.entries.iter() {
+        if let Some(category_id) = entry.category_id {
+            image_ids_by_category.entry(category_id).or_default().insert(entry.image_id);
+        }
+    }
+    image_ids_by_category
+        .into_iter()
+        .map(|(category_id, image_ids)| (category_id, image_ids.len()))
+        .max_by_key(|(category_id, count)| (*count, cmp::Reverse(*category_id)))
+        .map(|(category_id, _)| category_id)
+}
+
+// features at or below this are left out of the sparse arrays the rustlearn models are fit on & predict, training, validation & testing have to be cut the same way
+pub const SPARSE_THRESHOLD: f32 = 0.04;
+
+// the (column, value) pairs of a row that make it into the sparse array
+pub fn sparse_features(row: Vec<f32>) -> Vec<(usize, f32)> {
+    row.into_iter().enumerate().filter(|e| e.1 > SPARSE_THRESHOLD).collect()
+}
+
+pub fn to_sparse_array(rows: Vec<Vec<f32>>, cols: usize) -> array::sparse::SparseRowArray {
+    let mut sparse_array = array::sparse::SparseRowArray::zeros(rows.len(), cols);
+    for (i, row) in rows.into_iter().enumerate() {
+        for (j, value) in sparse_features(row).into_iter() {
+            sparse_array.set(i, j, value);
+        }
+    }
+    sparse_array
+}
+
+// serialize_test_data writes the sparse array as is, already cut at SPARSE_THRESHOLD
+pub fn read_testing_data(serialization_dir: &path::Path, width: u32, height: u32) -> io::Result<array::sparse::SparseRowArray> {
+    let mut testing_data_path = serialization_dir.to_path_buf();
+    testing_data_path.push(DatasetPart::Testing.data_file_name(width, height));
+    debug!("reading: {}", testing_data_path.to_string_lossy());
+
+    let testing_data_reader = io::BufReader::new(fs::File::open(testing_data_path.as_path())?);
+    let mut testing_data_decoder = GzDecoder::new(testing_data_reader);
+    bincode::deserialize_from(&mut testing_data_decoder).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestEntry;
+    use crate::Image;
+
+    fn testing_manifest(image_ids: &[i32]) -> Manifest {
+        Manifest {
+            entries: image_ids
+                .iter()
+                .map(|image_id| ManifestEntry {
+                    image_id: *image_id,
+                    file_path: format!("test/images/{}.jpg", image_id),
+                    category_id: None,
+                    region_id: None,
+                    split: DatasetPart::Testing,
+                    augmentation: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn test_metadata(image_ids: &[i32]) -> TestMetadata {
+        TestMetadata {
+            images: image_ids
+                .iter()
+                .map(|image_id| Image {
+                    id: *image_id,
+                    width: 0,
+                    height: 0,
+                    file_name: format!("images/{}.jpg", image_id),
+                    license: 0,
+                    source_id: None,
+                })
+                .collect(),
+            ..TestMetadata::default()
+        }
+    }
+
+    fn failure(image_id: i32) -> PreprocessFailure {
+        PreprocessFailure {
+            image_id,
+            file_path: format!("test/images/{}.jpg", image_id),
+            split: DatasetPart::Testing,
+            step: "open".to_string(),
+            reason: "unreadable".to_string(),
+        }
+    }
+
+    fn label_encoder() -> LabelEncoder {
+        vec![10, 20, 30].into_iter().collect()
+    }
+
+    #[test]
+    fn decodes_one_prediction_per_row() {
+        let submission = Submission::from_predictions(&testing_manifest(&[3, 1, 2]), &[2.0, 0.0, 1.0], &label_encoder()).unwrap();
+        assert_eq!(
+            submission.rows(),
+            vec![
+                SubmissionRow { id: 1, predicted: 10 },
+                SubmissionRow { id: 2, predicted: 20 },
+                SubmissionRow { id: 3, predicted: 30 }
+            ]
+        );
+        assert!(submission.validate(&test_metadata(&[1, 2, 3])).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_prediction_count_that_doesnt_match_the_rows() {
+        assert!(Submission::from_predictions(&testing_manifest(&[1, 2]), &[0.0], &label_encoder()).is_err());
+        assert!(Submission::from_predictions(&testing_manifest(&[1]), &[0.0, 1.0], &label_encoder()).is_err());
+    }
+
+    #[test]
+    fn rejects_an_image_predicted_twice() {
+        assert!(Submission::from_predictions(&testing_manifest(&[1, 2, 1]), &[0.0, 1.0, 2.0], &label_encoder()).is_err());
+    }
+
+    #[test]
+    fn rejects_a_prediction_outside_the_classes() {
+        assert!(Submission::from_predictions(&testing_manifest(&[1, 2]), &[0.0, 3.0], &label_encoder()).is_err());
+        assert!(Submission::from_predictions(&testing_manifest(&[1]), &[-1.0], &label_encoder()).is_err());
+    }
+
+    #[test]
+    fn validate_rejects_missing_and_unknown_images() {
+        let submission = Submission::from_predictions(&testing_manifest(&[1, 2]), &[0.0, 1.0], &label_encoder()).unwrap();
+        assert!(submission.validate(&test_metadata(&[1, 2, 3])).is_err());
+        assert!(submission.validate(&test_metadata(&[1])).is_err());
+        assert!(submission.validate(&test_metadata(&[1, 3])).is_err());
+    }
+
+    #[test]
+    fn fallback_covers_the_failed_images() {
+        let mut submission = Submission::from_predictions(&testing_manifest(&[1, 3]), &[0.0, 1.0], &label_encoder()).unwrap();
+        assert!(submission.validate(&test_metadata(&[1, 2, 3])).is_err());
+        submission.add_fallback(&[failure(2)], 30).unwrap();
+        assert!(submission.validate(&test_metadata(&[1, 2, 3])).is_ok());
+        assert_eq!(submission.rows()[1], SubmissionRow { id: 2, predicted: 30 });
+
+        // an image with a prediction can't also have failed
+        assert!(submission.add_fallback(&[failure(3)], 30).is_err());
+    }
+}
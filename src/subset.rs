@@ -0,0 +1,253 @@
+use crate::{Annotation, Category, HerbariumCatalog, Image, Info, Institution, License, Region, TestMetadata, TrainMetadata};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fs;
+use std::io;
+use std::path;
+use strum_macros::{Display, EnumString};
+
+// 0 means everything for the counts, region_id restricts both the categories and their images to one region
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubsetConfig {
+    pub seed: u64,
+    pub region_id: Option<i32>,
+    pub category_count: usize,
+    pub images_per_category: usize,
+    pub min_images_per_category: usize,
+    pub test_image_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, EnumString, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum LinkMode {
+    Copy,
+    HardLink,
+    Symlink,
+}
+
+// new id -> original id, written next to the subset so results can be traced back to the full dataset
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SubsetReport {
+    pub config: Option<SubsetConfig>,
+    pub source_version: String,
+    pub categories: Vec<(i32, i32)>,
+    pub images: Vec<(i32, i32)>,
+    pub test_images: Vec<(i32, i32)>,
+}
+
+impl SubsetReport {
+    pub fn write(&self, report_path: &path::Path) -> io::Result<()> {
+        let report_file = fs::File::create(report_path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(report_file), self)?;
+        Ok(())
+    }
+}
+
+pub struct Subset {
+    pub train_metadata: TrainMetadata,
+    pub test_metadata: Option<TestMetadata>,
+    pub report: SubsetReport,
+}
+
+// the subset is written in the normalized (2020) layout and MetadataVersion::detect keys on info.year, so the year has to say 2020 for the existing readers to pick it up
+fn subset_info(info: &Info) -> Info {
+    Info {
+        year: 2020,
+        version: format!("{} subset", info.version).trim_start().to_string(),
+        url: info.url.clone(),
+        description: format!("subset of the {} release: {}", info.year, info.description),
+        contributor: info.contributor.clone(),
+        date_created: info.date_created.clone(),
+    }
+}
+
+fn copy_image(image: &Image, id: i32) -> Image {
+    Image {
+        id,
+        width: image.width,
+        height: image.height,
+        file_name: image.file_name.clone(),
+        license: image.license,
+        source_id: image.source_id.clone(),
+    }
+}
+
+fn copy_licenses(licenses: &[License], images: &[Image]) -> Vec<License> {
+    let license_ids: collections::HashSet<i32> = images.iter().map(|e| e.license).collect();
+    licenses
+        .iter()
+        .filter(|e| license_ids.contains(&e.id))
+        .map(|e| License {
+            id: e.id,
+            name: e.name.clone(),
+            url: e.url.clone(),
+        })
+        .collect()
+}
+
+// categories & images are renumbered from 0 in category order, regions, licenses & institutions keep their ids but only the referenced ones are kept
+pub fn select_subset(catalog: &HerbariumCatalog, config: &SubsetConfig) -> Subset {
+    let train_metadata = catalog.train_metadata();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let image_ids_for = |category_id: i32| -> Vec<i32> {
+        match config.region_id {
+            Some(region_id) => catalog.image_ids_for_region_and_category(region_id, category_id).to_vec(),
+            None => catalog.image_ids_for_category(category_id).to_vec(),
+        }
+    };
+
+    let mut candidates: Vec<i32> = match config.region_id {
+        Some(region_id) => catalog.category_ids_for_region(region_id).to_vec(),
+        None => catalog.annotated_category_ids(),
+    };
+    candidates.sort();
+    candidates.retain(|e| image_ids_for(*e).len() >= config.min_images_per_category.max(1));
+    candidates.shuffle(&mut rng);
+    if config.category_count > 0 {
+        candidates.truncate(config.category_count);
+    }
+    candidates.sort();
+
+    let mut subset = TrainMetadata::default();
+    let mut report = SubsetReport {
+        config: Some(config.clone()),
+        source_version: format!("{} {}", train_metadata.info.year, train_metadata.info.version).trim_end().to_string(),
+        ..SubsetReport::default()
+    };
+    let mut new_image_ids: collections::HashMap<i32, i32> = collections::HashMap::new();
+
+    for (new_category_id, category_id) in candidates.iter().enumerate() {
+        let new_category_id = new_category_id as i32;
+        let category = catalog.category(*category_id).unwrap();
+        subset.categories.push(Category {
+            id: new_category_id,
+            name: category.name.clone(),
+            family: category.family.clone(),
+            genus: category.genus.clone(),
+            species: category.species.clone(),
+            order: category.order.clone(),
+            authors: category.authors.clone(),
+        });
+        report.categories.push((new_category_id, *category_id));
+
+        let mut image_ids = image_ids_for(*category_id);
+        image_ids.sort();
+        image_ids.dedup();
+        image_ids.shuffle(&mut rng);
+        if config.images_per_category > 0 {
+            image_ids.truncate(config.images_per_category);
+        }
+        image_ids.sort();
+
+        for image_id in image_ids.into_iter() {
+            // an image annotated with more than one selected category is only taken once
+            if new_image_ids.contains_key(&image_id) {
+                continue;
+            }
+            let new_image_id = subset.images.len() as i32;
+            new_image_ids.insert(image_id, new_image_id);
+            subset.images.push(copy_image(catalog.image(image_id).unwrap(), new_image_id));
+            report.images.push((new_image_id, image_id));
+        }
+    }
+
+    let new_category_ids: collections::HashMap<i32, i32> = report.categories.iter().map(|(new_id, id)| (*id, *new_id)).collect();
+    for annotation in train_metadata.annotations.iter() {
+        if config.region_id.map(|e| e != annotation.region_id).unwrap_or(false) {
+            continue;
+        }
+        if let (Some(image_id), Some(category_id)) = (new_image_ids.get(&annotation.image_id), new_category_ids.get(&annotation.category_id)) {
+            subset.annotations.push(Annotation {
+                id: subset.annotations.len() as i32,
+                image_id: *image_id,
+                category_id: *category_id,
+                region_id: annotation.region_id,
+                institution_id: annotation.institution_id,
+            });
+        }
+    }
+
+    let region_ids: collections::HashSet<i32> = subset.annotations.iter().map(|e| e.region_id).collect();
+    subset.regions = train_metadata
+        .regions
+        .iter()
+        .filter(|e| region_ids.contains(&e.id))
+        .map(|e| Region { id: e.id, name: e.name.clone() })
+        .collect();
+    let institution_ids: collections::HashSet<i32> = subset.annotations.iter().filter_map(|e| e.institution_id).collect();
+    subset.institutions = train_metadata
+        .institutions
+        .iter()
+        .filter(|e| institution_ids.contains(&e.id))
+        .map(|e| Institution { id: e.id, name: e.name.clone() })
+        .collect();
+    subset.licenses = copy_licenses(&train_metadata.licenses, &subset.images);
+    subset.info = subset_info(&train_metadata.info);
+
+    let test_metadata = match catalog.test_metadata() {
+        Some(test_metadata) if config.test_image_count > 0 => {
+            let mut test_images: Vec<&Image> = test_metadata.images.iter().collect();
+            test_images.shuffle(&mut rng);
+            test_images.truncate(config.test_image_count);
+            test_images.sort_by_key(|e| e.id);
+
+            let images: Vec<Image> = test_images.iter().enumerate().map(|(idx, e)| copy_image(e, idx as i32)).collect();
+            report.test_images = test_images.iter().enumerate().map(|(idx, e)| (idx as i32, e.id)).collect();
+            Some(TestMetadata {
+                licenses: copy_licenses(&test_metadata.licenses, &images),
+                info: subset_info(&test_metadata.info),
+                images,
+            })
+        }
+        _ => None,
+    };
+
+    Subset {
+        train_metadata: subset,
+        test_metadata,
+        report,
+    }
+}
+
+fn link_file(source: &path::Path, target: &path::Path, link_mode: LinkMode) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if target.exists() {
+        fs::remove_file(target)?;
+    }
+    match link_mode {
+        LinkMode::Copy => fs::copy(source, target).map(|_| ()),
+        LinkMode::HardLink => fs::hard_link(source, target),
+        #[cfg(unix)]
+        LinkMode::Symlink => std::os::unix::fs::symlink(fs::canonicalize(source)?, target),
+        #[cfg(not(unix))]
+        LinkMode::Symlink => Err(io::Error::new(io::ErrorKind::Other, "symlinks are only supported on unix")),
+    }
+}
+
+// file names are kept, so the images land at the same relative paths under the new split dir
+pub fn link_images(source_split_dir: &path::Path, target_split_dir: &path::Path, images: &[Image], link_mode: LinkMode) -> io::Result<usize> {
+    for image in images.iter() {
+        let mut source = source_split_dir.to_path_buf();
+        source.push(image.file_name.as_str());
+        let mut target = target_split_dir.to_path_buf();
+        target.push(image.file_name.as_str());
+        link_file(source.as_path(), target.as_path(), link_mode)?;
+    }
+    Ok(images.len())
+}
+
+pub fn write_metadata<T: Serialize>(metadata_path: &path::Path, metadata: &T) -> io::Result<()> {
+    if let Some(parent) = metadata_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let metadata_file = fs::File::create(metadata_path)?;
+    serde_json::to_writer(io::BufWriter::new(metadata_file), metadata)?;
+    Ok(())
+}
@@ -0,0 +1,314 @@
+use crate::subset;
+use crate::{Annotation, Category, Image, Info, License, Region, TestMetadata, TrainMetadata};
+use image::{ImageBuffer, Rgb, RgbImage};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path;
+
+// sizes default to roughly the scans preprocessing_step_1 - step_4 were tuned on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyntheticConfig {
+    pub seed: u64,
+    pub family_count: usize,
+    pub genera_per_family: usize,
+    pub species_per_genus: usize,
+    pub region_count: usize,
+    pub images_per_category: usize,
+    pub test_images_per_category: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> SyntheticConfig {
+        SyntheticConfig {
+            seed: 0,
+            family_count: 2,
+            genera_per_family: 2,
+            species_per_genus: 2,
+            region_count: 2,
+            images_per_category: 5,
+            test_images_per_category: 2,
+            width: 680,
+            height: 1000,
+        }
+    }
+}
+
+// the test metadata carries no labels, this is the answer key for it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestLabel {
+    pub image_id: i32,
+    pub category_id: i32,
+}
+
+pub struct SyntheticDataset {
+    pub train_metadata: TrainMetadata,
+    pub test_metadata: TestMetadata,
+    pub test_labels: Vec<TestLabel>,
+}
+
+// the class structure is fixed by the config: category ids run family by family, genus by genus, and each category lives in one region
+pub fn generate_metadata(config: &SyntheticConfig) -> SyntheticDataset {
+    let info = Info {
+        year: 2020,
+        version: "synthetic".to_string(),
+        url: String::new(),
+        description: format!("synthetic herbarium sheets, seed {}", config.seed),
+        contributor: "rusty-herbarium".to_string(),
+        date_created: String::new(),
+    };
+    let license = || License {
+        id: 0,
+        name: "Public Domain".to_string(),
+        url: "https://creativecommons.org/publicdomain/zero/1.0/".to_string(),
+    };
+
+    let mut train_metadata = TrainMetadata::default();
+    train_metadata.info = info;
+    train_metadata.licenses.push(license());
+    train_metadata.regions = (0..config.region_count.max(1))
+        .map(|region_id| Region {
+            id: region_id as i32,
+            name: format!("Region {}", region_id),
+        })
+        .collect();
+
+    let mut test_metadata = TestMetadata::default();
+    test_metadata.info = Info {
+        version: "synthetic".to_string(),
+        year: 2020,
+        ..Info::default()
+    };
+    test_metadata.licenses.push(license());
+    let mut test_labels = Vec::new();
+
+    let mut category_id = 0;
+    for family in 0..config.family_count {
+        for genus in 0..config.genera_per_family {
+            for species in 0..config.species_per_genus {
+                let family_name = format!("Familia{}aceae", family);
+                let genus_name = format!("Genus{}x{}", family, genus);
+                train_metadata.categories.push(Category {
+                    id: category_id,
+                    name: Some(format!("{} species{}", genus_name, species)),
+                    family: family_name,
+                    genus: genus_name,
+                    species: Some(format!("species{}", species)),
+                    order: None,
+                    authors: None,
+                });
+
+                let region_id = category_id % train_metadata.regions.len() as i32;
+                for _ in 0..config.images_per_category {
+                    let image_id = train_metadata.images.len() as i32;
+                    train_metadata.images.push(Image {
+                        id: image_id,
+                        width: config.width as i32,
+                        height: config.height as i32,
+                        file_name: format!("images/{:03}/{}.jpg", category_id, image_id),
+                        license: 0,
+                        source_id: None,
+                    });
+                    train_metadata.annotations.push(Annotation {
+                        id: image_id,
+                        image_id,
+                        category_id,
+                        region_id,
+                        institution_id: None,
+                    });
+                }
+
+                for _ in 0..config.test_images_per_category {
+                    let image_id = test_metadata.images.len() as i32;
+                    test_metadata.images.push(Image {
+                        id: image_id,
+                        width: config.width as i32,
+                        height: config.height as i32,
+                        file_name: format!("images/{:03}/{}.jpg", image_id / 100, image_id),
+                        license: 0,
+                        source_id: None,
+                    });
+                    test_labels.push(TestLabel { image_id, category_id });
+                }
+                category_id += 1;
+            }
+        }
+    }
+
+    SyntheticDataset {
+        train_metadata,
+        test_metadata,
+        test_labels,
+    }
+}
+
+// the traits that separate the classes, family sets the hue, genus the leaf angle and species the leaf count
+struct PlantTraits {
+    stem_color: Rgb<u8>,
+    leaf_color: Rgb<u8>,
+    leaf_pairs: u32,
+    leaf_angle: f32,
+}
+
+fn plant_traits(config: &SyntheticConfig, category_id: i32) -> PlantTraits {
+    let per_family = (config.genera_per_family * config.species_per_genus).max(1) as i32;
+    let family = category_id / per_family;
+    let genus = (category_id % per_family) / config.species_per_genus.max(1) as i32;
+    let species = category_id % config.species_per_genus.max(1) as i32;
+
+    let hue = 70.0 + 80.0 * family as f32 / config.family_count.max(1) as f32;
+    PlantTraits {
+        stem_color: hsv_to_rgb(hue, 0.6, 0.35),
+        leaf_color: hsv_to_rgb(hue, 0.7, 0.55),
+        leaf_pairs: 3 + species as u32 * 2,
+        leaf_angle: 0.3 + 0.9 * genus as f32 / config.genera_per_family.max(1) as f32,
+    }
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> Rgb<u8> {
+    let chroma = value * saturation;
+    let sector = (hue / 60.0) % 6.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+    Rgb([((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8])
+}
+
+fn fill_rect(img: &mut RgbImage, x0: i64, y0: i64, x1: i64, y1: i64, color: Rgb<u8>) {
+    let (width, height) = (img.width() as i64, img.height() as i64);
+    for y in y0.max(0)..y1.min(height) {
+        for x in x0.max(0)..x1.min(width) {
+            img.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+fn draw_line(img: &mut RgbImage, from: (f32, f32), to: (f32, f32), thickness: i64, color: Rgb<u8>) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as i64;
+    let half = thickness / 2;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let x = (from.0 + (to.0 - from.0) * t) as i64;
+        let y = (from.1 + (to.1 - from.1) * t) as i64;
+        fill_rect(img, x - half, y - half, x - half + thickness, y - half + thickness, color);
+    }
+}
+
+// dark scanner bed, cream sheet, color checker + ruler at the top, label box in the lower right and the plant in the middle
+pub fn draw_sheet(config: &SyntheticConfig, category_id: i32, rng: &mut StdRng) -> RgbImage {
+    let (width, height) = (config.width as i64, config.height as i64);
+    let mut img: RgbImage = ImageBuffer::from_pixel(config.width, config.height, Rgb([28, 28, 30]));
+
+    let margin_x = width / 20 + rng.gen_range(-3, 4);
+    let margin_y = height / 40 + rng.gen_range(-3, 4);
+    let paper = Rgb([rng.gen_range(225, 240), rng.gen_range(220, 232), rng.gen_range(200, 215)]);
+    fill_rect(&mut img, margin_x, margin_y, width - margin_x, height - margin_y, paper);
+
+    let patch = (width / 24).max(4);
+    let bar_x = margin_x + patch / 2;
+    let bar_y = margin_y + patch / 2;
+    for idx in 0..8 {
+        let color = hsv_to_rgb(idx as f32 * 45.0, 0.9, 0.9);
+        fill_rect(&mut img, bar_x + idx * patch, bar_y, bar_x + (idx + 1) * patch, bar_y + patch, color);
+    }
+    let ruler_y = bar_y + patch + patch / 2;
+    let ruler_length = patch * 8;
+    fill_rect(&mut img, bar_x, ruler_y, bar_x + ruler_length, ruler_y + patch / 2, Rgb([250, 250, 250]));
+    for tick in (0..ruler_length).step_by((patch / 4).max(2) as usize) {
+        let tick_height = if tick % patch == 0 { patch / 2 } else { patch / 4 };
+        fill_rect(&mut img, bar_x + tick, ruler_y, bar_x + tick + 1, ruler_y + tick_height, Rgb([10, 10, 10]));
+    }
+
+    let label_width = width / 3;
+    let label_height = height / 8;
+    let label_x = width - margin_x - label_width - patch / 2;
+    let label_y = height - margin_y - label_height - patch / 2;
+    fill_rect(&mut img, label_x - 1, label_y - 1, label_x + label_width + 1, label_y + label_height + 1, Rgb([60, 60, 60]));
+    fill_rect(&mut img, label_x, label_y, label_x + label_width, label_y + label_height, Rgb([248, 248, 244]));
+    let line_height = (label_height / 6).max(3);
+    for line in 1..5 {
+        let mut x = label_x + line_height;
+        let y = label_y + line * line_height;
+        while x < label_x + label_width - line_height {
+            let word = rng.gen_range(line_height, line_height * 4);
+            fill_rect(&mut img, x, y, (x + word).min(label_x + label_width - line_height), y + line_height / 2, Rgb([40, 40, 60]));
+            x += word + line_height / 2;
+        }
+    }
+
+    let traits = plant_traits(config, category_id);
+    let base = (width as f32 / 2.0 + rng.gen_range(-20.0, 20.0), (height - margin_y) as f32 - label_height as f32 * 0.5);
+    let top = (base.0 + rng.gen_range(-40.0, 40.0), (margin_y as f32) + height as f32 * 0.2);
+    let stem_thickness = (width / 120).max(2);
+    draw_line(&mut img, base, top, stem_thickness, traits.stem_color);
+
+    let leaf_length = height as f32 * 0.08;
+    for pair in 0..traits.leaf_pairs {
+        let t = (pair as f32 + 1.0) / (traits.leaf_pairs as f32 + 1.0);
+        let node = (base.0 + (top.0 - base.0) * t, base.1 + (top.1 - base.1) * t);
+        for side in [-1.0f32, 1.0].iter() {
+            let angle = traits.leaf_angle + rng.gen_range(-0.1, 0.1);
+            let length = leaf_length * rng.gen_range(0.8, 1.2);
+            let tip = (node.0 + side * length * angle.cos(), node.1 - length * angle.sin());
+            draw_line(&mut img, node, tip, stem_thickness * 3, traits.leaf_color);
+        }
+    }
+    img
+}
+
+// writes base_dir/train & base_dir/test the way the competition archives unpack, plus the test answer key as csv
+pub fn generate(base_dir: &path::Path, config: &SyntheticConfig) -> io::Result<SyntheticDataset> {
+    let dataset = generate_metadata(config);
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    let mut train_dir = base_dir.to_path_buf();
+    train_dir.push("train");
+    for annotation in dataset.train_metadata.annotations.iter() {
+        let image = &dataset.train_metadata.images[annotation.image_id as usize];
+        write_sheet(train_dir.as_path(), image, draw_sheet(config, annotation.category_id, &mut rng))?;
+    }
+    let mut train_metadata_path = train_dir.clone();
+    train_metadata_path.push("metadata.json");
+    subset::write_metadata(train_metadata_path.as_path(), &dataset.train_metadata)?;
+
+    let mut test_dir = base_dir.to_path_buf();
+    test_dir.push("test");
+    for label in dataset.test_labels.iter() {
+        let image = &dataset.test_metadata.images[label.image_id as usize];
+        write_sheet(test_dir.as_path(), image, draw_sheet(config, label.category_id, &mut rng))?;
+    }
+    let mut test_metadata_path = test_dir.clone();
+    test_metadata_path.push("metadata.json");
+    subset::write_metadata(test_metadata_path.as_path(), &dataset.test_metadata)?;
+
+    let mut test_labels_path = base_dir.to_path_buf();
+    test_labels_path.push("synthetic-test-labels.csv");
+    let mut writer = csv::Writer::from_path(test_labels_path.as_path())?;
+    for label in dataset.test_labels.iter() {
+        writer.serialize(label)?;
+    }
+    writer.flush()?;
+
+    Ok(dataset)
+}
+
+fn write_sheet(split_dir: &path::Path, image: &Image, img: RgbImage) -> io::Result<()> {
+    let mut image_path = split_dir.to_path_buf();
+    image_path.push(image.file_name.as_str());
+    if let Some(parent) = image_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    img.save(image_path.as_path()).map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+}
@@ -0,0 +1,164 @@
+use crate::stream::MetadataCounts;
+use crate::{Category, HerbariumCatalog};
+use serde::{Deserialize, Serialize};
+use std::collections;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FamilyNode {
+    pub name: String,
+    pub genera: Vec<String>,
+    pub category_count: usize,
+    pub image_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenusNode {
+    pub name: String,
+    pub family: String,
+    pub category_ids: Vec<i32>,
+    pub image_count: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpeciesNode {
+    pub category_id: i32,
+    pub name: Option<String>,
+    pub family: String,
+    pub genus: String,
+    pub image_count: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lineage<'a> {
+    pub family: &'a FamilyNode,
+    pub genus: &'a GenusNode,
+    pub species: &'a SpeciesNode,
+}
+
+// family -> genus -> species (category) tree derived from the flat category family/genus strings
+// genera are keyed by (family, genus) since the same genus name can show up under more than one family
+#[derive(Debug, Default)]
+pub struct Taxonomy {
+    families: collections::BTreeMap<String, FamilyNode>,
+    genera: collections::BTreeMap<(String, String), GenusNode>,
+    species: collections::BTreeMap<i32, SpeciesNode>,
+}
+
+impl Taxonomy {
+    pub fn new(catalog: &HerbariumCatalog) -> Taxonomy {
+        Taxonomy::from_categories(&catalog.train_metadata().categories, |category_id| catalog.image_ids_for_category(category_id).len())
+    }
+
+    // the same tree from the counts streamed out of the metadata, without a catalog holding every annotation
+    pub fn from_counts(counts: &MetadataCounts) -> Taxonomy {
+        Taxonomy::from_categories(&counts.categories, |category_id| counts.image_count_for_category(category_id))
+    }
+
+    fn from_categories<F: Fn(i32) -> usize>(categories: &[Category], image_count_for_category: F) -> Taxonomy {
+        let mut taxonomy = Taxonomy::default();
+
+        for category in categories.iter() {
+            let image_count = image_count_for_category(category.id);
+
+            taxonomy.species.insert(
+                category.id,
+                SpeciesNode {
+                    category_id: category.id,
+                    name: category.name.clone(),
+                    family: category.family.clone(),
+                    genus: category.genus.clone(),
+                    image_count,
+                },
+            );
+
+            let genus = taxonomy.genera.entry((category.family.clone(), category.genus.clone())).or_insert_with(|| GenusNode {
+                name: category.genus.clone(),
+                family: category.family.clone(),
+                category_ids: Vec::new(),
+                image_count: 0,
+            });
+            genus.category_ids.push(category.id);
+            genus.image_count += image_count;
+
+            let family = taxonomy.families.entry(category.family.clone()).or_insert_with(|| FamilyNode {
+                name: category.family.clone(),
+                genera: Vec::new(),
+                category_count: 0,
+                image_count: 0,
+            });
+            if !family.genera.contains(&category.genus) {
+                family.genera.push(category.genus.clone());
+            }
+            family.category_count += 1;
+            family.image_count += image_count;
+        }
+
+        for family in taxonomy.families.values_mut() {
+            family.genera.sort();
+        }
+        for genus in taxonomy.genera.values_mut() {
+            genus.category_ids.sort();
+        }
+
+        taxonomy
+    }
+
+    pub fn families(&self) -> impl Iterator<Item = &FamilyNode> {
+        self.families.values()
+    }
+
+    pub fn genera(&self) -> impl Iterator<Item = &GenusNode> {
+        self.genera.values()
+    }
+
+    pub fn species(&self) -> impl Iterator<Item = &SpeciesNode> {
+        self.species.values()
+    }
+
+    pub fn family(&self, family: &str) -> Option<&FamilyNode> {
+        self.families.get(family)
+    }
+
+    pub fn genus(&self, family: &str, genus: &str) -> Option<&GenusNode> {
+        self.genera.get(&(family.to_string(), genus.to_string()))
+    }
+
+    pub fn species_by_category(&self, category_id: i32) -> Option<&SpeciesNode> {
+        self.species.get(&category_id)
+    }
+
+    pub fn genera_of(&self, family: &FamilyNode) -> Vec<&GenusNode> {
+        family.genera.iter().filter_map(|genus| self.genus(&family.name, genus)).collect()
+    }
+
+    pub fn species_of(&self, genus: &GenusNode) -> Vec<&SpeciesNode> {
+        genus.category_ids.iter().filter_map(|category_id| self.species.get(category_id)).collect()
+    }
+
+    pub fn family_of(&self, genus: &GenusNode) -> Option<&FamilyNode> {
+        self.families.get(&genus.family)
+    }
+
+    pub fn genus_of(&self, species: &SpeciesNode) -> Option<&GenusNode> {
+        self.genus(&species.family, &species.genus)
+    }
+
+    pub fn ancestors(&self, category_id: i32) -> Option<Lineage<'_>> {
+        let species = self.species.get(&category_id)?;
+        let genus = self.genus_of(species)?;
+        let family = self.family_of(genus)?;
+        Some(Lineage { family, genus, species })
+    }
+
+    pub fn family_count(&self) -> usize {
+        self.families.len()
+    }
+
+    pub fn genus_count(&self) -> usize {
+        self.genera.len()
+    }
+
+    pub fn species_count(&self) -> usize {
+        self.species.len()
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{Image, TestMetadata, TrainMetadata};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections;
+use std::fmt;
+use std::path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ValidationIssue {
+    UnparsableMetadata {
+        path: String,
+        reason: String,
+    },
+    DuplicateId {
+        collection: String,
+        id: i32,
+        count: usize,
+    },
+    DanglingImageReference {
+        annotation_id: i32,
+        image_id: i32,
+    },
+    DanglingCategoryReference {
+        annotation_id: i32,
+        category_id: i32,
+    },
+    DanglingRegionReference {
+        annotation_id: i32,
+        region_id: i32,
+    },
+    DanglingLicenseReference {
+        image_id: i32,
+        license_id: i32,
+    },
+    MissingImageFile {
+        image_id: i32,
+        path: String,
+    },
+    UnreadableImageFile {
+        image_id: i32,
+        path: String,
+        reason: String,
+    },
+    DimensionMismatch {
+        image_id: i32,
+        path: String,
+        expected_width: i32,
+        expected_height: i32,
+        actual_width: u32,
+        actual_height: u32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ValidationReport {
+    pub train_issues: Vec<ValidationIssue>,
+    pub test_issues: Vec<ValidationIssue>,
+    pub train_images_checked: usize,
+    pub test_images_checked: usize,
+}
+
+impl ValidationReport {
+    pub fn issue_count(&self) -> usize {
+        self.train_issues.len() + self.test_issues.len()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.issue_count() > 0
+    }
+}
+
+fn duplicate_ids<I: Iterator<Item = i32>>(collection: &str, ids: I) -> Vec<ValidationIssue> {
+    let mut counts: collections::BTreeMap<i32, usize> = collections::BTreeMap::new();
+    for id in ids {
+        *counts.entry(id).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(id, count)| ValidationIssue::DuplicateId {
+            collection: collection.to_string(),
+            id,
+            count,
+        })
+        .collect()
+}
+
+pub fn validate_train_metadata(metadata: &TrainMetadata) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    issues.extend(duplicate_ids("annotations", metadata.annotations.iter().map(|e| e.id)));
+    issues.extend(duplicate_ids("images", metadata.images.iter().map(|e| e.id)));
+    issues.extend(duplicate_ids("categories", metadata.categories.iter().map(|e| e.id)));
+    issues.extend(duplicate_ids("regions", metadata.regions.iter().map(|e| e.id)));
+    issues.extend(duplicate_ids("licenses", metadata.licenses.iter().map(|e| e.id)));
+
+    let image_ids: collections::HashSet<i32> = metadata.images.iter().map(|e| e.id).collect();
+    let category_ids: collections::HashSet<i32> = metadata.categories.iter().map(|e| e.id).collect();
+    let region_ids: collections::HashSet<i32> = metadata.regions.iter().map(|e| e.id).collect();
+    let license_ids: collections::HashSet<i32> = metadata.licenses.iter().map(|e| e.id).collect();
+
+    for annotation in metadata.annotations.iter() {
+        if !image_ids.contains(&annotation.image_id) {
+            issues.push(ValidationIssue::DanglingImageReference {
+                annotation_id: annotation.id,
+                image_id: annotation.image_id,
+            });
+        }
+        if !category_ids.contains(&annotation.category_id) {
+            issues.push(ValidationIssue::DanglingCategoryReference {
+                annotation_id: annotation.id,
+                category_id: annotation.category_id,
+            });
+        }
+        if !region_ids.contains(&annotation.region_id) {
+            issues.push(ValidationIssue::DanglingRegionReference {
+                annotation_id: annotation.id,
+                region_id: annotation.region_id,
+            });
+        }
+    }
+
+    issues.extend(dangling_licenses(&metadata.images, &license_ids));
+    issues
+}
+
+pub fn validate_test_metadata(metadata: &TestMetadata) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    issues.extend(duplicate_ids("images", metadata.images.iter().map(|e| e.id)));
+    issues.extend(duplicate_ids("licenses", metadata.licenses.iter().map(|e| e.id)));
+
+    let license_ids: collections::HashSet<i32> = metadata.licenses.iter().map(|e| e.id).collect();
+    issues.extend(dangling_licenses(&metadata.images, &license_ids));
+    issues
+}
+
+fn dangling_licenses(images: &[Image], license_ids: &collections::HashSet<i32>) -> Vec<ValidationIssue> {
+    images
+        .iter()
+        .filter(|e| !license_ids.contains(&e.license))
+        .map(|e| ValidationIssue::DanglingLicenseReference {
+            image_id: e.id,
+            license_id: e.license,
+        })
+        .collect()
+}
+
+// only reads the image header, so this is cheap enough to run over the whole dataset
+pub fn validate_image_files(image_dir: &path::Path, images: &[Image]) -> Vec<ValidationIssue> {
+    let mut issues: Vec<ValidationIssue> = images.par_iter().filter_map(|image| validate_image_file(image_dir, image)).collect();
+    issues.sort_by_key(|e| match e {
+        ValidationIssue::MissingImageFile { image_id, .. } => *image_id,
+        ValidationIssue::UnreadableImageFile { image_id, .. } => *image_id,
+        ValidationIssue::DimensionMismatch { image_id, .. } => *image_id,
+        _ => 0,
+    });
+    issues
+}
+
+fn validate_image_file(image_dir: &path::Path, image: &Image) -> Option<ValidationIssue> {
+    let image_path = image_dir.join(&image.file_name);
+    if !image_path.is_file() {
+        return Some(ValidationIssue::MissingImageFile {
+            image_id: image.id,
+            path: image_path.to_string_lossy().to_string(),
+        });
+    }
+
+    match image::image_dimensions(image_path.as_path()) {
+        Ok((width, height)) => {
+            // 2022 images can come without dimensions, those are read as 0 & there's nothing to compare against
+            let has_dimensions = image.width != 0 && image.height != 0;
+            if has_dimensions && (width as i32 != image.width || height as i32 != image.height) {
+                Some(ValidationIssue::DimensionMismatch {
+                    image_id: image.id,
+                    path: image_path.to_string_lossy().to_string(),
+                    expected_width: image.width,
+                    expected_height: image.height,
+                    actual_width: width,
+                    actual_height: height,
+                })
+            } else {
+                None
+            }
+        }
+        Err(e) => Some(ValidationIssue::UnreadableImageFile {
+            image_id: image.id,
+            path: image_path.to_string_lossy().to_string(),
+            reason: e.to_string(),
+        }),
+    }
+}
+
+pub fn unparsable_metadata<E: fmt::Display>(metadata_path: &path::Path, error: E) -> ValidationIssue {
+    ValidationIssue::UnparsableMetadata {
+        path: metadata_path.to_string_lossy().to_string(),
+        reason: error.to_string(),
+    }
+}
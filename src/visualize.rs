@@ -0,0 +1,101 @@
+use crate::pipeline::RgbaImage;
+use crate::region::{BoundingBox, Mask};
+
+pub const BORDER_COLOR: image::Rgba<u8> = image::Rgba([230, 30, 30, 255]);
+pub const CUTOFF_COLOR: image::Rgba<u8> = image::Rgba([230, 30, 230, 255]);
+pub const CALIBRATION_COLOR: image::Rgba<u8> = image::Rgba([30, 90, 230, 255]);
+pub const LABEL_COLOR: image::Rgba<u8> = image::Rgba([240, 140, 0, 255]);
+pub const SEGMENTATION_COLOR: image::Rgba<u8> = image::Rgba([0, 200, 60, 255]);
+pub const CHANGED_COLOR: image::Rgba<u8> = image::Rgba([250, 230, 0, 255]);
+
+// what a step detected, drawn over the image the step was given
+#[derive(Debug, Clone)]
+pub enum Annotation {
+    Rect { bbox: BoundingBox, color: image::Rgba<u8> },
+    Row { y: u32, color: image::Rgba<u8> },
+    Mask { mask: Mask, color: image::Rgba<u8> },
+}
+
+// the pixels that differ, None when the sizes differ
+pub fn changed_pixels(before: &RgbaImage, after: &RgbaImage) -> Option<Mask> {
+    if before.dimensions() != after.dimensions() {
+        return None;
+    }
+    Some(Mask::from_fn(before.width(), before.height(), |x, y| before.get_pixel(x, y) != after.get_pixel(x, y)))
+}
+
+fn blend(pixel: &mut image::Rgba<u8>, color: image::Rgba<u8>) {
+    for channel in 0..3 {
+        pixel[channel] = ((pixel[channel] as u16 + color[channel] as u16) / 2) as u8;
+    }
+}
+
+fn fill_clipped(img: &mut RgbaImage, x0: u32, y0: u32, x1: u32, y1: u32, color: image::Rgba<u8>) {
+    for y in y0..y1.min(img.height()) {
+        for x in x0..x1.min(img.width()) {
+            img.put_pixel(x, y, color);
+        }
+    }
+}
+
+// masks are tinted first so the outlines & lines stay visible on top of them
+pub fn draw_annotations(img: &RgbaImage, annotations: &[Annotation]) -> RgbaImage {
+    let mut img = img.clone();
+    let (width, height) = img.dimensions();
+    let thickness = (width.min(height) / 250).max(1);
+
+    for annotation in annotations.iter() {
+        if let Annotation::Mask { mask, color } = annotation {
+            if mask.width != width || mask.height != height {
+                continue;
+            }
+            for (x, y, pixel) in img.enumerate_pixels_mut() {
+                if mask.get(x, y) {
+                    blend(pixel, *color);
+                }
+            }
+        }
+    }
+    for annotation in annotations.iter() {
+        match annotation {
+            Annotation::Rect { bbox, color } => {
+                let (right, bottom) = (bbox.right(), bbox.bottom());
+                fill_clipped(&mut img, bbox.x, bbox.y, right, bbox.y + thickness, *color);
+                fill_clipped(&mut img, bbox.x, bottom.saturating_sub(thickness), right, bottom, *color);
+                fill_clipped(&mut img, bbox.x, bbox.y, bbox.x + thickness, bottom, *color);
+                fill_clipped(&mut img, right.saturating_sub(thickness), bbox.y, right, bottom, *color);
+            }
+            Annotation::Row { y, color } => fill_clipped(&mut img, 0, y.saturating_sub(thickness / 2), width, y + thickness - thickness / 2, *color),
+            Annotation::Mask { .. } => {}
+        }
+    }
+    img
+}
+
+// one row per image, its cells left to right, every cell scaled to fit cell_width x cell_height
+pub fn contact_sheet(rows: &[Vec<RgbaImage>], cell_width: u32, cell_height: u32) -> RgbaImage {
+    let gap = (cell_width.min(cell_height) / 20).max(2);
+    let columns = rows.iter().map(|e| e.len()).max().unwrap_or(0) as u32;
+    let mut sheet = RgbaImage::from_pixel(
+        gap + columns * (cell_width + gap),
+        gap + rows.len() as u32 * (cell_height + gap),
+        image::Rgba([40, 40, 40, 255]),
+    );
+
+    for (row, cells) in rows.iter().enumerate() {
+        for (column, cell) in cells.iter().enumerate() {
+            let (width, height) = cell.dimensions();
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let scale = (cell_width as f32 / width as f32).min(cell_height as f32 / height as f32);
+            let scaled_width = ((width as f32 * scale).round() as u32).clamp(1, cell_width);
+            let scaled_height = ((height as f32 * scale).round() as u32).clamp(1, cell_height);
+            let thumbnail = image::imageops::thumbnail(cell, scaled_width, scaled_height);
+            let x = gap + column as u32 * (cell_width + gap) + (cell_width - scaled_width) / 2;
+            let y = gap + row as u32 * (cell_height + gap) + (cell_height - scaled_height) / 2;
+            image::imageops::replace(&mut sheet, &thumbnail, x, y);
+        }
+    }
+    sheet
+}
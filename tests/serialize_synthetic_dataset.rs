@@ -0,0 +1,114 @@
+use flate2::read::GzDecoder;
+use rusty_herbarium::manifest::{DatasetPart, Manifest};
+use rusty_herbarium::pipeline::{Pipeline, PipelineSpec};
+use rusty_herbarium::{manifest, metadata, pipeline, split, synthetic, HerbariumCatalog};
+use std::fs;
+use std::io;
+use std::path;
+use std::process;
+
+const WIDTH: u32 = 24;
+const HEIGHT: u32 = 30;
+
+fn scratch_dir(name: &str) -> path::PathBuf {
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("rusty-herbarium-{}-{}", name, process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(dir.as_path()).unwrap();
+    }
+    fs::create_dir_all(dir.as_path()).unwrap();
+    dir
+}
+
+fn read_labels(serialization_dir: &path::Path, part: DatasetPart) -> Vec<f32> {
+    let labels_file = fs::File::open(serialization_dir.join(part.labels_file_name(WIDTH, HEIGHT))).unwrap();
+    bincode::deserialize_from(GzDecoder::new(io::BufReader::new(labels_file))).unwrap()
+}
+
+fn read_data(serialization_dir: &path::Path, part: DatasetPart) -> Vec<Vec<f32>> {
+    let data_file = fs::File::open(serialization_dir.join(part.data_file_name(WIDTH, HEIGHT))).unwrap();
+    bincode::deserialize_from(GzDecoder::new(io::BufReader::new(data_file))).unwrap()
+}
+
+// generate -> read_train_metadata -> catalog -> split -> manifest -> serialize_train_and_label_data with the default pipeline
+#[test]
+fn serializes_a_synthetic_dataset_through_the_default_pipeline() {
+    let base_dir = scratch_dir("synthetic");
+    let output_dir = base_dir.join("serialized");
+    fs::create_dir_all(output_dir.as_path()).unwrap();
+
+    let config = synthetic::SyntheticConfig {
+        seed: 7,
+        images_per_category: 3,
+        test_images_per_category: 1,
+        width: 340,
+        height: 500,
+        ..synthetic::SyntheticConfig::default()
+    };
+    let dataset = synthetic::generate(base_dir.as_path(), &config).unwrap();
+    let category_count = config.family_count * config.genera_per_family * config.species_per_genus;
+
+    let train_metadata = metadata::read_train_metadata(base_dir.join("train").join("metadata.json").as_path(), None).unwrap();
+    assert_eq!(train_metadata.categories.len(), category_count);
+    assert_eq!(train_metadata.images.len(), dataset.train_metadata.images.len());
+    assert_eq!(train_metadata.annotations.len(), category_count * config.images_per_category);
+
+    let catalog = HerbariumCatalog::new(train_metadata);
+    let split_config = split::SplitConfig {
+        seed: 7,
+        sizes: "2,1,0".parse().unwrap(),
+        min_per_class: 1,
+        stratify_by_region: false,
+        category_limit: 0,
+    };
+    let split = split::stratified_split(&catalog, &split_config);
+    assert_eq!(split.train.len(), category_count * 2);
+    assert_eq!(split.validation.len(), category_count);
+    assert!(split.test.is_empty());
+
+    let manifest = Manifest::from_split(&catalog, &split);
+    let manifest_path = base_dir.join("manifest.csv");
+    manifest.write(manifest_path.as_path()).unwrap();
+
+    let status = process::Command::new(env!("CARGO_BIN_EXE_serialize_train_and_label_data"))
+        .arg("-w")
+        .arg(WIDTH.to_string())
+        .arg("-h")
+        .arg(HEIGHT.to_string())
+        .arg("-l")
+        .arg("warn")
+        .arg("-f")
+        .arg(manifest_path.as_path())
+        .arg("-b")
+        .arg(base_dir.as_path())
+        .arg("-o")
+        .arg(output_dir.as_path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let pipeline = Pipeline::new(PipelineSpec::default(), WIDTH, HEIGHT);
+    let record = pipeline::read_pipeline_record(output_dir.as_path(), WIDTH, HEIGHT).unwrap().unwrap();
+    assert_eq!(record.hash, pipeline.hash());
+    assert_eq!(record.spec, PipelineSpec::default());
+
+    // every image turns into its four rotations
+    for part in [DatasetPart::Training, DatasetPart::Validation].iter() {
+        assert!(!output_dir.join(part.failures_file_name(WIDTH, HEIGHT)).exists(), "{} had failures", part);
+
+        let images = manifest.entries(*part).len();
+        let data = read_data(output_dir.as_path(), *part);
+        let labels = read_labels(output_dir.as_path(), *part);
+        let rows = manifest::read_part_manifest(output_dir.as_path(), *part, WIDTH, HEIGHT).unwrap();
+
+        assert_eq!(data.len(), images * 4);
+        assert_eq!(labels.len(), images * 4);
+        assert_eq!(rows.entries.len(), images * 4);
+        assert!(data.iter().all(|row| row.len() == pipeline.feature_count()));
+        assert!(labels.iter().all(|label| (*label as usize) < category_count));
+        assert!(rows.entries.iter().all(|row| row.split == *part && row.augmentation.is_some()));
+    }
+    assert!(!output_dir.join(DatasetPart::Holdout.labels_file_name(WIDTH, HEIGHT)).exists());
+
+    fs::remove_dir_all(base_dir.as_path()).unwrap();
+}